@@ -17,28 +17,14 @@ function check_env {
 	fi
 }
 
-check_env ACCESS_TOKEN
+## NOTE: The registration token is requested by the link daemon; the
+## NOTE: organization access token is never handed to the container.
+check_env REG_TOKEN
 check_env ORGANIZATION
 #check_env REPOSITORY
 check_env LABELS
 check_env NAME
 
-REG_TOKEN_RAW="$( \
-	curl -sX POST \
-		-H "Authorization: Bearer ${ACCESS_TOKEN}" \
-		-H "Accept: application/vnd.github+json" \
-		-H "X-GitHub-Api-Version: 2022-11-28" \
-		"https://api.github.com/orgs/${ORGANIZATION}/actions/runners/registration-token")"
-#		"https://api.github.com/repos/${ORGANIZATION}/${REPOSITORY}/actions/runners/registration-token")"
-
-REG_TOKEN="$(jq .token --raw-output <<< "${REG_TOKEN_RAW}")"
-
-if [ "$REG_TOKEN" == "null" ]; then
-	echo "got 'null' registration token from GitHub:" >&2
-	echo "${REG_TOKEN_RAW}" >&2
-	exit 1
-fi
-
 #	--url "https://github.com/${ORGANIZATION}/${REPOSITORY}" \
 ./config.sh \
 	--url "https://github.com/${ORGANIZATION}" \
@@ -58,7 +44,7 @@ cleanup() {
 trap 'cleanup; exit 130' INT
 trap 'cleanup; exit 143' TERM
 
-unset ACCESS_TOKEN
+export -n REG_TOKEN
 unset ORGANIZATION
 #unset REPOSITORY
 unset LABELS
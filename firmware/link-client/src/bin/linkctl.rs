@@ -0,0 +1,588 @@
+//! Drives an Oro Link session from the command line, e.g. from a CI job
+//! running in the runner container.
+//!
+//! The session lasts as long as the connection, so everything a job
+//! needs is done in one invocation: either a script of steps (`run`) or
+//! an interactive serial console (`console`).
+use async_std::{
+	channel::Receiver,
+	fs,
+	io::{self, ReadExt, WriteExt},
+	sync::Mutex,
+	task,
+};
+use clap::Parser;
+use futures::{FutureExt, StreamExt, select};
+use link_client::{SerialEvent, Session, TestOutcome};
+use std::{path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
+
+#[macro_use]
+extern crate log;
+
+/// How long `expect` waits by default.
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Drives an Oro Link session over its session socket.
+#[derive(clap::Parser, Debug)]
+struct Options {
+	/// Turns on verbose logging
+	#[arg(short, long)]
+	verbose: bool,
+
+	/// The session socket to connect to
+	#[arg(
+		short,
+		long,
+		env = "ORO_LINK_SOCKET",
+		default_value = "/oro-link.sock",
+		conflicts_with = "link"
+	)]
+	socket: PathBuf,
+
+	/// Connects to the session socket of the link with the given
+	/// (hex-encoded) UID instead
+	#[arg(short, long)]
+	link: Option<String>,
+
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+	/// Runs a script of steps (from a file, or stdin) in one session,
+	/// printing serial output as it arrives. One step per line:
+	///
+	///   bootfile-size <uefi> <bios>
+	///   start-session <total-tests> <author> <title> <ref-id>
+	///   start-test <name>
+	///   result <name> <passed|failed|skipped> [duration-ms] [message]
+	///   end-session
+	///   power
+	///   reset
+	///   send <text>
+	///   expect <text> [timeout-secs]
+	///   sleep <secs>
+	///
+	/// Arguments are separated by spaces and may be double-quoted;
+	/// `\n`, `\r`, `\t`, `\\` and `\"` are unescaped. Lines starting
+	/// with `#` are ignored. Exits with a failure if a step fails
+	/// (e.g. `expect` times out).
+	///
+	/// `expect` only matches live output, not the recent output the
+	/// daemon replays on connecting, unless `--expect-history` is given.
+	#[command(verbatim_doc_comment)]
+	Run {
+		/// The script to run; defaults to stdin
+		script: Option<PathBuf>,
+		/// Lets `expect` match the output replayed on connecting, too
+		#[arg(long)]
+		expect_history: bool,
+	},
+	/// Bridges stdin and stdout to the machine's serial port until
+	/// stdin closes
+	Console,
+	/// Prints the machine's serial output as an observer, without
+	/// controlling the session, until the session ends
+	Watch,
+}
+
+#[async_std::main]
+async fn main() -> ExitCode {
+	let options = Options::parse();
+
+	stderrlog::new()
+		.module(module_path!())
+		.module("link_client")
+		.verbosity(if options.verbose {
+			log::LevelFilter::Trace
+		} else {
+			log::LevelFilter::Warn
+		})
+		.init()
+		.expect("failed to start stderr logger");
+
+	match run(options).await {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(err) => {
+			eprintln!("linkctl: {err}");
+			ExitCode::FAILURE
+		}
+	}
+}
+
+async fn run(options: Options) -> Result<(), String> {
+	let path = match &options.link {
+		Some(link_id) => link_client::socket_path(link_id),
+		None => options.socket,
+	};
+
+	let session = match options.command {
+		Command::Watch => Session::observe(&path).await,
+		_ => Session::connect(&path).await,
+	}
+	.map_err(|err| format!("failed to connect to session: {err}"))?;
+
+	match options.command {
+		Command::Run {
+			script,
+			expect_history,
+		} => {
+			let script = match script {
+				Some(path) => fs::read_to_string(&path)
+					.await
+					.map_err(|err| format!("failed to read {}: {err}", path.display()))?,
+				None => {
+					let mut script = String::new();
+					io::stdin()
+						.read_to_string(&mut script)
+						.await
+						.map_err(|err| format!("failed to read script from stdin: {err}"))?;
+					script
+				}
+			};
+
+			let steps = script
+				.lines()
+				.enumerate()
+				.filter_map(|(n, line)| Some((n + 1, parse_step(line).transpose()?)))
+				.map(|(n, step)| {
+					step.map(|step| (n, step))
+						.map_err(|err| format!("line {n}: {err}"))
+				})
+				.collect::<Result<Vec<_>, _>>()?;
+
+			run_script(session, steps, expect_history).await
+		}
+		Command::Console => console(session).await,
+		Command::Watch => watch(session).await,
+	}
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+	BootfileSize {
+		uefi: u64,
+		bios: u64,
+	},
+	StartSession {
+		total_tests: u32,
+		author: String,
+		title: String,
+		ref_id: String,
+	},
+	StartTest(String),
+	Result {
+		name: String,
+		outcome: TestOutcome,
+		duration: Duration,
+		message: String,
+	},
+	EndSession,
+	Power,
+	Reset,
+	Send(String),
+	Expect(String, Duration),
+	Sleep(Duration),
+}
+
+fn parse_step(line: &str) -> Result<Option<Step>, String> {
+	let line = line.trim();
+	if line.is_empty() || line.starts_with('#') {
+		return Ok(None);
+	}
+
+	let words = split_words(line)?;
+	let (command, args) = words.split_first().unwrap();
+
+	let number = |i: usize, what: &str| -> Result<u64, String> {
+		args.get(i)
+			.ok_or_else(|| format!("missing {what}"))?
+			.parse()
+			.map_err(|_| format!("invalid {what}: {}", args[i]))
+	};
+	let text = |i: usize, what: &str| -> Result<String, String> {
+		args.get(i)
+			.cloned()
+			.ok_or_else(|| format!("missing {what}"))
+	};
+
+	let (step, arity) = match command.as_str() {
+		"bootfile-size" => (
+			Step::BootfileSize {
+				uefi: number(0, "UEFI boot file size")?,
+				bios: number(1, "BIOS boot file size")?,
+			},
+			2,
+		),
+		"start-session" => (
+			Step::StartSession {
+				total_tests: number(0, "total test count")?
+					.try_into()
+					.map_err(|_| "total test count is too large")?,
+				author: text(1, "author")?,
+				title: text(2, "title")?,
+				ref_id: text(3, "ref ID")?,
+			},
+			4,
+		),
+		"start-test" => (Step::StartTest(text(0, "test name")?), 1),
+		"result" => (
+			Step::Result {
+				name: text(0, "test name")?,
+				outcome: match text(1, "outcome")?.as_str() {
+					"passed" => TestOutcome::Passed,
+					"failed" => TestOutcome::Failed,
+					"skipped" => TestOutcome::Skipped,
+					unknown => {
+						return Err(format!(
+							"unknown outcome (expected passed, failed or skipped): {unknown}"
+						));
+					}
+				},
+				duration: Duration::from_millis(if args.len() > 2 {
+					number(2, "duration")?
+				} else {
+					0
+				}),
+				message: if args.len() > 3 {
+					text(3, "message")?
+				} else {
+					String::new()
+				},
+			},
+			4,
+		),
+		"end-session" => (Step::EndSession, 0),
+		"power" => (Step::Power, 0),
+		"reset" => (Step::Reset, 0),
+		"send" => (Step::Send(text(0, "text")?), 1),
+		"expect" => {
+			let timeout = if args.len() > 1 {
+				Duration::from_secs(number(1, "timeout")?)
+			} else {
+				DEFAULT_EXPECT_TIMEOUT
+			};
+			let text = text(0, "text")?;
+			// It would match anything, including nothing at all.
+			if text.is_empty() {
+				return Err("expect needs some text to wait for".into());
+			}
+			(Step::Expect(text, timeout), 2)
+		}
+		"sleep" => (Step::Sleep(Duration::from_secs(number(0, "duration")?)), 1),
+		unknown => return Err(format!("unknown step: {unknown}")),
+	};
+
+	if args.len() > arity {
+		return Err(format!("too many arguments for {command}"));
+	}
+
+	Ok(Some(step))
+}
+
+/// Splits a line into words separated by spaces, where double-quoted
+/// words may contain spaces, and unescapes `\n`, `\r`, `\t`, `\\` and `\"`.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+	let mut words = Vec::new();
+	let mut word: Option<String> = None;
+	let mut quoted = false;
+	let mut chars = line.chars();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'"' => {
+				quoted = !quoted;
+				word.get_or_insert_default();
+			}
+			'\\' => {
+				let escaped = match chars.next() {
+					Some('n') => '\n',
+					Some('r') => '\r',
+					Some('t') => '\t',
+					Some(c @ ('\\' | '"')) => c,
+					Some(c) => return Err(format!("unknown escape: \\{c}")),
+					None => return Err("trailing backslash".into()),
+				};
+				word.get_or_insert_default().push(escaped);
+			}
+			c if c.is_whitespace() && !quoted => {
+				words.extend(word.take());
+			}
+			c => word.get_or_insert_default().push(c),
+		}
+	}
+
+	if quoted {
+		return Err("unterminated quote".into());
+	}
+
+	words.extend(word);
+	Ok(words)
+}
+
+/// Serial output received so far, for `expect` to search through.
+#[derive(Default)]
+struct Transcript {
+	output: Vec<u8>,
+	/// Where the next `expect` starts searching
+	cursor: usize,
+}
+
+impl Transcript {
+	/// Skips the output so far, which nothing matches anymore.
+	fn skip(&mut self) {
+		self.cursor = self.output.len();
+	}
+
+	/// Finds `text` in output that hasn't been matched yet, consuming
+	/// the output up to the end of the match.
+	fn find(&mut self, text: &str) -> bool {
+		let needle = text.as_bytes();
+		let found = self.output[self.cursor..]
+			.windows(needle.len().max(1))
+			.position(|window| window == needle);
+
+		if let Some(at) = found {
+			self.cursor += at + needle.len();
+		}
+
+		found.is_some()
+	}
+}
+
+/// Echoes serial output to stdout, noting on stderr where the output
+/// replayed on connecting (if any) ends and live output starts.
+#[derive(Default)]
+struct Echo {
+	/// Whether any output was echoed yet
+	echoed: bool,
+}
+
+impl Echo {
+	async fn event(&mut self, event: &SerialEvent) {
+		match event {
+			SerialEvent::Data(data) => {
+				let mut stdout = io::stdout();
+				let _ = stdout.write_all(data).await;
+				let _ = stdout.flush().await;
+				self.echoed = true;
+			}
+			SerialEvent::Live if self.echoed => {
+				eprintln!("\nlinkctl: end of replayed output; live from here on");
+			}
+			SerialEvent::Live => {}
+		}
+	}
+}
+
+async fn run_script(
+	mut session: Session,
+	steps: Vec<(usize, Step)>,
+	expect_history: bool,
+) -> Result<(), String> {
+	let transcript = Arc::new(Mutex::new(Transcript::default()));
+	let (notify, notified) = async_std::channel::unbounded::<()>();
+
+	// Echo serial output and record it for `expect`.
+	task::spawn({
+		let serial = session.serial();
+		let transcript = transcript.clone();
+		async move {
+			let mut echo = Echo::default();
+			let mut serial = serial;
+			while let Some(event) = serial.next().await {
+				echo.event(&event).await;
+				match event {
+					SerialEvent::Data(data) => transcript.lock().await.output.extend(data),
+					SerialEvent::Live if !expect_history => transcript.lock().await.skip(),
+					SerialEvent::Live => {}
+				}
+				let _ = notify.try_send(());
+			}
+		}
+	});
+
+	for (line, step) in steps {
+		info!("line {line}: {step:?}");
+
+		let result = match step {
+			Step::BootfileSize { uefi, bios } => session.bootfile_size(uefi, bios).await,
+			Step::StartSession {
+				total_tests,
+				author,
+				title,
+				ref_id,
+			} => {
+				session
+					.start_session(total_tests, &author, &title, &ref_id)
+					.await
+			}
+			Step::StartTest(name) => session.start_test(&name).await,
+			Step::Result {
+				name,
+				outcome,
+				duration,
+				message,
+			} => {
+				session
+					.test_result(&name, outcome, duration, &message)
+					.await
+			}
+			Step::EndSession => session.end_session().await,
+			Step::Power => session.press_power().await,
+			Step::Reset => session.press_reset().await,
+			Step::Send(text) => session.send_serial(text.as_bytes()).await,
+			Step::Expect(text, timeout) => {
+				expect(&transcript, &notified, &text, timeout)
+					.await
+					.map_err(|err| format!("line {line}: {err}"))?;
+				Ok(())
+			}
+			Step::Sleep(duration) => {
+				task::sleep(duration).await;
+				Ok(())
+			}
+		};
+
+		result.map_err(|err| format!("line {line}: {err}"))?;
+	}
+
+	session.close();
+	Ok(())
+}
+
+async fn expect(
+	transcript: &Mutex<Transcript>,
+	notified: &Receiver<()>,
+	text: &str,
+	timeout: Duration,
+) -> Result<(), String> {
+	let wait = async {
+		loop {
+			if transcript.lock().await.find(text) {
+				return Ok(());
+			}
+
+			if notified.recv().await.is_err() {
+				return Err(format!("session ended before {text:?} was received"));
+			}
+		}
+	};
+
+	async_std::future::timeout(timeout, wait)
+		.await
+		.unwrap_or_else(|_| Err(format!("timed out after {timeout:?} waiting for {text:?}")))
+}
+
+async fn console(mut session: Session) -> Result<(), String> {
+	let mut serial = session.serial();
+	let mut stdin = io::stdin();
+	let mut echo = Echo::default();
+	let mut buf = [0u8; 256];
+
+	loop {
+		select! {
+			read = stdin.read(&mut buf).fuse() => match read {
+				Ok(0) => break,
+				Ok(n) => session
+					.send_serial(&buf[..n])
+					.await
+					.map_err(|err| format!("failed to send serial data: {err}"))?,
+				Err(err) => return Err(format!("failed to read stdin: {err}")),
+			},
+			event = serial.next().fuse() => match event {
+				Some(event) => echo.event(&event).await,
+				None => return Err("session ended".into()),
+			},
+		}
+	}
+
+	session.close();
+	Ok(())
+}
+
+async fn watch(session: Session) -> Result<(), String> {
+	let mut serial = session.serial();
+	let mut echo = Echo::default();
+
+	while let Some(event) = serial.next().await {
+		echo.event(&event).await;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_steps() {
+		assert_eq!(parse_step("  # comment"), Ok(None));
+		assert_eq!(
+			parse_step("bootfile-size 1024 0"),
+			Ok(Some(Step::BootfileSize {
+				uefi: 1024,
+				bios: 0
+			}))
+		);
+		assert_eq!(
+			parse_step(r#"start-session 2 "Jane Doe" "fix \"it\"" refs/heads/main"#),
+			Ok(Some(Step::StartSession {
+				total_tests: 2,
+				author: "Jane Doe".into(),
+				title: "fix \"it\"".into(),
+				ref_id: "refs/heads/main".into(),
+			}))
+		);
+		assert_eq!(
+			parse_step(r#"send "shutdown\n""#),
+			Ok(Some(Step::Send("shutdown\n".into())))
+		);
+		assert_eq!(
+			parse_step("expect booted"),
+			Ok(Some(Step::Expect("booted".into(), DEFAULT_EXPECT_TIMEOUT)))
+		);
+		assert_eq!(
+			parse_step(r#"expect "login: " 5"#),
+			Ok(Some(Step::Expect("login: ".into(), Duration::from_secs(5))))
+		);
+
+		assert_eq!(
+			parse_step(r#"result boots failed 1500 "no prompt""#),
+			Ok(Some(Step::Result {
+				name: "boots".into(),
+				outcome: TestOutcome::Failed,
+				duration: Duration::from_millis(1500),
+				message: "no prompt".into(),
+			}))
+		);
+		assert_eq!(parse_step("end-session"), Ok(Some(Step::EndSession)));
+
+		assert!(parse_step("result boots flaky").is_err());
+		assert!(parse_step("power on").is_err());
+		assert!(parse_step("bootfile-size 1024").is_err());
+		assert!(parse_step("send \"unterminated").is_err());
+		assert!(parse_step(r#"expect "" 5"#).is_err());
+		assert!(parse_step("launch").is_err());
+	}
+
+	#[test]
+	fn consumes_matched_output() {
+		let mut transcript = Transcript {
+			output: b"booting... ok\nbooting... ok\n".to_vec(),
+			cursor: 0,
+		};
+
+		assert!(transcript.find("ok"));
+		assert!(transcript.find("ok"));
+		assert!(!transcript.find("ok"));
+
+		transcript.output.extend_from_slice(b"login: ");
+		transcript.skip();
+		transcript.output.extend_from_slice(b"root\n");
+		assert!(!transcript.find("login"));
+		assert!(transcript.find("root"));
+	}
+}
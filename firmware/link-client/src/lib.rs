@@ -0,0 +1,404 @@
+//! A client for the session socket the link daemon opens for each session
+//! (`/tmp/link-<id>.sock`, mounted at `/oro-link.sock` in runner containers),
+//! through which a test harness drives the link and the machine under test.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), link_client::Error> {
+//! use futures::StreamExt;
+//! use link_client::{SerialEvent, TestOutcome};
+//! use std::time::Duration;
+//!
+//! let mut session = link_client::Session::connect("/oro-link.sock").await?;
+//! let mut serial = session.serial();
+//!
+//! session.bootfile_size(1 << 20, 0).await?;
+//! session.start_session(1, "author", "title", "refs/heads/main").await?;
+//! session.start_test("boots").await?;
+//!
+//! // Wait for the machine to boot to a login prompt, skipping the output
+//! // from before connecting that the daemon replays first.
+//! let mut output = String::new();
+//! let mut live = false;
+//! while !output.contains("login: ") {
+//!     match serial.next().await {
+//!         Some(SerialEvent::Data(data)) if live => {
+//!             output.push_str(&String::from_utf8_lossy(&data));
+//!         }
+//!         Some(SerialEvent::Data(_)) => {}
+//!         Some(SerialEvent::Live) => live = true,
+//!         // The session ended first.
+//!         None => break,
+//!     }
+//! }
+//!
+//! let outcome = if output.contains("login: ") {
+//!     TestOutcome::Passed
+//! } else {
+//!     TestOutcome::Failed
+//! };
+//! session
+//!     .test_result("boots", outcome, Duration::from_secs(3), "")
+//!     .await?;
+//!
+//! // The session ends once all tests have run (or the client disconnects).
+//! session.end_session().await?;
+//! session.close();
+//! # Ok(())
+//! # }
+//! ```
+use async_std::{
+	channel::{Receiver, Sender, unbounded as make_unbounded_channel},
+	io::{BufReader, BufWriter},
+	os::unix::net::UnixStream,
+	task,
+};
+use link_protocol::{
+	Error as ProtoError, Packet,
+	channel::{self, PacketReceiver, PacketSender, RWError},
+};
+use log::{debug, trace, warn};
+use rand::rngs::OsRng;
+use std::{
+	io,
+	net::Shutdown,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+pub use link_protocol::TestOutcome;
+
+/// Where the daemon creates session sockets.
+pub const SOCKET_DIR: &str = "/tmp";
+
+/// The most serial data a single packet carries.
+const SERIAL_CHUNK: usize = 256;
+
+/// The path of the session socket for a link, given its (hex-encoded) UID.
+pub fn socket_path(link_id: &str) -> PathBuf {
+	Path::new(SOCKET_DIR).join(format!("link-{link_id}.sock"))
+}
+
+#[derive(thiserror::Error, Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum Error {
+	#[error("i/o error")]
+	AsyncIo(#[from] io::Error),
+	#[error("i/o error during protocol transcoding")]
+	Proto(#[from] ProtoError<io::Error>),
+	#[error("i/o error during session connection negotiation")]
+	RWError(#[from] RWError<ProtoError<io::Error>, ProtoError<io::Error>>),
+	#[error("{0} is too long (at most 255 bytes)")]
+	TooLong(&'static str),
+}
+
+/// What a serial subscription receives (see [`Session::serial`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialEvent {
+	/// Output from the machine under test
+	Data(Vec<u8>),
+	/// Any output before this was recent output the daemon replayed on
+	/// connecting; output after it is live.
+	Live,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+	senders: Vec<Sender<SerialEvent>>,
+	/// Whether the daemon is done replaying recent output
+	live: bool,
+}
+
+impl Subscriptions {
+	/// Sends an event to every subscriber, dropping those that went away.
+	fn publish(&mut self, event: SerialEvent) {
+		self.senders.retain(|s| s.try_send(event.clone()).is_ok());
+	}
+}
+
+type Subscribers = Arc<Mutex<Option<Subscriptions>>>;
+
+/// A connection to a session socket.
+///
+/// The session ends (and the runner is considered finished) once the
+/// connection is closed, either with [`Session::close`] or by dropping it.
+pub struct Session {
+	stream: UnixStream,
+	outgoing: PacketSender<BufWriter<UnixStream>>,
+	/// `None` once the daemon has disconnected
+	subscribers: Subscribers,
+}
+
+impl Session {
+	/// Connects to the session socket at `path`.
+	pub async fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let path = path.as_ref();
+		debug!("connecting to session socket: {}", path.display());
+		Self::from_stream(UnixStream::connect(path).await?).await
+	}
+
+	/// Connects to the session socket of a link, given its (hex-encoded) UID.
+	pub async fn connect_link(link_id: &str) -> Result<Self, Error> {
+		Self::connect(socket_path(link_id)).await
+	}
+
+	/// Connects to the session socket at `path` as an observer, which
+	/// receives serial output without being able to control the session
+	/// (anything it sends is ignored). Observers don't end the session
+	/// when they disconnect.
+	pub async fn observe(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let mut session = Self::connect(path).await?;
+		session.send(Packet::Observe).await?;
+		Ok(session)
+	}
+
+	/// Negotiates a session over an already connected stream.
+	pub async fn from_stream(stream: UnixStream) -> Result<Self, Error> {
+		let (outgoing, incoming) = channel::negotiate(
+			BufWriter::new(stream.clone()),
+			BufReader::new(stream.clone()),
+			&mut OsRng,
+			channel::Side::Client,
+		)
+		.await?;
+
+		debug!("negotiated session channel");
+
+		let subscribers: Subscribers = Arc::new(Mutex::new(Some(Subscriptions::default())));
+		task::spawn(receive_packets(incoming, subscribers.clone()));
+
+		Ok(Self {
+			stream,
+			outgoing,
+			subscribers,
+		})
+	}
+
+	/// Subscribes to serial output from the machine under test. Only
+	/// output received after subscribing is delivered, which starts with
+	/// the session's recent output (replayed by the daemon on connecting)
+	/// up to [`SerialEvent::Live`]; subscribing after that starts with
+	/// `Live` right away. The stream ends once the daemon disconnects.
+	pub fn serial(&self) -> Receiver<SerialEvent> {
+		let (sender, receiver) = make_unbounded_channel();
+
+		// If the daemon already disconnected, the sender is dropped
+		// right away, ending the stream.
+		if let Some(subscriptions) = self.subscribers.lock().unwrap().as_mut() {
+			if subscriptions.live {
+				let _ = sender.try_send(SerialEvent::Live);
+			}
+			subscriptions.senders.push(sender);
+		}
+
+		receiver
+	}
+
+	/// Sends a raw packet to the link.
+	pub async fn send(&mut self, packet: Packet) -> Result<(), Error> {
+		trace!("session <- {packet:?}");
+		self.outgoing.send(packet).await?;
+		Ok(())
+	}
+
+	/// Tells the link how big the boot files served to the machine are.
+	/// Must be sent before the session is started.
+	pub async fn bootfile_size(&mut self, uefi: u64, bios: u64) -> Result<(), Error> {
+		self.send(Packet::BootfileSize { uefi, bios }).await
+	}
+
+	/// Starts the test session. Once the boot file sizes are known, too,
+	/// the machine is powered on.
+	pub async fn start_session(
+		&mut self,
+		total_tests: u32,
+		author: &str,
+		title: &str,
+		ref_id: &str,
+	) -> Result<(), Error> {
+		self.send(Packet::StartTestSession {
+			total_tests,
+			author: author.try_into().map_err(|_| Error::TooLong("author"))?,
+			title: title.try_into().map_err(|_| Error::TooLong("title"))?,
+			ref_id: ref_id.try_into().map_err(|_| Error::TooLong("ref ID"))?,
+		})
+		.await
+	}
+
+	/// Starts the next test.
+	pub async fn start_test(&mut self, name: &str) -> Result<(), Error> {
+		self.send(Packet::StartTest {
+			name: name.try_into().map_err(|_| Error::TooLong("test name"))?,
+		})
+		.await
+	}
+
+	/// Reports the result of the test last started.
+	pub async fn test_result(
+		&mut self,
+		name: &str,
+		outcome: TestOutcome,
+		duration: Duration,
+		message: &str,
+	) -> Result<(), Error> {
+		self.send(Packet::TestResult {
+			name: name.try_into().map_err(|_| Error::TooLong("test name"))?,
+			outcome,
+			duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+			message: message
+				.try_into()
+				.map_err(|_| Error::TooLong("result message"))?,
+		})
+		.await
+	}
+
+	/// Ends the test session once all tests have run. The runner is then
+	/// expected to exit; its exit status decides the session's outcome.
+	pub async fn end_session(&mut self) -> Result<(), Error> {
+		self.send(Packet::EndTestSession).await
+	}
+
+	/// Presses the machine's power button.
+	pub async fn press_power(&mut self) -> Result<(), Error> {
+		self.send(Packet::PressPower).await
+	}
+
+	/// Presses the machine's reset button.
+	pub async fn press_reset(&mut self) -> Result<(), Error> {
+		self.send(Packet::PressReset).await
+	}
+
+	/// Sends data to the machine's serial port.
+	pub async fn send_serial(&mut self, data: &[u8]) -> Result<(), Error> {
+		for chunk in data.chunks(SERIAL_CHUNK) {
+			self.send(Packet::Serial(chunk.try_into().unwrap())).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Closes the connection, ending the session.
+	pub fn close(self) {}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		// Also stops the receiving task.
+		let _ = self.stream.shutdown(Shutdown::Both);
+	}
+}
+
+async fn receive_packets(
+	mut incoming: PacketReceiver<BufReader<UnixStream>>,
+	subscribers: Subscribers,
+) {
+	loop {
+		match incoming.receive().await {
+			Ok(Packet::Serial(data)) => {
+				if let Some(subscriptions) = subscribers.lock().unwrap().as_mut() {
+					subscriptions.publish(SerialEvent::Data(data.to_vec()));
+				}
+			}
+			Ok(Packet::BacklogReplayed) => {
+				if let Some(subscriptions) = subscribers.lock().unwrap().as_mut() {
+					subscriptions.live = true;
+					subscriptions.publish(SerialEvent::Live);
+				}
+			}
+			Ok(packet) => debug!("ignoring packet from session: {packet:?}"),
+			Err(ProtoError::Eof) => {
+				debug!("session socket closed");
+				break;
+			}
+			Err(ProtoError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+				debug!("session socket closed");
+				break;
+			}
+			Err(err) => {
+				warn!("failed to receive from session socket: {err}");
+				break;
+			}
+		}
+	}
+
+	// Ends all subscriptions.
+	subscribers.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::StreamExt;
+
+	#[async_std::test]
+	async fn drives_a_session() {
+		let (ours, theirs) = UnixStream::pair().unwrap();
+
+		let server = task::spawn(async move {
+			channel::negotiate(
+				BufWriter::new(theirs.clone()),
+				BufReader::new(theirs),
+				&mut OsRng,
+				channel::Side::Server,
+			)
+			.await
+			.unwrap()
+		});
+
+		let mut session = Session::from_stream(ours).await.unwrap();
+		let (mut outgoing, mut incoming) = server.await;
+
+		let mut serial = session.serial();
+
+		session.bootfile_size(1, 2).await.unwrap();
+		session.start_test("boots").await.unwrap();
+		session.send_serial(&[b'x'; 300]).await.unwrap();
+
+		assert!(matches!(
+			incoming.receive().await.unwrap(),
+			Packet::BootfileSize { uefi: 1, bios: 2 }
+		));
+		assert!(matches!(
+			incoming.receive().await.unwrap(),
+			Packet::StartTest { name } if name == "boots"
+		));
+		for len in [256, 44] {
+			assert!(matches!(
+				incoming.receive().await.unwrap(),
+				Packet::Serial(data) if data.len() == len
+			));
+		}
+
+		assert!(matches!(
+			session.start_test(&"x".repeat(300)).await,
+			Err(Error::TooLong("test name"))
+		));
+
+		for packet in [
+			Packet::Serial(b"login: ".as_slice().try_into().unwrap()),
+			Packet::BacklogReplayed,
+			Packet::Serial(b"hello".as_slice().try_into().unwrap()),
+		] {
+			outgoing.send(packet).await.unwrap();
+		}
+		assert_eq!(
+			serial.next().await.unwrap(),
+			SerialEvent::Data(b"login: ".to_vec())
+		);
+		assert_eq!(serial.next().await.unwrap(), SerialEvent::Live);
+		assert_eq!(
+			serial.next().await.unwrap(),
+			SerialEvent::Data(b"hello".to_vec())
+		);
+
+		// Later subscribers only get live output.
+		let mut late = session.serial();
+		assert_eq!(late.next().await.unwrap(), SerialEvent::Live);
+
+		// The subscription ends once the daemon disconnects.
+		drop((outgoing, incoming));
+		assert_eq!(serial.next().await, None);
+		assert_eq!(late.next().await, None);
+		assert_eq!(session.serial().next().await, None);
+	}
+}
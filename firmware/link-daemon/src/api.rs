@@ -0,0 +1,174 @@
+//! A local HTTP/JSON API for inspecting the fleet of connected links
+//! and poking at them during bench maintenance:
+//!
+//! - `GET /links` lists all connected links
+//! - `GET /links/:uid` gets a single link
+//! - `POST /links/:uid/power/{on,off,standby}` sets the SUT's power state
+//! - `POST /links/:uid/power/press` presses the SUT's power button
+//! - `POST /links/:uid/reset` presses the SUT's reset button
+//! - `POST /links/:uid/scene/{logo,log,test}` switches the monitor scene
+//!
+//! Links that the link configuration rejected are listed until they're
+//! disconnected, but can't be controlled (`409 Conflict`).
+//!
+//! With `CONSOLE` set, it also serves each link's serial console
+//! (see [`crate::console`]).
+use crate::fleet::Fleet;
+use link_protocol::{Packet, PowerState, Scene};
+use log::info;
+use std::sync::Arc;
+use tide::{Body, Request, Response, StatusCode};
+
+type State = Arc<Fleet>;
+
+pub(crate) fn server(fleet: Arc<Fleet>) -> tide::Server<State> {
+	let mut app = tide::with_state(fleet);
+
+	app.at("/links").get(list_links);
+	app.at("/links/:uid").get(get_link);
+	app.at("/links/:uid/power/:action").post(power);
+	app.at("/links/:uid/reset").post(reset);
+	app.at("/links/:uid/scene/:scene").post(scene);
+
+	app
+}
+
+async fn list_links(req: Request<State>) -> tide::Result {
+	Ok(Body::from_json(&req.state().list())?.into())
+}
+
+async fn get_link(req: Request<State>) -> tide::Result {
+	match req.state().get(&uid(&req)?) {
+		Some(status) => Ok(Body::from_json(&status)?.into()),
+		None => Ok(Response::new(StatusCode::NotFound)),
+	}
+}
+
+async fn power(req: Request<State>) -> tide::Result {
+	let packet = match req.param("action")? {
+		"on" => Packet::SetPowerState(PowerState::On),
+		"off" => Packet::SetPowerState(PowerState::Off),
+		"standby" => Packet::SetPowerState(PowerState::Standby),
+		"press" => Packet::PressPower,
+		_ => return Ok(Response::new(StatusCode::BadRequest)),
+	};
+
+	send(&req, packet).await
+}
+
+async fn reset(req: Request<State>) -> tide::Result {
+	send(&req, Packet::PressReset).await
+}
+
+async fn scene(req: Request<State>) -> tide::Result {
+	let scene = match req.param("scene")? {
+		"logo" => Scene::Logo,
+		"log" => Scene::Log,
+		"test" => Scene::Test,
+		_ => return Ok(Response::new(StatusCode::BadRequest)),
+	};
+
+	send(&req, Packet::SetScene(scene)).await
+}
+
+/// Sends a packet to the link named in the request.
+async fn send(req: &Request<State>, packet: Packet) -> tide::Result {
+	let uid = uid(req)?;
+
+	if req.state().get(&uid).is_some_and(|status| status.rejected) {
+		return Ok(Response::new(StatusCode::Conflict));
+	}
+
+	let Some(control) = req.state().control(&uid) else {
+		return Ok(Response::new(StatusCode::NotFound));
+	};
+
+	info!("maintenance API: sending to link {uid}: {packet:?}");
+	control
+		.send(packet)
+		.await
+		.map_err(|err| tide::Error::new(StatusCode::ServiceUnavailable, err))?;
+
+	Ok(Response::new(StatusCode::Accepted))
+}
+
+/// UIDs are matched case-insensitively.
+fn uid(req: &Request<State>) -> tide::Result<String> {
+	Ok(req.param("uid")?.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::session::LinkControl;
+	use tide::http::{Method, Url};
+
+	async fn call(app: &tide::Server<State>, method: Method, path: &str) -> tide::http::Response {
+		let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+		app.respond(tide::http::Request::new(method, url))
+			.await
+			.unwrap()
+	}
+
+	#[async_std::test]
+	async fn lists_and_controls_links() {
+		let fleet = Arc::new(Fleet::default());
+		let app = server(fleet.clone());
+
+		let mut res = call(&app, Method::Get, "/links").await;
+		assert_eq!(res.status(), StatusCode::Ok);
+		assert_eq!(
+			res.body_json::<serde_json::Value>().await.unwrap(),
+			serde_json::json!([])
+		);
+
+		let (control, packets) = LinkControl::stand_in();
+		let entry = fleet.register("ABCD", "0.1.0", "10.0.0.2:4242".parse().unwrap(), control);
+		entry.set_container(Some("c1".into()));
+
+		let mut res = call(&app, Method::Get, "/links/abcd").await;
+		assert_eq!(res.status(), StatusCode::Ok);
+		let status: serde_json::Value = res.body_json().await.unwrap();
+		assert_eq!(status["uid"], "ABCD");
+		assert_eq!(status["version"], "0.1.0");
+		assert_eq!(status["peer"], "10.0.0.2:4242");
+		assert_eq!(status["state"], "waiting_for_client");
+		assert_eq!(status["container"], "c1");
+
+		let res = call(&app, Method::Post, "/links/ABCD/reset").await;
+		assert_eq!(res.status(), StatusCode::Accepted);
+		assert!(matches!(packets.recv().await, Ok(Packet::PressReset)));
+
+		let res = call(&app, Method::Post, "/links/ABCD/power/off").await;
+		assert_eq!(res.status(), StatusCode::Accepted);
+		assert!(matches!(
+			packets.recv().await,
+			Ok(Packet::SetPowerState(PowerState::Off))
+		));
+
+		let res = call(&app, Method::Post, "/links/ABCD/scene/log").await;
+		assert_eq!(res.status(), StatusCode::Accepted);
+		assert!(matches!(
+			packets.recv().await,
+			Ok(Packet::SetScene(Scene::Log))
+		));
+
+		let res = call(&app, Method::Post, "/links/ABCD/scene/nope").await;
+		assert_eq!(res.status(), StatusCode::BadRequest);
+
+		let res = call(&app, Method::Post, "/links/EF01/reset").await;
+		assert_eq!(res.status(), StatusCode::NotFound);
+
+		entry.set_rejected();
+		let mut res = call(&app, Method::Get, "/links/ABCD").await;
+		let status: serde_json::Value = res.body_json().await.unwrap();
+		assert_eq!(status["rejected"], true);
+		let res = call(&app, Method::Post, "/links/ABCD/reset").await;
+		assert_eq!(res.status(), StatusCode::Conflict);
+		assert!(packets.is_empty());
+
+		drop(entry);
+		let res = call(&app, Method::Get, "/links/ABCD").await;
+		assert_eq!(res.status(), StatusCode::NotFound);
+	}
+}
@@ -0,0 +1,155 @@
+//! Files kept from sessions (test reports, serial captures and the like)
+//! when `ARTIFACTS_DIR` is set. Each session gets its own directory:
+//!
+//! ```text
+//! <ARTIFACTS_DIR>/<link UID>/<ref ID>/<session start>/
+//! ```
+//!
+//! where the session start is in milliseconds since the Unix epoch, so that
+//! sessions for the same ref don't overwrite each other and sort by age.
+//!
+//! The ref ID is only known once the client starts the test session, so
+//! files written while the session runs are staged in
+//! `<ARTIFACTS_DIR>/<link UID>/.staging-<session start>/` until it ends.
+//! Sessions that never get a ref ID end up under a ref ID of `_`, as do
+//! staging directories the reconciler finds left behind (e.g. after the
+//! daemon crashed).
+use crate::fleet::unix_millis;
+use std::{
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Where a single session's artifacts go.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionArtifacts {
+	root: PathBuf,
+	link_id: String,
+	started_at: SystemTime,
+}
+
+impl SessionArtifacts {
+	/// The artifacts of a session starting now.
+	pub fn new(root: impl Into<PathBuf>, link_id: impl Into<String>) -> Self {
+		Self {
+			root: root.into(),
+			link_id: link_id.into(),
+			started_at: SystemTime::now(),
+		}
+	}
+
+	pub fn link_id(&self) -> &str {
+		&self.link_id
+	}
+
+	/// The session's directory, once its ref ID is known.
+	pub fn dir(&self, ref_id: &str) -> PathBuf {
+		session_dir(&self.root, &self.link_id, ref_id, self.started_at)
+	}
+
+	/// Where files are kept while the session runs.
+	pub fn staging_dir(&self) -> PathBuf {
+		self.root
+			.join(component(&self.link_id))
+			.join(format!(".staging-{}", unix_millis(self.started_at)))
+	}
+}
+
+/// Where to keep a staging directory (named `name`) that a session left
+/// behind; `None` if it isn't a staging directory.
+pub(crate) fn unstaged_dir(root: &Path, link_id: &str, name: &str) -> Option<PathBuf> {
+	let started_at = name.strip_prefix(".staging-")?.parse().ok()?;
+	Some(session_dir(
+		root,
+		link_id,
+		"",
+		UNIX_EPOCH + Duration::from_millis(started_at),
+	))
+}
+
+/// The directory a session's artifacts are kept in.
+fn session_dir(root: &Path, link_id: &str, ref_id: &str, started_at: SystemTime) -> PathBuf {
+	root.join(component(link_id))
+		.join(component(ref_id))
+		.join(unix_millis(started_at).to_string())
+}
+
+/// Makes a single path component out of arbitrary text, so that e.g. a ref
+/// ID of `refs/pull/1/merge` becomes `refs_pull_1_merge` rather than
+/// escaping (or nesting within) the link's directory.
+fn component(text: &str) -> String {
+	let component: String = text
+		.chars()
+		.map(|c| {
+			if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+				c
+			} else {
+				'_'
+			}
+		})
+		.collect();
+
+	if component.chars().all(|c| c == '.') {
+		// Also covers the empty string.
+		format!("_{component}")
+	} else {
+		component
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/// A fresh artifacts directory for a test, so that tests running
+	/// at the same time don't clean up each other's files.
+	pub(crate) fn temp_root(name: &str) -> PathBuf {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+		let root = std::env::temp_dir().join(format!(
+			"link-daemon-{name}-{}-{}",
+			std::process::id(),
+			COUNTER.fetch_add(1, Ordering::Relaxed)
+		));
+		let _ = std::fs::remove_dir_all(&root);
+		root
+	}
+
+	#[test]
+	fn keeps_sessions_apart() {
+		let started_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+		assert_eq!(
+			session_dir(
+				Path::new("/artifacts"),
+				"ABCD",
+				"refs/pull/1/merge",
+				started_at
+			),
+			Path::new("/artifacts/ABCD/refs_pull_1_merge/1700000000123")
+		);
+		assert_eq!(
+			session_dir(Path::new("/artifacts"), "ABCD", "..", started_at),
+			Path::new("/artifacts/ABCD/_../1700000000123")
+		);
+		assert_eq!(
+			session_dir(Path::new("/artifacts"), "ABCD", "", started_at),
+			Path::new("/artifacts/ABCD/_/1700000000123")
+		);
+	}
+
+	#[test]
+	fn keeps_staging_left_behind() {
+		let root = Path::new("/artifacts");
+		let staging = SessionArtifacts::new(root, "ABCD").staging_dir();
+		let name = staging.file_name().unwrap().to_str().unwrap();
+
+		let dir = unstaged_dir(root, "ABCD", name).unwrap();
+		assert_eq!(dir.parent(), Some(Path::new("/artifacts/ABCD/_")));
+		assert_eq!(dir.file_name().unwrap(), &name[".staging-".len()..]);
+
+		assert_eq!(unstaged_dir(root, "ABCD", ".staging-soon"), None);
+		assert_eq!(unstaged_dir(root, "ABCD", "refs_heads_main"), None);
+	}
+}
@@ -0,0 +1,324 @@
+//! A serial console for links, served alongside the maintenance API
+//! when `CONSOLE` is set:
+//!
+//! - `GET /links/:uid/console` serves a minimal terminal page
+//! - `GET /links/:uid/console/ws` bridges the SUT's serial port over
+//!   a WebSocket: serial output arrives as binary messages (starting with
+//!   the session's recent output), and text or binary messages sent to it
+//!   are typed into the SUT
+//!
+//! Anyone may watch the console, but only those passing `?token=` with
+//! the `CONSOLE_TOKEN` may type into it, unless the link is configured to
+//! be in maintenance mode. The first message on the socket is a text
+//! message saying which it is, e.g. `{"writable":false}`. Once the recent
+//! output has been sent, a `{"live":true}` text message follows.
+use crate::{
+	fleet::Fleet,
+	session::{LinkControl, SerialEvent},
+};
+use async_tungstenite::{
+	WebSocketStream,
+	tungstenite::{self, Message, handshake::derive_accept_key, protocol::Role},
+};
+use futures::{prelude::*, select};
+use link_protocol::Packet;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use tide::{
+	Body, Request, Response, StatusCode,
+	http::{headers, mime},
+};
+
+type State = Arc<Fleet>;
+
+/// The terminal page.
+const PAGE: &str = include_str!("console.html");
+
+/// Adds the console's routes to the maintenance API server. With no
+/// `token`, only links in maintenance mode can be typed into.
+pub(crate) fn route(app: &mut tide::Server<State>, token: Option<String>) {
+	let token: Option<Arc<str>> = token.map(Into::into);
+
+	app.at("/links/:uid/console").get(page);
+	app.at("/links/:uid/console/ws")
+		.get(move |req| socket(req, token.clone()));
+}
+
+async fn page(req: Request<State>) -> tide::Result {
+	if req.state().get(&uid(&req)?).is_none() {
+		return Ok(Response::new(StatusCode::NotFound));
+	}
+
+	let mut body = Body::from_string(PAGE.into());
+	body.set_mime(mime::HTML);
+	Ok(body.into())
+}
+
+async fn socket(req: Request<State>, token: Option<Arc<str>>) -> tide::Result {
+	let uid = uid(&req)?;
+	let fleet = req.state();
+
+	let (Some(status), Some(control), Some(serial)) =
+		(fleet.get(&uid), fleet.control(&uid), fleet.serial(&uid))
+	else {
+		return Ok(Response::new(StatusCode::NotFound));
+	};
+
+	let given = req
+		.url()
+		.query_pairs()
+		.find(|(key, _)| key == "token")
+		.map(|(_, value)| value.into_owned());
+
+	let writable = match (given, &token) {
+		(Some(given), Some(token)) if given == **token => true,
+		(Some(_), _) => return Ok(Response::new(StatusCode::Unauthorized)),
+		(None, _) => status.maintenance,
+	};
+
+	let upgrade_requested = req
+		.header(headers::UPGRADE)
+		.is_some_and(|value| value.as_str().eq_ignore_ascii_case("websocket"));
+	let Some(key) = req
+		.header("Sec-WebSocket-Key")
+		.filter(|_| upgrade_requested)
+	else {
+		return Ok(Response::new(StatusCode::BadRequest));
+	};
+
+	let mut res = Response::new(StatusCode::SwitchingProtocols);
+	res.insert_header(headers::UPGRADE, "websocket");
+	res.insert_header(headers::CONNECTION, "Upgrade");
+	res.insert_header(
+		"Sec-WebSocket-Accept",
+		derive_accept_key(key.as_str().as_bytes()),
+	);
+
+	// Subscribe right away so that nothing is missed while upgrading.
+	let serial = serial.subscribe();
+	let upgrade = AsMut::<tide::http::Response>::as_mut(&mut res)
+		.recv_upgrade()
+		.await;
+
+	async_std::task::spawn(async move {
+		let Some(connection) = upgrade.await else {
+			return;
+		};
+
+		info!(
+			"console connected to link {uid} ({})",
+			if writable { "writable" } else { "read-only" }
+		);
+
+		let ws = WebSocketStream::from_raw_socket(connection, Role::Server, None).await;
+		match bridge(ws, serial, control, writable).await {
+			Ok(()) => info!("console disconnected from link {uid}"),
+			Err(err) => warn!("console for link {uid} failed: {err}"),
+		}
+	});
+
+	Ok(res)
+}
+
+/// Relays between a console's WebSocket and the link until either goes away.
+async fn bridge<S, T>(
+	ws: WebSocketStream<S>,
+	mut serial: T,
+	control: LinkControl,
+	writable: bool,
+) -> Result<(), tungstenite::Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+	T: Stream<Item = SerialEvent> + Unpin,
+{
+	let (mut outgoing, mut incoming) = ws.split();
+
+	outgoing
+		.send(Message::Text(
+			serde_json::json!({ "writable": writable }).to_string(),
+		))
+		.await?;
+
+	loop {
+		select! {
+			event = serial.next().fuse() => match event {
+				Some(SerialEvent::Data(data)) => outgoing.send(Message::Binary(data)).await?,
+				Some(SerialEvent::Live) => {
+					outgoing
+						.send(Message::Text(serde_json::json!({ "live": true }).to_string()))
+						.await?;
+				}
+				None => {
+					debug!("session ended; closing console");
+					let _ = outgoing.send(Message::Close(None)).await;
+					return Ok(());
+				}
+			},
+			message = incoming.next().fuse() => {
+				let input = match message.transpose()? {
+					Some(Message::Text(text)) => text.into_bytes(),
+					Some(Message::Binary(data)) => data,
+					Some(Message::Close(_)) | None => return Ok(()),
+					// Pings are answered by the WebSocket itself.
+					Some(_) => continue,
+				};
+
+				if !writable {
+					debug!("ignoring input from read-only console");
+					continue;
+				}
+
+				for chunk in input.chunks(256) {
+					if control.send(Packet::Serial(chunk.try_into().unwrap())).await.is_err() {
+						return Ok(());
+					}
+				}
+			}
+		}
+	}
+}
+
+/// UIDs are matched case-insensitively.
+fn uid(req: &Request<State>) -> tide::Result<String> {
+	Ok(req.param("uid")?.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::session::SerialTap;
+	use async_std::{net::TcpStream, task};
+	use async_tungstenite::client_async;
+	use tide::listener::{Listener, ToListener};
+
+	/// Serves the console on a local port, returning its address.
+	async fn serve(fleet: Arc<Fleet>) -> String {
+		let mut app = tide::with_state(fleet);
+		route(&mut app, Some("secret".into()));
+
+		let mut listener = "127.0.0.1:0".to_listener().unwrap();
+		listener.bind(app).await.unwrap();
+		let addr = listener.info()[0]
+			.connection()
+			.trim_start_matches("http://")
+			.to_string();
+
+		task::spawn(async move { listener.accept().await });
+		addr
+	}
+
+	async fn connect(
+		addr: &str,
+		path: &str,
+	) -> Result<WebSocketStream<TcpStream>, tungstenite::Error> {
+		let stream = TcpStream::connect(addr).await.unwrap();
+		let (ws, _) = client_async(format!("ws://{addr}{path}"), stream).await?;
+		Ok(ws)
+	}
+
+	async fn next_message(ws: &mut WebSocketStream<TcpStream>) -> Message {
+		ws.next().await.unwrap().unwrap()
+	}
+
+	#[async_std::test]
+	async fn bridges_serial_to_a_fake_link() {
+		let fleet = Arc::new(Fleet::default());
+		let addr = serve(fleet.clone()).await;
+
+		let (control, packets) = LinkControl::stand_in();
+		let entry = fleet.register("ABCD", "0.1.0", "10.0.0.2:4242".parse().unwrap(), control);
+		let (serial, feed) = SerialTap::stand_in();
+		entry.set_serial(serial);
+
+		// Anyone may watch, but not type.
+		let mut viewer = connect(&addr, "/links/abcd/console/ws").await.unwrap();
+		assert_eq!(
+			next_message(&mut viewer).await,
+			Message::Text(r#"{"writable":false}"#.into())
+		);
+		// There's no recent output yet.
+		assert_eq!(
+			next_message(&mut viewer).await,
+			Message::Text(r#"{"live":true}"#.into())
+		);
+
+		feed(b"login: ");
+		assert_eq!(
+			next_message(&mut viewer).await,
+			Message::Binary(b"login: ".to_vec())
+		);
+		viewer.send(Message::Text("ignored".into())).await.unwrap();
+
+		assert!(
+			connect(&addr, "/links/ABCD/console/ws?token=nope")
+				.await
+				.is_err()
+		);
+		assert!(connect(&addr, "/links/EF01/console/ws").await.is_err());
+
+		let mut typist = connect(&addr, "/links/ABCD/console/ws?token=secret")
+			.await
+			.unwrap();
+		assert_eq!(
+			next_message(&mut typist).await,
+			Message::Text(r#"{"writable":true}"#.into())
+		);
+		typist.send(Message::Text("root\r".into())).await.unwrap();
+		assert!(matches!(
+			packets.recv().await,
+			Ok(Packet::Serial(data)) if data == b"root\r"
+		));
+
+		// Links in maintenance mode can be typed into without the token.
+		entry.set_maintenance(true);
+		let mut bench = connect(&addr, "/links/ABCD/console/ws").await.unwrap();
+		assert_eq!(
+			next_message(&mut bench).await,
+			Message::Text(r#"{"writable":true}"#.into())
+		);
+		// Late arrivals see what they missed, then what's live.
+		assert_eq!(
+			next_message(&mut bench).await,
+			Message::Binary(b"login: ".to_vec())
+		);
+		assert_eq!(
+			next_message(&mut bench).await,
+			Message::Text(r#"{"live":true}"#.into())
+		);
+
+		// Consoles are closed once the session ends.
+		drop(entry);
+		drop(feed);
+		assert!(matches!(next_message(&mut bench).await, Message::Close(_)));
+	}
+
+	#[async_std::test]
+	async fn serves_the_page() {
+		let fleet = Arc::new(Fleet::default());
+		let mut app = tide::with_state(fleet.clone());
+		route(&mut app, None);
+
+		let (control, _) = LinkControl::stand_in();
+		let _entry = fleet.register("ABCD", "0.1.0", "10.0.0.2:4242".parse().unwrap(), control);
+
+		let get = async |path: &str| -> tide::http::Response {
+			let url = tide::http::Url::parse("http://localhost")
+				.unwrap()
+				.join(path)
+				.unwrap();
+			app.respond(tide::http::Request::new(tide::http::Method::Get, url))
+				.await
+				.unwrap()
+		};
+
+		let mut res = get("/links/ABCD/console").await;
+		assert_eq!(res.status(), StatusCode::Ok);
+		assert_eq!(res.content_type(), Some(mime::HTML));
+		assert!(res.body_string().await.unwrap().contains("new WebSocket"));
+
+		assert_eq!(
+			get("/links/EF01/console").await.status(),
+			StatusCode::NotFound
+		);
+	}
+}
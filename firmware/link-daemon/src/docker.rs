@@ -14,21 +14,28 @@
 //!
 //! Word of the wise: If you're doing async HTTP in 2023, use Tokio. Even if you
 //! really dislike Tokio, save yourself the headache.
-use log::warn;
+use async_std::os::unix::net::UnixStream;
+use futures::{AsyncBufReadExt, AsyncRead, AsyncReadExt, StreamExt};
+use log::{info, trace, warn};
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeSeq};
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
+use surf::http::{Body, Method, Request, Response};
 use url::Url;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("failed to parse URI: {0}")]
 	Uri(#[from] url::ParseError),
+	#[error("failed to connect to docker socket: {0}")]
+	Socket(#[from] std::io::Error),
 	#[error("failed to perform HTTP request: {0}")]
 	Http(surf::Error),
 	#[error("request returned non-2xx status: {0}")]
 	HttpStatus(surf::StatusCode),
 	#[error("failed to serialize JSON: {0}")]
 	SerdeJson(#[from] serde_json::Error),
+	#[error("failed to pull image: {0}")]
+	Pull(String),
 }
 
 impl From<surf::Error> for Error {
@@ -41,36 +48,103 @@ impl From<surf::Error> for Error {
 #[derive(Clone)]
 pub struct Docker {
 	base: Url,
+	/// If set, requests are sent over this Unix domain socket
+	/// instead of to `base`.
+	socket: Option<PathBuf>,
 }
 
 impl Docker {
+	/// Creates a new client given either an `http://` base URL
+	/// or a `unix:///path/to/docker.sock` URI.
 	pub fn new(path: &str) -> Result<Self, Error> {
-		Ok(Self {
-			base: Url::parse(path)?,
-		})
+		let uri = Url::parse(path)?;
+
+		if uri.scheme() == "unix" {
+			Ok(Self {
+				// Only used to form the request line and `Host` header.
+				base: Url::parse("http://localhost")?,
+				socket: Some(uri.path().into()),
+			})
+		} else {
+			Ok(Self {
+				base: uri,
+				socket: None,
+			})
+		}
 	}
 
-	fn url<S: AsRef<str>>(&self, path: S) -> String {
-		let mut r = self.base.clone();
-		r.set_path(path.as_ref());
-		r.as_str().to_string()
+	fn request<S: AsRef<str>>(&self, method: Method, path: S) -> Request {
+		let mut url = self.base.clone();
+		url.set_path(path.as_ref());
+		Request::new(method, url)
 	}
 
-	pub async fn check_image(&self, id: &str) -> Result<(), Error> {
-		let res = surf::get(self.url(format!("/v1.43/images/{id}/json")))
-			.send()
+	async fn send(&self, req: Request) -> Result<Response, Error> {
+		let res: Response = match &self.socket {
+			Some(socket) => {
+				let stream = UnixStream::connect(socket).await?;
+				async_h1::connect(stream, req).await?
+			}
+			None => surf::client().send(req).await?.into(),
+		};
+
+		res.status().ok()?;
+
+		Ok(res)
+	}
+
+	pub async fn inspect_image(&self, id: &str) -> Result<ImageInspect, Error> {
+		let mut res = self
+			.send(self.request(Method::Get, format!("/v1.43/images/{id}/json")))
 			.await?;
 
-		res.status().ok()
+		Ok(res.body_json().await?)
+	}
+
+	/// Pulls an image, logging the progress reported by the daemon
+	/// as it goes.
+	pub async fn pull_image(&self, reference: &str) -> Result<(), Error> {
+		let (from_image, tag) = split_reference(reference);
+
+		let mut req = self.request(Method::Post, "/v1.43/images/create");
+		req.set_query(&PullImageQuery { from_image, tag })?;
+
+		let mut res = self.send(req).await?;
+
+		// The response is a stream of JSON objects, one per line, which
+		// ends once the pull has finished. Failures part-way through are
+		// reported in-band (the status code is still 200).
+		let mut lines = res.take_body().lines();
+		while let Some(line) = lines.next().await {
+			let line = line?;
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let progress: PullProgress = serde_json::from_str(&line)?;
+
+			if let Some(error) = progress.error {
+				return Err(Error::Pull(error));
+			}
+
+			let status = progress.status.unwrap_or_default();
+			let prefix = progress.id.map(|id| format!("{id}: ")).unwrap_or_default();
+
+			if let Some(bar) = progress.progress {
+				trace!("docker: pull {reference}: {prefix}{status} {bar}");
+			} else {
+				info!("docker: pull {reference}: {prefix}{status}");
+			}
+		}
+
+		Ok(())
 	}
 
 	pub async fn create_container(&self, options: &CreateContainer) -> Result<String, Error> {
-		let mut res = surf::post(self.url("/v1.43/containers/create"))
-			.body_json(options)?
-			.send()
-			.await?;
+		let mut req = self.request(Method::Post, "/v1.43/containers/create");
+		req.set_body(Body::from_json(options)?);
 
-		res.status().ok()?;
+		let mut res = self.send(req).await?;
 
 		let payload: CreateContainerResponse = res.body_json().await?;
 
@@ -82,59 +156,225 @@ impl Docker {
 	}
 
 	pub async fn start_container(&self, id: &str) -> Result<(), Error> {
-		let res = surf::post(self.url(format!("/v1.43/containers/{id}/start")))
-			.send()
+		self.send(self.request(Method::Post, format!("/v1.43/containers/{id}/start")))
 			.await?;
 
-		res.status().ok()
+		Ok(())
 	}
 
-	pub async fn wait_for_container(&self, id: &str) -> Result<(), Error> {
-		let res = surf::post(self.url(format!("/v1.43/containers/{id}/wait")))
-			.send()
+	/// Waits for a container to exit, returning its exit code.
+	pub async fn wait_for_container(&self, id: &str) -> Result<i64, Error> {
+		let mut res = self
+			.send(self.request(Method::Post, format!("/v1.43/containers/{id}/wait")))
 			.await?;
 
-		res.status().ok()
+		let payload: WaitContainerResponse = res.body_json().await?;
+
+		if let Some(WaitContainerError {
+			message: Some(message),
+		}) = payload.error
+		{
+			warn!("docker: wait for container: {message}");
+		}
+
+		Ok(payload.status_code)
 	}
 
-	pub async fn remove_container(&self, id: &str, force: bool) -> Result<(), Error> {
-		let res = surf::delete(self.url(format!("/v1.43/containers/{id}")))
-			.query(&RemoveContainerQuery { force: Some(force) })?
-			.send()
+	pub async fn kill_container(&self, id: &str) -> Result<(), Error> {
+		self.send(self.request(Method::Post, format!("/v1.43/containers/{id}/kill")))
 			.await?;
 
-		res.status().ok()
+		Ok(())
+	}
+
+	/// Follows a container's output until it exits, calling `on_line`
+	/// for each line written to its stdout or stderr.
+	///
+	/// Only works for containers created without a TTY.
+	pub async fn follow_logs<F: FnMut(LogStream, &str)>(
+		&self,
+		id: &str,
+		on_line: F,
+	) -> Result<(), Error> {
+		let mut req = self.request(Method::Get, format!("/v1.43/containers/{id}/logs"));
+		req.set_query(&LogsQuery {
+			follow: true,
+			stdout: true,
+			stderr: true,
+		})?;
+
+		let mut res = self.send(req).await?;
+
+		demux_logs(res.take_body(), on_line).await
+	}
+
+	pub async fn remove_container(&self, id: &str, force: bool) -> Result<(), Error> {
+		let mut req = self.request(Method::Delete, format!("/v1.43/containers/{id}"));
+		req.set_query(&RemoveContainerQuery { force: Some(force) })?;
+
+		self.send(req).await?;
+
+		Ok(())
 	}
 
+	/// Lists containers (including stopped ones), optionally
+	/// filtered to those with all of the given labels.
 	pub async fn list_containers(
 		&self,
 		labels: Option<Vec<(String, String)>>,
-	) -> Result<Vec<(String, String)>, Error> {
-		let req = surf::get(self.url("/v1.43/containers/json"));
-
-		let req = if let Some(labels) = labels {
-			req.query(&PruneContainersQuery {
-				filters: serde_json::to_string(&LabelFilters {
-					label: HashMap::from_iter(
-						labels.into_iter().map(|(k, v)| (format!("{k}={v}"), true)),
-					),
-				})?,
-			})
-			.unwrap()
-		} else {
-			req
-		};
+	) -> Result<Vec<ContainerListing>, Error> {
+		let mut req = self.request(Method::Get, "/v1.43/containers/json");
+
+		req.set_query(&ListContainersQuery {
+			all: true,
+			filters: labels
+				.map(|labels| {
+					serde_json::to_string(&LabelFilters {
+						label: HashMap::from_iter(
+							labels.into_iter().map(|(k, v)| (format!("{k}={v}"), true)),
+						),
+					})
+				})
+				.transpose()?,
+		})
+		.unwrap();
 
-		let mut res = req.send().await?;
+		let mut res = self.send(req).await?;
 
-		res.status().ok()?;
+		Ok(res.body_json().await?)
+	}
+}
 
-		let res: Vec<ContainerListing> = res.body_json().await?;
+/// Splits an image reference into the `fromImage` and `tag` parameters
+/// expected by `/images/create`. The tag may also be a digest.
+///
+/// Untagged references are given the `latest` tag; otherwise the daemon
+/// would pull every tag of the repository.
+pub(crate) fn split_reference(reference: &str) -> (&str, &str) {
+	if let Some((name, digest)) = reference.split_once('@') {
+		return (name, digest);
+	}
 
-		Ok(res.into_iter().map(|l| (l.id, l.state)).collect())
+	// A colon before the last slash belongs to a registry host:port.
+	let name_start = reference.rfind('/').map_or(0, |i| i + 1);
+	match reference[name_start..].rfind(':') {
+		Some(i) => (
+			&reference[..name_start + i],
+			&reference[name_start + i + 1..],
+		),
+		None => (reference, "latest"),
 	}
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullImageQuery<'a> {
+	from_image: &'a str,
+	tag: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PullProgress {
+	id: Option<String>,
+	status: Option<String>,
+	progress: Option<String>,
+	error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageInspect {
+	/// The local, content-addressed ID of the image (`sha256:...`)
+	pub id: String,
+	/// The `repo@sha256:...` digests the image is known by in
+	/// its registries, if it was pulled from one
+	#[serde(default)]
+	pub repo_digests: Vec<String>,
+}
+
+/// The stream a container log line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+	Stdout,
+	Stderr,
+}
+
+impl std::fmt::Display for LogStream {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Stdout => f.write_str("stdout"),
+			Self::Stderr => f.write_str("stderr"),
+		}
+	}
+}
+
+/// Splits Docker's multiplexed log stream into lines.
+///
+/// Each frame is an 8-byte header (the stream type, three bytes of padding
+/// and a big-endian `u32` length) followed by the payload. Frames don't
+/// line up with lines, so each stream is buffered separately until a
+/// newline shows up.
+async fn demux_logs<R: AsyncRead + Unpin, F: FnMut(LogStream, &str)>(
+	mut reader: R,
+	mut on_line: F,
+) -> Result<(), Error> {
+	let mut stdout = Vec::new();
+	let mut stderr = Vec::new();
+
+	let mut emit = |stream: LogStream, buf: &mut Vec<u8>, flush: bool| {
+		while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+			let line = buf.drain(..=end).collect::<Vec<_>>();
+			let line = String::from_utf8_lossy(&line);
+			on_line(stream, line.trim_end_matches(['\r', '\n']));
+		}
+
+		if flush && !buf.is_empty() {
+			on_line(stream, &String::from_utf8_lossy(buf));
+			buf.clear();
+		}
+	};
+
+	let mut header = [0u8; 8];
+	loop {
+		match reader.read_exact(&mut header).await {
+			Ok(()) => {}
+			Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+			Err(err) => return Err(err.into()),
+		}
+
+		let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+		let mut payload = vec![0u8; len];
+		reader.read_exact(&mut payload).await?;
+
+		match header[0] {
+			1 => {
+				stdout.extend_from_slice(&payload);
+				emit(LogStream::Stdout, &mut stdout, false);
+			}
+			2 => {
+				stderr.extend_from_slice(&payload);
+				emit(LogStream::Stderr, &mut stderr, false);
+			}
+			// stdin (0) is never sent back to us
+			unknown => warn!("docker: unknown log stream type: {unknown}"),
+		}
+	}
+
+	emit(LogStream::Stdout, &mut stdout, true);
+	emit(LogStream::Stderr, &mut stderr, true);
+
+	Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+struct LogsQuery {
+	follow: bool,
+	stdout: bool,
+	stderr: bool,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "lowercase")]
 struct RemoveContainerQuery {
@@ -143,9 +383,11 @@ struct RemoveContainerQuery {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
-struct ContainerListing {
-	id: String,
-	state: String,
+pub struct ContainerListing {
+	pub id: String,
+	pub state: String,
+	#[serde(default)]
+	pub labels: HashMap<String, String>,
 }
 
 #[derive(Serialize)]
@@ -159,8 +401,10 @@ struct LabelFilters {
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "lowercase")]
-struct PruneContainersQuery {
-	filters: String,
+struct ListContainersQuery {
+	all: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	filters: Option<String>,
 }
 
 #[derive(Debug, Default)]
@@ -250,6 +494,29 @@ pub struct CreateContainer {
 #[serde(rename_all = "PascalCase")]
 pub struct HostConfig {
 	pub binds: Option<Binds>,
+	pub memory: Option<i64>,
+	pub memory_swap: Option<i64>,
+	pub nano_cpus: Option<i64>,
+	pub pids_limit: Option<i64>,
+	pub readonly_rootfs: Option<bool>,
+	pub cap_drop: Option<Vec<String>>,
+	pub security_opt: Option<Vec<String>>,
+	pub network_mode: Option<String>,
+	pub tmpfs: Option<HashMap<String, String>>,
+	pub auto_remove: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct WaitContainerResponse {
+	status_code: i64,
+	error: Option<WaitContainerError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct WaitContainerError {
+	message: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -272,3 +539,382 @@ impl StatusCodeCheck for surf::StatusCode {
 		}
 	}
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use async_std::{os::unix::net::UnixListener, sync::Mutex, task};
+	use std::{
+		collections::BTreeMap,
+		sync::{
+			Arc,
+			atomic::{AtomicUsize, Ordering},
+		},
+	};
+	use surf::{StatusCode, http::Url};
+
+	/// A stand-in for the Docker Engine API, served over a Unix domain socket.
+	/// Only models the parts of the `/v1.43/{containers,images}/*` endpoints
+	/// that the daemon uses.
+	#[derive(Default)]
+	pub(crate) struct StandIn {
+		next_id: usize,
+		/// Images present on the "host"
+		pub images: Vec<String>,
+		/// Images that can be pulled from the "registry"
+		pub registry: Vec<String>,
+		/// Every image pull requested, successful or not
+		pub pulls: Vec<String>,
+		pub containers: BTreeMap<String, StandInContainer>,
+	}
+
+	pub(crate) struct StandInContainer {
+		pub image: String,
+		pub labels: HashMap<String, String>,
+		pub state: &'static str,
+	}
+
+	impl StandIn {
+		fn handle(&mut self, method: Method, url: &Url, body: serde_json::Value) -> Response {
+			let path = url
+				.path()
+				.strip_prefix("/v1.43/")
+				.expect("unversioned request");
+			let segments = path.split('/').collect::<Vec<_>>();
+
+			let query = url.query_pairs().collect::<HashMap<_, _>>();
+
+			match (method, segments.as_slice()) {
+				(Method::Get, ["images", image @ .., "json"]) => {
+					let image = image.join("/");
+					if self.images.contains(&image) {
+						json(
+							StatusCode::Ok,
+							serde_json::json!({
+								"Id": image_id(&image),
+								// Also known by a mirror, which is listed first
+								"RepoDigests": [
+									format!("mirror.local/{}@{}", split_reference(&image).0, image_id(&image)),
+									format!("{}@{}", split_reference(&image).0, image_id(&image)),
+								],
+							}),
+						)
+					} else {
+						not_found()
+					}
+				}
+				(Method::Post, ["images", "create"]) => {
+					let image = format!("{}:{}", query["fromImage"], query["tag"]);
+					self.pulls.push(image.clone());
+
+					let lines = if self.registry.contains(&image) {
+						self.images.push(image.clone());
+						vec![
+							serde_json::json!({ "status": format!("Pulling from {}", query["fromImage"]), "id": query["tag"] }),
+							serde_json::json!({ "status": "Downloading", "id": "abcd", "progress": "[=>  ]" }),
+							serde_json::json!({ "status": format!("Status: Downloaded newer image for {image}") }),
+						]
+					} else {
+						vec![
+							serde_json::json!({ "status": format!("Pulling from {}", query["fromImage"]), "id": query["tag"] }),
+							serde_json::json!({ "errorDetail": { "message": "manifest unknown" }, "error": "manifest unknown" }),
+						]
+					};
+
+					let mut res = Response::new(StatusCode::Ok);
+					res.set_body(
+						lines
+							.into_iter()
+							.map(|l| format!("{l}\r\n"))
+							.collect::<String>(),
+					);
+					res
+				}
+				(Method::Post, ["containers", "create"]) => {
+					self.next_id += 1;
+					let id = format!("c{}", self.next_id);
+					self.containers.insert(
+						id.clone(),
+						StandInContainer {
+							image: body["Image"].as_str().unwrap().into(),
+							labels: serde_json::from_value(body["Labels"].clone())
+								.unwrap_or_default(),
+							state: "created",
+						},
+					);
+					json(
+						StatusCode::Created,
+						serde_json::json!({ "Id": id, "Warnings": [] }),
+					)
+				}
+				(Method::Get, ["containers", "json"]) => {
+					let filters: HashMap<String, HashMap<String, bool>> = query
+						.get("filters")
+						.map(|f| serde_json::from_str(f).unwrap())
+						.unwrap_or_default();
+					let labels = filters.get("label").cloned().unwrap_or_default();
+
+					let listing = self
+						.containers
+						.iter()
+						.filter(|(_, c)| {
+							labels.keys().all(|kv| {
+								let (k, v) = kv.split_once('=').unwrap();
+								c.labels.get(k).map(String::as_str) == Some(v)
+							})
+						})
+						.map(
+							|(id, c)| serde_json::json!({ "Id": id, "State": c.state, "Labels": c.labels }),
+						)
+						.collect::<Vec<_>>();
+
+					json(StatusCode::Ok, listing.into())
+				}
+				(method, ["containers", id, action @ ..]) => {
+					let Some(container) = self.containers.get_mut(*id) else {
+						return not_found();
+					};
+
+					match (method, action) {
+						(Method::Post, ["start"]) => {
+							container.state = "running";
+							Response::new(StatusCode::NoContent)
+						}
+						(Method::Post, ["wait"]) => {
+							container.state = "exited";
+							json(StatusCode::Ok, serde_json::json!({ "StatusCode": 3 }))
+						}
+						(Method::Get, ["logs"]) => {
+							let mut body = Vec::new();
+							for (stream, data) in [
+								(1u8, &b"hello "[..]),
+								(2, b"warning: oops\r\n"),
+								(1, b"world\nbye"),
+							] {
+								body.extend_from_slice(&[stream, 0, 0, 0]);
+								body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+								body.extend_from_slice(data);
+							}
+
+							let mut res = Response::new(StatusCode::Ok);
+							res.set_body(body);
+							res
+						}
+						(Method::Post, ["kill"]) => {
+							container.state = "exited";
+							Response::new(StatusCode::NoContent)
+						}
+						(Method::Delete, []) => {
+							if container.state == "running"
+								&& query.get("force").map(|f| f.as_ref()) != Some("true")
+							{
+								return json(
+									StatusCode::Conflict,
+									serde_json::json!({ "message": "container is running" }),
+								);
+							}
+
+							self.containers.remove(*id);
+							Response::new(StatusCode::NoContent)
+						}
+						_ => not_found(),
+					}
+				}
+				_ => not_found(),
+			}
+		}
+	}
+
+	fn json(status: StatusCode, value: serde_json::Value) -> Response {
+		let mut res = Response::new(status);
+		res.set_body(Body::from_json(&value).unwrap());
+		res
+	}
+
+	pub(crate) fn image_id(image: &str) -> String {
+		format!("sha256:{:064x}", image.len())
+	}
+
+	fn not_found() -> Response {
+		json(
+			StatusCode::NotFound,
+			serde_json::json!({ "message": "not found" }),
+		)
+	}
+
+	/// Starts a stand-in server on a fresh socket, returning its state
+	/// and a client pointed at it.
+	pub(crate) async fn stand_in() -> (Arc<Mutex<StandIn>>, Docker) {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+		let socket = std::env::temp_dir().join(format!(
+			"link-daemon-docker-{}-{}.sock",
+			std::process::id(),
+			COUNTER.fetch_add(1, Ordering::Relaxed)
+		));
+		let _ = std::fs::remove_file(&socket);
+
+		let listener = UnixListener::bind(&socket).await.unwrap();
+		let state = Arc::new(Mutex::new(StandIn {
+			images: vec!["oro/runner:latest".into()],
+			registry: vec!["oro/runner:latest".into(), "oro/runner:next".into()],
+			..Default::default()
+		}));
+
+		task::spawn({
+			let state = state.clone();
+			async move {
+				loop {
+					let (stream, _) = listener.accept().await.unwrap();
+					let state = state.clone();
+					task::spawn(async_h1::accept(stream, move |mut req: Request| {
+						let state = state.clone();
+						async move {
+							let body = req.body_string().await?;
+							let body = if body.is_empty() {
+								serde_json::Value::Null
+							} else {
+								serde_json::from_str(&body)?
+							};
+							Ok(state.lock().await.handle(req.method(), req.url(), body))
+						}
+					}));
+				}
+			}
+		});
+
+		let docker = Docker::new(&format!("unix://{}", socket.display())).unwrap();
+
+		(state, docker)
+	}
+
+	#[test]
+	fn parses_host_uris() {
+		let docker = Docker::new("unix:///var/run/docker.sock").unwrap();
+		assert_eq!(
+			docker.socket.as_deref(),
+			Some(std::path::Path::new("/var/run/docker.sock"))
+		);
+
+		let docker = Docker::new("http://127.0.0.1:2375").unwrap();
+		assert!(docker.socket.is_none());
+		assert_eq!(docker.base.as_str(), "http://127.0.0.1:2375/");
+	}
+
+	#[test]
+	fn splits_image_references() {
+		assert_eq!(split_reference("oro/runner"), ("oro/runner", "latest"));
+		assert_eq!(split_reference("oro/runner:next"), ("oro/runner", "next"));
+		assert_eq!(
+			split_reference("localhost:5000/oro/runner"),
+			("localhost:5000/oro/runner", "latest")
+		);
+		assert_eq!(
+			split_reference("localhost:5000/oro/runner:next"),
+			("localhost:5000/oro/runner", "next")
+		);
+		assert_eq!(
+			split_reference("oro/runner@sha256:1234"),
+			("oro/runner", "sha256:1234")
+		);
+	}
+
+	#[async_std::test]
+	async fn inspects_images_over_unix_socket() {
+		let (_, docker) = stand_in().await;
+
+		let image = docker.inspect_image("oro/runner:latest").await.unwrap();
+		assert_eq!(image.id, image_id("oro/runner:latest"));
+		assert_eq!(image.repo_digests.len(), 2);
+
+		match docker.inspect_image("oro/missing:latest").await {
+			Err(Error::HttpStatus(StatusCode::NotFound)) => {}
+			other => panic!("expected 404, got {other:?}"),
+		}
+	}
+
+	#[async_std::test]
+	async fn pulls_images() {
+		let (state, docker) = stand_in().await;
+
+		docker.pull_image("oro/runner:next").await.unwrap();
+		assert!(
+			state
+				.lock()
+				.await
+				.images
+				.contains(&"oro/runner:next".into())
+		);
+		docker.inspect_image("oro/runner:next").await.unwrap();
+
+		match docker.pull_image("oro/missing").await {
+			Err(Error::Pull(message)) => assert_eq!(message, "manifest unknown"),
+			other => panic!("expected pull error, got {other:?}"),
+		}
+	}
+
+	#[async_std::test]
+	async fn manages_container_lifecycle_over_unix_socket() {
+		let (state, docker) = stand_in().await;
+
+		let id = docker
+			.create_container(&CreateContainer {
+				image: "oro/runner:latest".into(),
+				labels: Some(
+					Map::new()
+						.add("sh.oro".into(), "link".into())
+						.add("sh.oro.link".into(), "ABCD".into()),
+				),
+				..Default::default()
+			})
+			.await
+			.unwrap();
+
+		assert_eq!(
+			state.lock().await.containers[&id].image,
+			"oro/runner:latest"
+		);
+
+		docker.start_container(&id).await.unwrap();
+
+		let listed = docker
+			.list_containers(Some(vec![("sh.oro.link".into(), "ABCD".into())]))
+			.await
+			.unwrap();
+		assert_eq!(listed.len(), 1);
+		assert_eq!(listed[0].id, id);
+		assert_eq!(listed[0].state, "running");
+		assert_eq!(listed[0].labels["sh.oro.link"], "ABCD");
+
+		let listed = docker
+			.list_containers(Some(vec![("sh.oro.link".into(), "EFGH".into())]))
+			.await
+			.unwrap();
+		assert!(listed.is_empty());
+
+		let mut lines = Vec::new();
+		docker
+			.follow_logs(&id, |stream, line| lines.push((stream, line.to_string())))
+			.await
+			.unwrap();
+		assert_eq!(
+			lines,
+			vec![
+				(LogStream::Stderr, "warning: oops".into()),
+				(LogStream::Stdout, "hello world".into()),
+				(LogStream::Stdout, "bye".into()),
+			]
+		);
+
+		assert_eq!(docker.wait_for_container(&id).await.unwrap(), 3);
+		docker.kill_container(&id).await.unwrap();
+		docker.remove_container(&id, true).await.unwrap();
+
+		assert!(docker.list_containers(None).await.unwrap().is_empty());
+
+		match docker.start_container(&id).await {
+			Err(Error::HttpStatus(StatusCode::NotFound)) => {}
+			other => panic!("expected 404, got {other:?}"),
+		}
+	}
+}
@@ -0,0 +1,232 @@
+//! An in-process registry of the links connected to the daemon
+//! and what their sessions are up to.
+use crate::{
+	hardware::HardwareInfo,
+	session::{LinkControl, SerialTap, SessionState},
+};
+use serde::Serialize;
+use std::{
+	collections::BTreeMap,
+	net::SocketAddr,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A snapshot of a connected link.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LinkStatus {
+	/// The (hex-encoded) UID of the link
+	pub uid: String,
+	/// The link's display name, if configured
+	pub name: Option<String>,
+	/// The link's firmware version
+	pub version: String,
+	/// The link's self-reported hardware, if it sent a report
+	pub hardware: Option<HardwareInfo>,
+	/// The address the link connected from
+	pub peer: SocketAddr,
+	pub state: SessionState,
+	/// The ID of the runner container, if any
+	pub container: Option<String>,
+	/// When the link connected, in milliseconds since the Unix epoch
+	pub connected_at: u64,
+	/// When a packet was last received from the link, in milliseconds
+	/// since the Unix epoch
+	pub last_seen: u64,
+	/// The last measured round-trip time to the link, in milliseconds
+	pub rtt_ms: Option<f64>,
+	/// Whether the link is on the bench for maintenance
+	pub maintenance: bool,
+	/// Whether the link configuration turned the link away; it's
+	/// disconnected shortly, and can't be controlled until then
+	pub rejected: bool,
+}
+
+struct Record {
+	connection: u64,
+	status: LinkStatus,
+	control: LinkControl,
+	/// Set once the link's session is up
+	serial: Option<SerialTap>,
+}
+
+/// The links currently connected to the daemon, keyed by UID.
+#[derive(Default)]
+pub(crate) struct Fleet {
+	links: Mutex<BTreeMap<String, Record>>,
+	next_connection: AtomicU64,
+}
+
+impl Fleet {
+	/// Registers a newly connected link. It's removed again once the
+	/// returned handle (and all of its clones) are dropped.
+	///
+	/// If the link was already registered (e.g. it reconnected before
+	/// its previous session was torn down), it's replaced.
+	pub fn register(
+		self: &Arc<Self>,
+		uid: &str,
+		version: &str,
+		peer: SocketAddr,
+		control: LinkControl,
+	) -> FleetEntry {
+		let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+		let now = unix_millis(SystemTime::now());
+
+		self.links.lock().unwrap().insert(
+			uid.to_string(),
+			Record {
+				connection,
+				status: LinkStatus {
+					uid: uid.to_string(),
+					name: None,
+					version: version.to_string(),
+					hardware: None,
+					peer,
+					state: SessionState::WaitingForClient,
+					container: None,
+					connected_at: now,
+					last_seen: now,
+					rtt_ms: None,
+					maintenance: false,
+					rejected: false,
+				},
+				control,
+				serial: None,
+			},
+		);
+
+		FleetEntry(Arc::new(EntryInner {
+			fleet: self.clone(),
+			uid: uid.to_string(),
+			connection,
+		}))
+	}
+
+	/// Lists all connected links.
+	pub fn list(&self) -> Vec<LinkStatus> {
+		self.links
+			.lock()
+			.unwrap()
+			.values()
+			.map(|r| r.status.clone())
+			.collect()
+	}
+
+	pub fn get(&self, uid: &str) -> Option<LinkStatus> {
+		self.links
+			.lock()
+			.unwrap()
+			.get(uid)
+			.map(|r| r.status.clone())
+	}
+
+	/// Gets a handle to send maintenance packets to a link.
+	pub fn control(&self, uid: &str) -> Option<LinkControl> {
+		self.links
+			.lock()
+			.unwrap()
+			.get(uid)
+			.map(|r| r.control.clone())
+	}
+
+	/// Gets a handle to watch a link's serial output.
+	pub fn serial(&self, uid: &str) -> Option<SerialTap> {
+		self.links
+			.lock()
+			.unwrap()
+			.get(uid)
+			.and_then(|r| r.serial.clone())
+	}
+}
+
+/// A connected link's entry in the [`Fleet`], through which its
+/// session keeps the entry up to date.
+#[derive(Clone)]
+pub(crate) struct FleetEntry(Arc<EntryInner>);
+
+struct EntryInner {
+	fleet: Arc<Fleet>,
+	uid: String,
+	connection: u64,
+}
+
+impl FleetEntry {
+	fn update_record(&self, f: impl FnOnce(&mut Record)) {
+		let mut links = self.0.fleet.links.lock().unwrap();
+		if let Some(record) = links.get_mut(&self.0.uid)
+			&& record.connection == self.0.connection
+		{
+			f(record);
+		}
+	}
+
+	fn update(&self, f: impl FnOnce(&mut LinkStatus)) {
+		self.update_record(|record| f(&mut record.status));
+	}
+
+	pub fn set_name(&self, name: Option<String>) {
+		self.update(|status| status.name = name);
+	}
+
+	pub fn set_hardware(&self, hardware: Option<HardwareInfo>) {
+		self.update(|status| status.hardware = hardware);
+	}
+
+	pub fn set_state(&self, state: SessionState) {
+		self.update(|status| status.state = state);
+	}
+
+	pub fn set_container(&self, container: Option<String>) {
+		self.update(|status| status.container = container);
+	}
+
+	pub fn set_maintenance(&self, maintenance: bool) {
+		self.update(|status| status.maintenance = maintenance);
+	}
+
+	pub fn set_rejected(&self) {
+		self.update(|status| status.rejected = true);
+	}
+
+	pub fn set_serial(&self, serial: SerialTap) {
+		self.update_record(|record| record.serial = Some(serial));
+	}
+
+	/// Marks the link as having just been heard from.
+	pub fn seen(&self) {
+		let now = unix_millis(SystemTime::now());
+		self.update(|status| status.last_seen = now);
+	}
+
+	pub fn set_rtt(&self, rtt: Duration) {
+		self.update(|status| status.rtt_ms = Some(rtt.as_secs_f64() * 1000.0));
+	}
+}
+
+impl std::fmt::Debug for FleetEntry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_tuple("FleetEntry").field(&self.0.uid).finish()
+	}
+}
+
+impl Drop for EntryInner {
+	fn drop(&mut self) {
+		let mut links = self.fleet.links.lock().unwrap();
+		if links
+			.get(&self.uid)
+			.is_some_and(|r| r.connection == self.connection)
+		{
+			links.remove(&self.uid);
+		}
+	}
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_millis() as u64)
+		.unwrap_or_default()
+}
@@ -0,0 +1,217 @@
+//! A (very) minimal GitHub REST API client, used to register
+//! runners without handing the organization access token to them.
+use serde::Deserialize;
+use surf::StatusCode;
+use url::Url;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("failed to parse URI: {0}")]
+	Uri(#[from] url::ParseError),
+	#[error("failed to perform HTTP request: {0}")]
+	Http(surf::Error),
+	#[error("request returned non-2xx status: {0}: {1}")]
+	HttpStatus(StatusCode, String),
+}
+
+impl From<surf::Error> for Error {
+	#[inline]
+	fn from(value: surf::Error) -> Self {
+		Self::Http(value)
+	}
+}
+
+#[derive(Clone)]
+pub struct GitHub {
+	base: Url,
+	access_token: String,
+}
+
+/// A short-lived token that registers a single runner.
+#[derive(Deserialize, Debug)]
+pub struct RegistrationToken {
+	pub token: String,
+	pub expires_at: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+	message: String,
+}
+
+impl GitHub {
+	/// Creates a new client given the API base URL
+	/// (e.g. `https://api.github.com`, or `https://HOST/api/v3` for
+	/// GitHub Enterprise Server) and an access token.
+	pub fn new(base: &str, access_token: String) -> Result<Self, Error> {
+		let mut base = Url::parse(base)?;
+		// Endpoints are joined onto the base, which would otherwise
+		// replace its last path segment (e.g. the `v3` of `/api/v3`).
+		if !base.path().ends_with('/') {
+			base.set_path(&format!("{}/", base.path()));
+		}
+
+		Ok(Self { base, access_token })
+	}
+
+	/// The URL of an API endpoint, given its path (without a leading `/`).
+	fn endpoint(&self, path: &str) -> Result<Url, Error> {
+		Ok(self.base.join(path)?)
+	}
+
+	/// Creates a registration token for an organization-level runner.
+	pub async fn registration_token(&self, org: &str) -> Result<RegistrationToken, Error> {
+		let url = self.endpoint(&format!("orgs/{org}/actions/runners/registration-token"))?;
+
+		let mut res = surf::post(url)
+			.header("Authorization", format!("Bearer {}", self.access_token))
+			.header("Accept", "application/vnd.github+json")
+			.header("X-GitHub-Api-Version", "2022-11-28")
+			.header(
+				"User-Agent",
+				concat!("oro-linkd/", env!("CARGO_PKG_VERSION")),
+			)
+			.await?;
+
+		if !res.status().is_success() {
+			let message = match res.body_json::<ErrorResponse>().await {
+				Ok(body) => body.message,
+				Err(_) => String::new(),
+			};
+
+			return Err(Error::HttpStatus(res.status(), message));
+		}
+
+		Ok(res.body_json().await?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use async_std::{net::TcpListener, sync::Mutex, task};
+	use std::sync::Arc;
+	use surf::http::{Method, Request, Response};
+
+	const ACCESS_TOKEN: &str = "ghp_test";
+
+	/// Serves a stand-in for the GitHub API that hands out registration
+	/// tokens to requests bearing `ACCESS_TOKEN` for the `oro-os` organization.
+	async fn stand_in() -> (Arc<Mutex<Vec<Request>>>, GitHub) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let requests = Arc::new(Mutex::new(Vec::new()));
+
+		task::spawn({
+			let requests = requests.clone();
+			async move {
+				loop {
+					let (stream, _) = listener.accept().await.unwrap();
+					let requests = requests.clone();
+					task::spawn(async_h1::accept(stream, move |req: Request| {
+						let requests = requests.clone();
+						async move {
+							let res = handle(&req);
+							requests.lock().await.push(req);
+							Ok(res)
+						}
+					}));
+				}
+			}
+		});
+
+		let github = GitHub::new(&format!("http://{addr}/"), ACCESS_TOKEN.into()).unwrap();
+
+		(requests, github)
+	}
+
+	fn handle(req: &Request) -> Response {
+		let json = |status, value: serde_json::Value| {
+			let mut res = Response::new(status);
+			res.set_body(value);
+			res
+		};
+
+		let authorized = req
+			.header("Authorization")
+			.is_some_and(|v| v.as_str() == format!("Bearer {ACCESS_TOKEN}"));
+
+		if !authorized {
+			return json(
+				StatusCode::Unauthorized,
+				serde_json::json!({ "message": "Bad credentials" }),
+			);
+		}
+
+		match (req.method(), req.url().path()) {
+			(Method::Post, "/orgs/oro-os/actions/runners/registration-token") => json(
+				StatusCode::Created,
+				serde_json::json!({
+					"token": "REGTOKEN",
+					"expires_at": "2020-01-22T12:13:35.123-08:00",
+				}),
+			),
+			_ => json(
+				StatusCode::NotFound,
+				serde_json::json!({ "message": "Not Found" }),
+			),
+		}
+	}
+
+	#[test]
+	fn keeps_the_base_path() {
+		for base in [
+			"https://api.github.com",
+			"https://api.github.com/",
+			"https://github.example.com/api/v3",
+			"https://github.example.com/api/v3/",
+		] {
+			let github = GitHub::new(base, ACCESS_TOKEN.into()).unwrap();
+			assert_eq!(
+				github.endpoint("orgs/oro-os").unwrap().as_str(),
+				format!("{}/orgs/oro-os", base.trim_end_matches('/')),
+			);
+		}
+	}
+
+	#[async_std::test]
+	async fn creates_registration_tokens() {
+		let (requests, github) = stand_in().await;
+
+		let token = github.registration_token("oro-os").await.unwrap();
+		assert_eq!(token.token, "REGTOKEN");
+		assert_eq!(token.expires_at, "2020-01-22T12:13:35.123-08:00");
+
+		let requests = requests.lock().await;
+		assert_eq!(requests.len(), 1);
+		assert_eq!(
+			requests[0].header("Accept").unwrap().as_str(),
+			"application/vnd.github+json"
+		);
+		assert!(requests[0].header("User-Agent").is_some());
+	}
+
+	#[async_std::test]
+	async fn reports_api_errors() {
+		let (_, github) = stand_in().await;
+
+		match github.registration_token("someone-else").await {
+			Err(Error::HttpStatus(StatusCode::NotFound, message)) => {
+				assert_eq!(message, "Not Found")
+			}
+			other => panic!("expected 404, got {other:?}"),
+		}
+
+		let github = GitHub {
+			access_token: "ghp_wrong".into(),
+			..github
+		};
+
+		match github.registration_token("oro-os").await {
+			Err(Error::HttpStatus(StatusCode::Unauthorized, message)) => {
+				assert_eq!(message, "Bad credentials")
+			}
+			other => panic!("expected 401, got {other:?}"),
+		}
+	}
+}
@@ -0,0 +1,113 @@
+//! The hardware a link reports about itself (see
+//! [`Packet::HardwareReport`]), and the runner labels derived from it.
+use link_protocol::{Packet, SutArch};
+use serde::Serialize;
+
+/// A link's self-reported hardware description.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct HardwareInfo {
+	/// The link's board variant
+	pub board: String,
+	/// The architecture of the system under test, as used in
+	/// GitHub runner labels (e.g. `x64`)
+	pub arch: String,
+	pub usb_hid: bool,
+	pub capture: bool,
+	pub sd_mux: bool,
+	pub serial_ports: u8,
+	pub features: Vec<String>,
+}
+
+impl HardwareInfo {
+	/// Extracts the hardware description from a [`Packet::HardwareReport`].
+	pub fn from_packet(packet: &Packet) -> Option<Self> {
+		let Packet::HardwareReport {
+			board,
+			arch,
+			usb_hid,
+			capture,
+			sd_mux,
+			serial_ports,
+			features,
+		} = packet
+		else {
+			return None;
+		};
+
+		Some(Self {
+			board: board.to_string(),
+			arch: match arch {
+				SutArch::X86_64 => "x64".into(),
+				SutArch::Aarch64 => "arm64".into(),
+				SutArch::Riscv64 => "riscv64".into(),
+				unknown => format!("{unknown:?}").to_ascii_lowercase(),
+			},
+			usb_hid: *usb_hid,
+			capture: *capture,
+			sd_mux: *sd_mux,
+			serial_ports: *serial_ports,
+			features: features
+				.split(',')
+				.map(str::trim)
+				.filter(|f| !f.is_empty())
+				.map(String::from)
+				.collect(),
+		})
+	}
+
+	/// The runner labels describing the hardware, e.g.
+	/// `x64`, `board-stm32f479vg`, `usb-hid`, `serial`, `feature-monitor`.
+	pub fn labels(&self) -> Vec<String> {
+		let mut labels = vec![self.arch.clone(), format!("board-{}", self.board)];
+
+		for (present, label) in [
+			(self.usb_hid, "usb-hid"),
+			(self.capture, "capture"),
+			(self.sd_mux, "sd-mux"),
+			(self.serial_ports > 0, "serial"),
+		] {
+			if present {
+				labels.push(label.into());
+			}
+		}
+
+		labels.extend(self.features.iter().map(|f| format!("feature-{f}")));
+
+		labels
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn derives_labels_from_report() {
+		let info = HardwareInfo::from_packet(&Packet::HardwareReport {
+			board: "stm32f479vg".try_into().unwrap(),
+			arch: SutArch::X86_64,
+			usb_hid: true,
+			capture: false,
+			sd_mux: true,
+			serial_ports: 1,
+			features: "monitor, indicators,".try_into().unwrap(),
+		})
+		.unwrap();
+
+		assert_eq!(info.features, vec!["monitor", "indicators"]);
+		assert_eq!(
+			info.labels(),
+			vec![
+				"x64",
+				"board-stm32f479vg",
+				"usb-hid",
+				"sd-mux",
+				"serial",
+				"feature-monitor",
+				"feature-indicators",
+			]
+		);
+
+		assert_eq!(HardwareInfo::from_packet(&Packet::PressReset), None);
+	}
+}
@@ -0,0 +1,323 @@
+//! Resolves the runner image, pulling it as needed, and pins the exact
+//! image (by ID) that sessions run.
+use crate::{
+	Config, Error,
+	docker::{self, Docker},
+};
+use async_std::{sync::Mutex, task};
+use log::{debug, info, warn};
+use std::{
+	str::FromStr,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+/// When the runner image is pulled from its registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PullPolicy {
+	/// Never pull; the image must already exist on the Docker host
+	Never,
+	/// Pull only if the image doesn't exist on the Docker host
+	Missing,
+	/// Pull at startup and re-check for updates between sessions,
+	/// at most once every `DOCKER_PULL_INTERVAL` seconds
+	Always,
+}
+
+impl FromStr for PullPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"never" => Ok(Self::Never),
+			"missing" => Ok(Self::Missing),
+			"always" => Ok(Self::Always),
+			unknown => Err(format!(
+				"unknown pull policy (expected never, missing or always): {unknown}"
+			)),
+		}
+	}
+}
+
+/// The image a session should run.
+#[derive(Debug, Clone)]
+pub(crate) struct PinnedImage {
+	/// The local image ID (`sha256:...`), which is what containers
+	/// are created from so that a tag moving mid-session (or between
+	/// resolving and creating) can't change what runs.
+	pub id: String,
+	/// The registry digest (`repo@sha256:...`), if the image came from one
+	pub digest: Option<String>,
+}
+
+struct Resolved {
+	image: PinnedImage,
+	checked_at: Instant,
+}
+
+/// Tracks the runner image reference (e.g. `DOCKER_REF`) and the
+/// image it currently resolves to. Shared between all sessions.
+pub(crate) struct RunnerImage {
+	docker: Docker,
+	reference: String,
+	policy: PullPolicy,
+	interval: Duration,
+	/// What the reference currently resolves to. Only ever locked
+	/// briefly, so that sessions never wait on Docker through it.
+	resolved: Mutex<Option<Resolved>>,
+	/// Held while resolving, so that only one pull happens at a time
+	resolving: Mutex<()>,
+	/// Whether a check for updates is running in the background
+	refreshing: AtomicBool,
+}
+
+impl RunnerImage {
+	pub fn new(config: &Config) -> Result<Self, Error> {
+		Self::with_reference(
+			config,
+			config
+				.docker_ref
+				.clone()
+				.ok_or(Error::MissingConfig("DOCKER_REF"))?,
+		)
+	}
+
+	/// Tracks a reference other than `DOCKER_REF` (e.g. one
+	/// given for a link in the link configuration).
+	pub fn with_reference(config: &Config, reference: String) -> Result<Self, Error> {
+		Ok(Self {
+			docker: Docker::new(
+				config
+					.docker_host
+					.as_deref()
+					.ok_or(Error::MissingConfig("DOCKER_HOST"))?,
+			)?,
+			reference,
+			policy: config.docker_pull_policy,
+			interval: Duration::from_secs(config.docker_pull_interval),
+			resolved: Mutex::new(None),
+			resolving: Mutex::new(()),
+			refreshing: AtomicBool::new(false),
+		})
+	}
+
+	/// The configured image reference (e.g. as given in `DOCKER_REF`).
+	pub fn reference(&self) -> &str {
+		&self.reference
+	}
+
+	/// Resolves the image to run, pulling it first if the pull policy
+	/// calls for it.
+	///
+	/// Once resolved, checks for updates happen in the background;
+	/// sessions keep getting the current image in the meantime.
+	pub async fn resolve(self: &Arc<Self>) -> Result<PinnedImage, Error> {
+		if let Some(resolved) = self.resolved.lock().await.as_ref() {
+			if self.is_stale(resolved) && !self.refreshing.swap(true, Ordering::AcqRel) {
+				let this = self.clone();
+				task::spawn(async move {
+					// The current image stays in place; it's checked
+					// again for the next session.
+					if let Err(err) = this.refresh().await {
+						warn!(
+							"failed to check for updates to image: {}: {err}",
+							this.reference
+						);
+					}
+					this.refreshing.store(false, Ordering::Release);
+				});
+			}
+
+			return Ok(resolved.image.clone());
+		}
+
+		self.refresh().await
+	}
+
+	fn is_stale(&self, resolved: &Resolved) -> bool {
+		self.policy == PullPolicy::Always && resolved.checked_at.elapsed() >= self.interval
+	}
+
+	/// Resolves the reference again, pulling as the pull policy calls for.
+	async fn refresh(&self) -> Result<PinnedImage, Error> {
+		let _resolving = self.resolving.lock().await;
+
+		// It may have been resolved while waiting for the lock.
+		let previous = match self.resolved.lock().await.as_ref() {
+			Some(resolved) if !self.is_stale(resolved) => return Ok(resolved.image.clone()),
+			resolved => resolved.map(|resolved| resolved.image.clone()),
+		};
+
+		let reference = &self.reference;
+
+		if self.policy == PullPolicy::Always {
+			debug!("checking for updates to image: {reference}");
+			if let Err(err) = self.docker.pull_image(reference).await {
+				// Keep running whatever we had, if anything, rather than
+				// taking the whole fleet down because the registry is.
+				if previous.is_none() {
+					return Err(err.into());
+				}
+
+				warn!("failed to check for updates to image: {reference}: {err}");
+			}
+		}
+
+		let inspect = match self.docker.inspect_image(reference).await {
+			Err(docker::Error::HttpStatus(surf::StatusCode::NotFound))
+				if self.policy == PullPolicy::Missing =>
+			{
+				info!("image not found on docker host; pulling: {reference}");
+				self.docker.pull_image(reference).await?;
+				self.docker.inspect_image(reference).await?
+			}
+			result => result?,
+		};
+
+		// An image may be known by several repositories; the one pinned
+		// is the one it was asked for by.
+		let (repository, _) = docker::split_reference(reference);
+		let image = PinnedImage {
+			digest: inspect
+				.repo_digests
+				.into_iter()
+				.find(|digest| digest.split_once('@').map(|(repo, _)| repo) == Some(repository)),
+			id: inspect.id,
+		};
+
+		match previous {
+			Some(previous) if previous.id == image.id => {
+				debug!("image is up to date: {reference}");
+			}
+			Some(previous) => {
+				info!(
+					"image updated: {reference}: {} -> {} ({})",
+					previous.id,
+					image.id,
+					image.digest.as_deref().unwrap_or("no digest"),
+				);
+			}
+			None => {
+				info!(
+					"pinned image: {reference}: {} ({})",
+					image.id,
+					image.digest.as_deref().unwrap_or("no digest"),
+				);
+			}
+		}
+
+		*self.resolved.lock().await = Some(Resolved {
+			image: image.clone(),
+			checked_at: Instant::now(),
+		});
+
+		Ok(image)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::docker::tests::{StandIn, image_id, stand_in};
+
+	async fn runner_image(
+		reference: &str,
+		policy: PullPolicy,
+		interval: Duration,
+	) -> (Arc<Mutex<StandIn>>, Arc<RunnerImage>) {
+		let (state, docker) = stand_in().await;
+		let image = RunnerImage {
+			docker,
+			reference: reference.into(),
+			policy,
+			interval,
+			resolved: Mutex::new(None),
+			resolving: Mutex::new(()),
+			refreshing: AtomicBool::new(false),
+		};
+
+		(state, Arc::new(image))
+	}
+
+	const HOUR: Duration = Duration::from_secs(3600);
+
+	#[async_std::test]
+	async fn pins_the_requested_repository() {
+		let (state, image) = runner_image("oro/runner:latest", PullPolicy::Never, HOUR).await;
+
+		let pinned = image.resolve().await.unwrap();
+		assert_eq!(pinned.id, image_id("oro/runner:latest"));
+		assert_eq!(
+			pinned.digest,
+			Some(format!("oro/runner@{}", image_id("oro/runner:latest")))
+		);
+		assert!(state.lock().await.pulls.is_empty());
+	}
+
+	#[async_std::test]
+	async fn never_pulls_with_never() {
+		let (state, image) = runner_image("oro/runner:next", PullPolicy::Never, HOUR).await;
+
+		assert!(image.resolve().await.is_err());
+		assert!(state.lock().await.pulls.is_empty());
+	}
+
+	#[async_std::test]
+	async fn pulls_missing_images_once() {
+		let (state, image) = runner_image("oro/runner:next", PullPolicy::Missing, HOUR).await;
+
+		let pinned = image.resolve().await.unwrap();
+		assert_eq!(pinned.id, image_id("oro/runner:next"));
+		image.resolve().await.unwrap();
+		assert_eq!(state.lock().await.pulls, ["oro/runner:next"]);
+
+		// Images already on the host aren't pulled at all
+		let (state, image) = runner_image("oro/runner:latest", PullPolicy::Missing, HOUR).await;
+		image.resolve().await.unwrap();
+		assert!(state.lock().await.pulls.is_empty());
+	}
+
+	#[async_std::test]
+	async fn pulls_always_at_most_once_per_interval() {
+		let (state, image) = runner_image("oro/runner:latest", PullPolicy::Always, HOUR).await;
+
+		image.resolve().await.unwrap();
+		image.resolve().await.unwrap();
+		assert_eq!(state.lock().await.pulls.len(), 1);
+
+		// Once stale, the current image is handed out while checking
+		// for updates in the background.
+		let (state, image) =
+			runner_image("oro/runner:latest", PullPolicy::Always, Duration::ZERO).await;
+		let pinned = image.resolve().await.unwrap();
+		assert_eq!(image.resolve().await.unwrap().id, pinned.id);
+
+		async_std::future::timeout(Duration::from_secs(5), async {
+			while state.lock().await.pulls.len() < 2 {
+				task::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.expect("no background pull");
+	}
+
+	#[async_std::test]
+	async fn keeps_the_previous_image_when_the_registry_fails() {
+		let (state, image) = runner_image("oro/runner:latest", PullPolicy::Always, HOUR).await;
+		let pinned = image.resolve().await.unwrap();
+
+		state.lock().await.registry.clear();
+		image.resolved.lock().await.as_mut().unwrap().checked_at -= HOUR;
+
+		assert_eq!(image.refresh().await.unwrap().id, pinned.id);
+		assert_eq!(state.lock().await.pulls.len(), 2);
+
+		// With nothing to fall back on, it's an error.
+		let (state, image) = runner_image("oro/runner:next", PullPolicy::Always, HOUR).await;
+		state.lock().await.registry.clear();
+		assert!(image.resolve().await.is_err());
+	}
+}
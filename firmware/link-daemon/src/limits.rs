@@ -0,0 +1,237 @@
+//! Resource limits and hardening options for runner containers.
+//!
+//! Daemon-wide defaults come from the `DOCKER_*` environment variables
+//! (see [`Config`]); individual links can override them in the link
+//! configuration file (see [`crate::links`]).
+use crate::{Config, docker::HostConfig};
+use serde::{Deserialize, Deserializer, de};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Limits applied to a runner container. Anything left unset
+/// is left up to the Docker host.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ContainerLimits {
+	/// Memory limit (e.g. `4g`). Swap is disabled when set.
+	pub memory: Option<ByteSize>,
+	/// How many CPUs worth of time the container may use (e.g. `1.5`)
+	pub cpus: Option<f64>,
+	/// Maximum number of processes/threads in the container
+	pub pids_limit: Option<i64>,
+	/// Mounts the container's root filesystem read-only
+	pub read_only: Option<bool>,
+	/// Capabilities to drop (e.g. `ALL`)
+	pub cap_drop: Option<Vec<String>>,
+	/// Stops processes from gaining privileges (e.g. via `sudo`)
+	pub no_new_privileges: Option<bool>,
+	/// The network mode (e.g. `bridge`, `none` or a network name)
+	pub network: Option<String>,
+	/// tmpfs mounts, as `/path` or `/path:options`
+	/// (e.g. `/tmp:rw,size=64m`)
+	pub tmpfs: Option<Vec<String>>,
+	/// Has Docker remove the container as soon as it exits
+	pub auto_remove: Option<bool>,
+}
+
+impl ContainerLimits {
+	/// The daemon-wide limits.
+	pub fn from_config(config: &Config) -> Self {
+		Self {
+			memory: config.docker_memory,
+			cpus: config.docker_cpus,
+			pids_limit: config.docker_pids_limit,
+			read_only: config.docker_read_only,
+			cap_drop: config.docker_cap_drop.clone().map(|l| l.0),
+			no_new_privileges: config.docker_no_new_privileges,
+			network: config.docker_network.clone(),
+			tmpfs: config.docker_tmpfs.clone().map(|l| l.0),
+			auto_remove: config.docker_auto_remove,
+		}
+	}
+
+	/// Fills in anything that isn't set from `defaults`.
+	pub fn or(self, defaults: &Self) -> Self {
+		Self {
+			memory: self.memory.or(defaults.memory),
+			cpus: self.cpus.or(defaults.cpus),
+			pids_limit: self.pids_limit.or(defaults.pids_limit),
+			read_only: self.read_only.or(defaults.read_only),
+			cap_drop: self.cap_drop.or_else(|| defaults.cap_drop.clone()),
+			no_new_privileges: self.no_new_privileges.or(defaults.no_new_privileges),
+			network: self.network.or_else(|| defaults.network.clone()),
+			tmpfs: self.tmpfs.or_else(|| defaults.tmpfs.clone()),
+			auto_remove: self.auto_remove.or(defaults.auto_remove),
+		}
+	}
+
+	/// Applies the limits to a container's host configuration.
+	pub fn apply(&self, host_config: &mut HostConfig) {
+		if let Some(memory) = self.memory {
+			host_config.memory = Some(memory.0 as i64);
+			host_config.memory_swap = Some(memory.0 as i64);
+		}
+
+		host_config.nano_cpus = self.cpus.map(|cpus| (cpus * 1e9) as i64);
+		host_config.pids_limit = self.pids_limit;
+		host_config.readonly_rootfs = self.read_only;
+		host_config.cap_drop = self.cap_drop.clone();
+		host_config.network_mode = self.network.clone();
+		host_config.auto_remove = self.auto_remove;
+
+		if self.no_new_privileges == Some(true) {
+			host_config.security_opt = Some(vec!["no-new-privileges".into()]);
+		}
+
+		host_config.tmpfs = self.tmpfs.as_ref().map(|mounts| {
+			mounts
+				.iter()
+				.map(|mount| match mount.split_once(':') {
+					Some((path, options)) => (path.into(), options.into()),
+					None => (mount.clone(), String::new()),
+				})
+				.collect::<HashMap<_, _>>()
+		});
+	}
+}
+
+/// A size in bytes, given either as a number of bytes or with a
+/// (binary) unit suffix, like Docker's `--memory` (e.g. `512m`, `4g`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+		let (number, unit) = s.split_at(split);
+
+		let number: u64 = number.parse().map_err(|_| format!("invalid size: {s}"))?;
+
+		let shift = match unit.to_ascii_lowercase().as_str() {
+			"" | "b" => 0,
+			"k" | "kb" => 10,
+			"m" | "mb" => 20,
+			"g" | "gb" => 30,
+			"t" | "tb" => 40,
+			_ => return Err(format!("invalid size unit (expected b, k, m, g or t): {s}")),
+		};
+
+		number
+			.checked_mul(1 << shift)
+			.map(Self)
+			.ok_or_else(|| format!("size is too large: {s}"))
+	}
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		struct ByteSizeVisitor;
+
+		impl de::Visitor<'_> for ByteSizeVisitor {
+			type Value = ByteSize;
+
+			fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				f.write_str("a number of bytes or a size such as \"4g\"")
+			}
+
+			fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+				Ok(ByteSize(v))
+			}
+
+			fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+				u64::try_from(v)
+					.map(ByteSize)
+					.map_err(|_| E::custom("size must not be negative"))
+			}
+
+			fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+				v.parse().map_err(E::custom)
+			}
+		}
+
+		deserializer.deserialize_any(ByteSizeVisitor)
+	}
+}
+
+/// A whitespace-separated list, as given in an environment variable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct List(pub Vec<String>);
+
+impl FromStr for List {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Self(s.split_whitespace().map(String::from).collect()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_byte_sizes() {
+		assert_eq!("1234".parse(), Ok(ByteSize(1234)));
+		assert_eq!("512m".parse(), Ok(ByteSize(512 << 20)));
+		assert_eq!("4G".parse(), Ok(ByteSize(4 << 30)));
+		assert_eq!("2kb".parse(), Ok(ByteSize(2048)));
+		assert!("4x".parse::<ByteSize>().is_err());
+		assert!("g".parse::<ByteSize>().is_err());
+		assert!("99999999999t".parse::<ByteSize>().is_err());
+	}
+
+	#[test]
+	fn link_limits_override_defaults() {
+		let defaults = ContainerLimits {
+			memory: Some(ByteSize(1 << 30)),
+			pids_limit: Some(512),
+			cap_drop: Some(vec!["ALL".into()]),
+			..Default::default()
+		};
+
+		let limits = ContainerLimits {
+			memory: Some(ByteSize(8 << 30)),
+			network: Some("none".into()),
+			..Default::default()
+		}
+		.or(&defaults);
+
+		assert_eq!(limits.memory, Some(ByteSize(8 << 30)));
+		assert_eq!(limits.pids_limit, Some(512));
+		assert_eq!(limits.cap_drop, Some(vec!["ALL".into()]));
+		assert_eq!(limits.network.as_deref(), Some("none"));
+		assert_eq!(limits.cpus, None);
+	}
+
+	#[test]
+	fn applies_to_host_config() {
+		let limits = ContainerLimits {
+			memory: Some(ByteSize(1 << 30)),
+			cpus: Some(1.5),
+			read_only: Some(true),
+			no_new_privileges: Some(true),
+			tmpfs: Some(vec!["/tmp:rw,size=64m".into(), "/run".into()]),
+			..Default::default()
+		};
+
+		let mut host_config = HostConfig::default();
+		limits.apply(&mut host_config);
+
+		let json = serde_json::to_value(&host_config).unwrap();
+		assert_eq!(json["Memory"], 1 << 30);
+		assert_eq!(json["MemorySwap"], 1 << 30);
+		assert_eq!(json["NanoCpus"], 1_500_000_000i64);
+		assert_eq!(json["ReadonlyRootfs"], true);
+		assert_eq!(
+			json["SecurityOpt"],
+			serde_json::json!(["no-new-privileges"])
+		);
+		assert_eq!(
+			json["Tmpfs"],
+			serde_json::json!({ "/tmp": "rw,size=64m", "/run": "" })
+		);
+		assert_eq!(json["PidsLimit"], serde_json::Value::Null);
+	}
+}
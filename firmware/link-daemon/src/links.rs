@@ -0,0 +1,264 @@
+//! Per-link configuration, loaded from the TOML file given in `LINK_CONFIG`
+//! and keyed by the link's (hex-encoded) UID:
+//!
+//! ```toml
+//! # What to do with links that aren't listed below:
+//! # "default" (use the default profile) or "reject"
+//! unknown-links = "reject"
+//!
+//! # The profile that listed links fall back to, and that
+//! # unknown links get if they aren't rejected
+//! [default]
+//! labels = ["lab-berlin"]
+//!
+//! [links.0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF]
+//! name = "bench-1"
+//! image = "ghcr.io/oro-os/runner:next"
+//! labels = ["uefi"]
+//! exit-timeout = 120
+//! test-timeout = 3600
+//! client-protocol = "json"
+//! maintenance = true
+//!
+//! [links.0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF.container]
+//! memory = "8g"
+//! cpus = 4
+//!
+//! # Rules for the serial output; see the session's expectations
+//! [[links.0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF.expect]]
+//! pattern = "PXE-E\\d+"
+//! action = "power-cycle"
+//! retries = 3
+//!
+//! [links.FEDCBA9876543210FEDCBA9876543210FEDCBA9876543210FEDCBA9876543210]
+//! allow = false
+//! ```
+//!
+//! The file is re-read when the daemon receives `SIGHUP`; the new
+//! configuration applies to sessions started after that.
+use crate::{
+	Error,
+	limits::ContainerLimits,
+	session::{ClientProtocol, ExpectRule},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// What happens to links that aren't listed in the configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UnknownLinks {
+	/// They're given the default profile
+	#[default]
+	Default,
+	/// They're turned away
+	Reject,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct LinksConfig {
+	#[serde(default)]
+	unknown_links: UnknownLinks,
+	#[serde(default)]
+	default: LinkProfile,
+	#[serde(default)]
+	links: HashMap<String, LinkProfile>,
+}
+
+/// The configuration for a single link.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct LinkProfile {
+	/// Whether the link may run sessions at all
+	pub allow: Option<bool>,
+	/// A display name, also used as the runner's name
+	pub name: Option<String>,
+	/// Overrides the runner image (`DOCKER_REF`)
+	pub image: Option<String>,
+	/// Runner labels, in addition to `oro`, `oro-link` and those
+	/// derived from the link's hardware report
+	pub labels: Option<Vec<String>>,
+	/// Overrides how long (in seconds) the runner has to exit after
+	/// the session is finished, before it's stopped
+	pub exit_timeout: Option<u64>,
+	/// Overrides how long (in seconds) the outcome is shown on the link
+	/// (`SESSION_OUTCOME_HOLD`)
+	pub outcome_hold: Option<u64>,
+	/// Overrides how long (in seconds) a session may take
+	/// (`SESSION_TIMEOUT`)
+	pub session_timeout: Option<u64>,
+	/// Overrides how long (in seconds) a single test may take
+	/// (`SESSION_TEST_TIMEOUT`)
+	pub test_timeout: Option<u64>,
+	/// Overrides how long (in seconds) the SUT may go without serial
+	/// output once powered on (`SESSION_SERIAL_TIMEOUT`)
+	pub serial_timeout: Option<u64>,
+	/// Overrides how the runner talks to the daemon over the
+	/// session socket (`CLIENT_PROTOCOL`)
+	pub client_protocol: Option<ClientProtocol>,
+	/// Whether the link is on the bench for maintenance, in which case
+	/// anyone with access to its serial console may type into it
+	/// (rather than only those with `CONSOLE_TOKEN`)
+	pub maintenance: Option<bool>,
+	/// Rules that react to the SUT's serial output, e.g. to power-cycle
+	/// it when it's stuck in a boot loop
+	pub expect: Option<Vec<ExpectRule>>,
+	/// Overrides the daemon-wide container limits
+	#[serde(default)]
+	pub container: ContainerLimits,
+}
+
+impl LinkProfile {
+	/// Fills in anything that isn't set from `defaults`.
+	pub fn or(self, defaults: &Self) -> Self {
+		Self {
+			allow: self.allow.or(defaults.allow),
+			name: self.name.or_else(|| defaults.name.clone()),
+			image: self.image.or_else(|| defaults.image.clone()),
+			labels: self.labels.or_else(|| defaults.labels.clone()),
+			exit_timeout: self.exit_timeout.or(defaults.exit_timeout),
+			outcome_hold: self.outcome_hold.or(defaults.outcome_hold),
+			session_timeout: self.session_timeout.or(defaults.session_timeout),
+			test_timeout: self.test_timeout.or(defaults.test_timeout),
+			serial_timeout: self.serial_timeout.or(defaults.serial_timeout),
+			client_protocol: self.client_protocol.or(defaults.client_protocol),
+			maintenance: self.maintenance.or(defaults.maintenance),
+			expect: self.expect.or_else(|| defaults.expect.clone()),
+			container: self.container.or(&defaults.container),
+		}
+	}
+}
+
+impl LinksConfig {
+	pub async fn load(path: &str) -> Result<Self, Error> {
+		let source = async_std::fs::read_to_string(path).await?;
+		Self::parse(&source)
+	}
+
+	fn parse(source: &str) -> Result<Self, Error> {
+		let config: Self = toml::from_str(source)?;
+
+		Ok(Self {
+			// UIDs are matched case-insensitively.
+			links: config
+				.links
+				.into_iter()
+				.map(|(id, profile)| (id.to_ascii_uppercase(), profile))
+				.collect(),
+			..config
+		})
+	}
+
+	/// Gets the profile for a link, or `None` if the link
+	/// isn't allowed to run sessions.
+	pub fn profile(&self, link_id: &str) -> Option<LinkProfile> {
+		let profile = match self.links.get(link_id) {
+			Some(profile) => profile.clone().or(&self.default),
+			None if self.unknown_links == UnknownLinks::Reject => return None,
+			None => self.default.clone(),
+		};
+
+		(profile.allow != Some(false)).then_some(profile)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::limits::ByteSize;
+
+	#[test]
+	fn parses_link_profiles() {
+		let config = LinksConfig::parse(
+			r#"
+			[default]
+			labels = ["x64"]
+			exit-timeout = 60
+			serial-timeout = 0
+
+			[links.abcd]
+			name = "bench-1"
+			image = "oro/runner:next"
+			client-protocol = "json"
+			exit-timeout = 120
+			maintenance = true
+
+			[links.abcd.container]
+			memory = "8g"
+			cpus = 4
+			cap-drop = ["ALL"]
+
+			[[links.abcd.expect]]
+			pattern = "Boot failed"
+			action = "power-cycle"
+			retries = 3
+
+			[links.EF01]
+			"#,
+		)
+		.unwrap();
+
+		let profile = config.profile("ABCD").unwrap();
+		assert_eq!(profile.name.as_deref(), Some("bench-1"));
+		assert_eq!(profile.image.as_deref(), Some("oro/runner:next"));
+		assert_eq!(profile.client_protocol, Some(ClientProtocol::Json));
+		assert_eq!(profile.labels, Some(vec!["x64".into()]));
+		assert_eq!(profile.exit_timeout, Some(120));
+		assert_eq!(profile.maintenance, Some(true));
+		assert_eq!(profile.container.memory, Some(ByteSize(8 << 30)));
+		assert_eq!(profile.container.cpus, Some(4.0));
+		assert_eq!(profile.container.cap_drop, Some(vec!["ALL".into()]));
+		assert_eq!(profile.expect.as_ref().map(Vec::len), Some(1));
+
+		let profile = config.profile("EF01").unwrap();
+		assert_eq!(profile.name, None);
+		assert_eq!(profile.exit_timeout, Some(60));
+		assert_eq!(profile.serial_timeout, Some(0));
+		assert_eq!(profile.container, Default::default());
+
+		assert_eq!(config.profile("2345"), Some(config.default.clone()));
+	}
+
+	#[test]
+	fn rejects_denied_and_unknown_links() {
+		let config = LinksConfig::parse(
+			r#"
+			[links.ABCD]
+			allow = false
+
+			[links.EF01]
+			"#,
+		)
+		.unwrap();
+
+		assert_eq!(config.profile("ABCD"), None);
+		assert!(config.profile("EF01").is_some());
+		assert!(config.profile("2345").is_some());
+
+		let config = LinksConfig::parse(
+			r#"
+			unknown-links = "reject"
+
+			[default]
+			allow = true
+
+			[links.EF01]
+			"#,
+		)
+		.unwrap();
+
+		assert!(config.profile("EF01").is_some());
+		assert_eq!(config.profile("2345"), None);
+	}
+
+	#[test]
+	fn rejects_unknown_options() {
+		assert!(LinksConfig::parse("[links.ABCD.container]\nmemroy = \"8g\"").is_err());
+		assert!(LinksConfig::parse("unknown-links = \"maybe\"").is_err());
+		assert!(
+			LinksConfig::parse("[[links.ABCD.expect]]\npattern = \"(\"\naction = \"reset\"")
+				.is_err()
+		);
+	}
+}
@@ -1,16 +1,48 @@
-#![feature(never_type, async_closure)]
-
+mod api;
+mod artifacts;
+mod console;
 mod docker;
+mod fleet;
+mod github;
+mod hardware;
+mod image;
+mod limits;
+mod links;
+mod reconcile;
+mod runner;
 mod session;
+mod shutdown;
 
-use self::docker::Docker;
+use self::{
+	docker::Docker,
+	fleet::Fleet,
+	image::{PullPolicy, RunnerImage},
+	limits::{ByteSize, ContainerLimits, List},
+	links::LinksConfig,
+	reconcile::LiveSessions,
+	runner::RunnerBackendKind,
+	session::ClientProtocol,
+	shutdown::Shutdown,
+};
+use async_signal::{Signal, Signals};
 use async_std::{io, net::TcpListener, prelude::*, task};
 use envconfig::Envconfig;
+use futures::{FutureExt, select};
 
 use link_protocol::{Error as ProtoError, channel::RWError};
 use log::{debug, error, info, warn};
+use regex::Regex;
+
+use std::{
+	collections::HashMap,
+	path::Path,
+	str::FromStr,
+	sync::{Arc, Mutex, RwLock},
+	time::{Duration, Instant},
+};
 
-use std::str::FromStr;
+/// How often to check whether all sessions have ended while shutting down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Envconfig, Clone)]
 pub(crate) struct Config {
@@ -18,23 +50,161 @@ pub(crate) struct Config {
 	pub link_server_port: u16,
 	#[envconfig(from = "LINK_SERVER_BIND", default = "0.0.0.0")]
 	pub link_server_bind: String,
+	#[envconfig(from = "API_PORT", default = "1338")]
+	pub api_port: u16,
+	#[envconfig(from = "API_BIND", default = "127.0.0.1")]
+	pub api_bind: String,
+	#[envconfig(from = "CONSOLE", default = "0")]
+	pub console: u8,
+	#[envconfig(from = "CONSOLE_TOKEN")]
+	pub console_token: Option<String>,
+	#[envconfig(from = "ARTIFACTS_DIR")]
+	pub artifacts_dir: Option<String>,
 	#[envconfig(from = "USE_JOURNALD", default = "0")]
 	#[allow(unused)]
 	pub use_journald: u8,
+	#[envconfig(from = "RUNNER_BACKEND", default = "docker")]
+	pub runner_backend: RunnerBackendKind,
+	#[envconfig(from = "RUNNER_COMMAND")]
+	pub runner_command: Option<String>,
+	#[envconfig(from = "RUNNER_LOG_FORWARD")]
+	pub runner_log_forward: Option<Regex>,
+	#[envconfig(from = "CLIENT_PROTOCOL", default = "auto")]
+	pub client_protocol: ClientProtocol,
+	#[envconfig(from = "SESSION_OUTCOME_HOLD", default = "30")]
+	pub session_outcome_hold: u64,
+	#[envconfig(from = "SESSION_CLIENT_TIMEOUT", default = "0")]
+	pub session_client_timeout: u64,
+	#[envconfig(from = "SESSION_BOOT_TIMEOUT", default = "600")]
+	pub session_boot_timeout: u64,
+	#[envconfig(from = "SESSION_FINISH_TIMEOUT", default = "300")]
+	pub session_finish_timeout: u64,
+	#[envconfig(from = "SESSION_TIMEOUT", default = "7200")]
+	pub session_timeout: u64,
+	#[envconfig(from = "SESSION_TEST_TIMEOUT", default = "1800")]
+	pub session_test_timeout: u64,
+	#[envconfig(from = "SESSION_SERIAL_TIMEOUT", default = "600")]
+	pub session_serial_timeout: u64,
+	#[envconfig(from = "RECONCILE_INTERVAL", default = "300")]
+	pub reconcile_interval: u64,
+	#[envconfig(from = "SHUTDOWN_TIMEOUT", default = "60")]
+	pub shutdown_timeout: u64,
 	#[envconfig(from = "DOCKER_HOST")]
-	pub docker_host: String,
+	pub docker_host: Option<String>,
 	#[envconfig(from = "DOCKER_REF")]
-	pub docker_ref: String,
+	pub docker_ref: Option<String>,
+	#[envconfig(from = "DOCKER_PULL_POLICY", default = "missing")]
+	pub docker_pull_policy: PullPolicy,
+	#[envconfig(from = "DOCKER_PULL_INTERVAL", default = "300")]
+	pub docker_pull_interval: u64,
+	#[envconfig(from = "DOCKER_MEMORY")]
+	pub docker_memory: Option<ByteSize>,
+	#[envconfig(from = "DOCKER_CPUS")]
+	pub docker_cpus: Option<f64>,
+	#[envconfig(from = "DOCKER_PIDS_LIMIT")]
+	pub docker_pids_limit: Option<i64>,
+	#[envconfig(from = "DOCKER_READ_ONLY")]
+	pub docker_read_only: Option<bool>,
+	#[envconfig(from = "DOCKER_CAP_DROP")]
+	pub docker_cap_drop: Option<List>,
+	#[envconfig(from = "DOCKER_NO_NEW_PRIVILEGES")]
+	pub docker_no_new_privileges: Option<bool>,
+	#[envconfig(from = "DOCKER_NETWORK")]
+	pub docker_network: Option<String>,
+	#[envconfig(from = "DOCKER_TMPFS")]
+	pub docker_tmpfs: Option<List>,
+	#[envconfig(from = "DOCKER_AUTO_REMOVE")]
+	pub docker_auto_remove: Option<bool>,
+	#[envconfig(from = "LINK_CONFIG")]
+	pub link_config: Option<String>,
 	#[envconfig(from = "GH_ACCESS_TOKEN")]
-	pub gh_access_token: String,
+	pub gh_access_token: Option<String>,
 	#[envconfig(from = "GH_ORGANIZATION")]
-	pub gh_organization: String,
+	pub gh_organization: Option<String>,
+	#[envconfig(from = "GH_API_URL", default = "https://api.github.com")]
+	pub gh_api_url: String,
 	#[envconfig(from = "LEVEL", default = "trace")]
 	pub log_level: String,
 	#[envconfig(from = "VERBOSE", default = "0")]
 	pub verbose: u8,
 }
 
+/// State shared between all sessions.
+pub(crate) struct Daemon {
+	pub config: Config,
+	/// The runner image, if the docker runner backend is used
+	pub runner_image: Option<Arc<RunnerImage>>,
+	/// Runner images given for individual links, by reference
+	link_images: Mutex<HashMap<String, Arc<RunnerImage>>>,
+	/// The daemon-wide runner container limits
+	pub container_limits: ContainerLimits,
+	/// The link configuration; replaced when it's reloaded
+	links: RwLock<Arc<LinksConfig>>,
+	/// The links with a live session
+	pub sessions: Arc<LiveSessions>,
+	/// The connected links
+	pub fleet: Arc<Fleet>,
+	/// Triggered on `SIGINT`/`SIGTERM`
+	pub shutdown: Shutdown,
+}
+
+impl Daemon {
+	/// The current link configuration.
+	pub fn links(&self) -> Arc<LinksConfig> {
+		self.links.read().unwrap().clone()
+	}
+
+	/// Re-reads the link configuration, keeping the current
+	/// configuration if the new one can't be loaded.
+	pub async fn reload_links(&self) {
+		match load_links(&self.config).await {
+			Ok(links) => {
+				*self.links.write().unwrap() = Arc::new(links);
+				info!("reloaded link configuration");
+			}
+			Err(err) => {
+				error!("failed to reload link configuration; keeping the current one: {err}");
+			}
+		}
+	}
+
+	/// The runner image for a session, given the link's
+	/// image override (if any).
+	pub fn image_for(&self, reference: Option<&str>) -> Result<Arc<RunnerImage>, Error> {
+		let default = self
+			.runner_image
+			.clone()
+			.ok_or(Error::MissingConfig("DOCKER_REF"))?;
+
+		let Some(reference) = reference.filter(|r| *r != default.reference()) else {
+			return Ok(default);
+		};
+
+		let mut images = self.link_images.lock().unwrap();
+		if let Some(image) = images.get(reference) {
+			return Ok(image.clone());
+		}
+
+		let image = Arc::new(RunnerImage::with_reference(
+			&self.config,
+			reference.to_string(),
+		)?);
+		images.insert(reference.to_string(), image.clone());
+
+		Ok(image)
+	}
+}
+
+async fn load_links(config: &Config) -> Result<LinksConfig, Error> {
+	match config.link_config.as_deref() {
+		Some(path) => {
+			info!("loading link configuration: {path}");
+			LinksConfig::load(path).await
+		}
+		None => Ok(LinksConfig::default()),
+	}
+}
+
 #[derive(thiserror::Error, Debug)]
 #[allow(clippy::enum_variant_names)]
 pub(crate) enum Error {
@@ -46,10 +216,18 @@ pub(crate) enum Error {
 	RWError(#[from] RWError<ProtoError<io::Error>, ProtoError<io::Error>>),
 	#[error("expected link to send LinkOnline but another packet was sent instead")]
 	NoHelloPacket,
+	#[error("link is not allowed to run sessions: {0}")]
+	LinkRejected(String),
 	#[error("unexpected packet was sent by peer (either link or client connection)")]
 	UnexpectedPacket,
 	#[error("docker request failed: {0}")]
 	Docker(#[from] docker::Error),
+	#[error("github request failed: {0}")]
+	GitHub(#[from] github::Error),
+	#[error("missing required configuration for the selected runner backend: {0}")]
+	MissingConfig(&'static str),
+	#[error("failed to parse link configuration: {0}")]
+	LinkConfig(#[from] toml::de::Error),
 	#[error("failed to receive channel message")]
 	ChannelRecv,
 	#[error("failed to send channel message")]
@@ -71,7 +249,7 @@ impl<T> From<async_std::channel::SendError<T>> for Error {
 }
 
 #[async_std::main]
-async fn main() -> Result<!, Error> {
+async fn main() -> Result<(), Error> {
 	let config = Config::init_from_env().unwrap();
 
 	let log_level = log::LevelFilter::from_str(&config.log_level)
@@ -116,16 +294,95 @@ async fn main() -> Result<!, Error> {
 
 	info!("starting oro-linkd version {}", env!("CARGO_PKG_VERSION"));
 
-	let docker = Docker::new(&config.docker_host).expect("failed to parse DOCKER_HOST uri");
+	info!("using runner backend: {:?}", config.runner_backend);
 
-	debug!(
-		"checking to see if docker ref exists: {}",
-		config.docker_ref
-	);
-	docker
-		.check_image(&config.docker_ref)
-		.await
-		.unwrap_or_else(|err| panic!("failed to check image: {:?}: {}", err, config.docker_ref));
+	let runner_image = if config.runner_backend == RunnerBackendKind::Docker {
+		let runner_image = Arc::new(RunnerImage::new(&config)?);
+
+		debug!(
+			"resolving runner image ({:?} pull policy): {}",
+			config.docker_pull_policy,
+			runner_image.reference()
+		);
+		runner_image.resolve().await?;
+
+		Some(runner_image)
+	} else {
+		None
+	};
+
+	let links = load_links(&config).await?;
+
+	let daemon = Arc::new(Daemon {
+		container_limits: ContainerLimits::from_config(&config),
+		config,
+		runner_image,
+		link_images: Default::default(),
+		links: RwLock::new(Arc::new(links)),
+		sessions: Default::default(),
+		fleet: Default::default(),
+		shutdown: Default::default(),
+	});
+	let config = &daemon.config;
+
+	let mut hangups = Signals::new([Signal::Hup])?;
+	task::spawn({
+		let daemon = daemon.clone();
+		async move {
+			while hangups.next().await.is_some() {
+				info!("received SIGHUP; reloading link configuration");
+				daemon.reload_links().await;
+			}
+		}
+	});
+
+	// Clean up after any previous instance of the daemon before
+	// accepting links, then keep doing so periodically.
+	let docker = match (&config.runner_backend, config.docker_host.as_deref()) {
+		(RunnerBackendKind::Docker, Some(docker_host)) => Some(Docker::new(docker_host)?),
+		_ => None,
+	};
+
+	let socket_dir = Path::new(self::session::SOCKET_DIR);
+	let artifacts_dir = config.artifacts_dir.as_deref().map(Path::new);
+	if let Err(err) =
+		reconcile::reconcile(docker.as_ref(), &daemon.sessions, socket_dir, artifacts_dir).await
+	{
+		error!("failed to reconcile runner containers and session sockets: {err}");
+	}
+
+	if config.reconcile_interval > 0 {
+		task::spawn(reconcile::run_reconciler(
+			docker.clone(),
+			daemon.sessions.clone(),
+			socket_dir,
+			artifacts_dir.map(Path::to_path_buf),
+			Duration::from_secs(config.reconcile_interval),
+		));
+	}
+
+	if config.api_port != 0 {
+		let mut api = self::api::server(daemon.fleet.clone());
+		if config.console != 0 {
+			self::console::route(&mut api, config.console_token.clone());
+			info!(
+				"serving serial consoles on http://{}:{}/links/<uid>/console",
+				config.api_bind, config.api_port
+			);
+		}
+		let addr = (config.api_bind.clone(), config.api_port);
+
+		info!(
+			"serving maintenance API on http://{}:{}",
+			config.api_bind, config.api_port
+		);
+
+		task::spawn(async move {
+			if let Err(err) = api.listen(addr).await {
+				error!("maintenance API server failed: {err}");
+			}
+		});
+	}
 
 	let listener =
 		TcpListener::bind((config.link_server_bind.as_str(), config.link_server_port)).await?;
@@ -136,12 +393,37 @@ async fn main() -> Result<!, Error> {
 		config.link_server_bind, config.link_server_port
 	);
 
-	while let Some(stream) = incoming.next().await {
-		let stream = stream?;
-		let config = config.clone();
+	let mut terminations = Signals::new([Signal::Int, Signal::Term])?;
+	task::spawn({
+		let shutdown = daemon.shutdown.clone();
+		async move {
+			if let Some(signal) = terminations.next().await {
+				info!("received {signal:?}; shutting down");
+				shutdown.trigger();
+			}
+		}
+	});
+
+	// Held by every link connection, so that shutting down also waits for
+	// those that haven't registered a session (yet), e.g. ones still
+	// negotiating or being rejected.
+	let connections = Arc::new(());
+
+	loop {
+		let stream = select! {
+			stream = incoming.next().fuse() => match stream {
+				Some(stream) => stream?,
+				None => break,
+			},
+			() = daemon.shutdown.triggered().fuse() => break,
+		};
+
+		let daemon = daemon.clone();
+		let connection = connections.clone();
 
 		task::spawn(async move {
-			if let Err(err) = self::session::run_session(config, stream).await {
+			let _connection = connection;
+			if let Err(err) = self::session::run_session(daemon, stream).await {
 				error!("oro link peer connection encountered error: {:?}", err);
 			} else {
 				warn!("oro link peer connection ended with OK result");
@@ -149,6 +431,37 @@ async fn main() -> Result<!, Error> {
 		});
 	}
 
-	async_io::Timer::never().await;
-	unreachable!();
+	// Stop accepting links; the sessions are already powering
+	// their SUTs off and stopping their runners.
+	drop(incoming);
+	drop(listener);
+
+	let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+	info!("waiting up to {shutdown_timeout:?} for sessions to end");
+
+	let deadline = Instant::now() + shutdown_timeout;
+	while Arc::strong_count(&connections) > 1 || !daemon.sessions.is_empty() {
+		if Instant::now() >= deadline {
+			warn!("sessions did not end in time; cleaning up after them");
+			break;
+		}
+
+		task::sleep(SHUTDOWN_POLL_INTERVAL).await;
+	}
+
+	// With no live sessions, this removes every runner container and
+	// session socket, including those of sessions that didn't end in time.
+	if let Err(err) = reconcile::reconcile(
+		docker.as_ref(),
+		&LiveSessions::default(),
+		socket_dir,
+		artifacts_dir,
+	)
+	.await
+	{
+		error!("failed to clean up runner containers and session sockets: {err}");
+	}
+
+	info!("shut down");
+	Ok(())
 }
@@ -0,0 +1,295 @@
+//! Cleans up runner containers, session sockets and staged session
+//! artifacts that aren't owned by a live session, e.g. those left behind
+//! after the daemon crashed or for links that never reconnected.
+use crate::{Error, artifacts, docker::Docker};
+use async_std::{fs, stream::StreamExt};
+use log::{debug, error, info, warn};
+use std::{
+	collections::HashMap,
+	io,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+/// The link IDs of all live sessions.
+#[derive(Debug, Default)]
+pub(crate) struct LiveSessions(Mutex<HashMap<String, usize>>);
+
+impl LiveSessions {
+	/// Marks a session for the link as live until the returned
+	/// guard is dropped.
+	pub fn register(self: &Arc<Self>, link_id: &str) -> LiveSession {
+		*self
+			.0
+			.lock()
+			.unwrap()
+			.entry(link_id.to_string())
+			.or_default() += 1;
+
+		LiveSession {
+			sessions: self.clone(),
+			link_id: link_id.to_string(),
+		}
+	}
+
+	pub fn is_live(&self, link_id: &str) -> bool {
+		self.0.lock().unwrap().contains_key(link_id)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.lock().unwrap().is_empty()
+	}
+}
+
+/// Keeps a session's resources from being reconciled away.
+pub(crate) struct LiveSession {
+	sessions: Arc<LiveSessions>,
+	link_id: String,
+}
+
+impl Drop for LiveSession {
+	fn drop(&mut self) {
+		let mut sessions = self.sessions.0.lock().unwrap();
+		if let Some(count) = sessions.get_mut(&self.link_id) {
+			*count -= 1;
+			if *count == 0 {
+				sessions.remove(&self.link_id);
+			}
+		}
+	}
+}
+
+/// Removes every `sh.oro=link` container (if `docker` is given) and every
+/// `link-*.sock` socket in `socket_dir` that belongs to a link without a
+/// live session. Such links' staging directories in `artifacts_dir` (if
+/// given) are kept as sessions without a ref ID (see [`crate::artifacts`]).
+pub(crate) async fn reconcile(
+	docker: Option<&Docker>,
+	sessions: &LiveSessions,
+	socket_dir: &Path,
+	artifacts_dir: Option<&Path>,
+) -> Result<(), Error> {
+	if let Some(docker) = docker {
+		// List before checking liveness; a session is always registered
+		// before it creates its container.
+		let containers = docker
+			.list_containers(Some(vec![("sh.oro".into(), "link".into())]))
+			.await?;
+
+		for container in containers {
+			let link_id = container.labels.get("sh.oro.link").map(String::as_str);
+
+			if link_id.is_some_and(|id| sessions.is_live(id)) {
+				continue;
+			}
+
+			info!(
+				"removing orphaned runner container {} ({}) for link {}",
+				container.id,
+				container.state,
+				link_id.unwrap_or("<unknown>")
+			);
+			if let Err(err) = docker.remove_container(&container.id, true).await {
+				warn!("failed to remove orphaned runner container: {err}");
+			}
+		}
+	}
+
+	let mut entries = fs::read_dir(socket_dir).await?;
+	while let Some(entry) = entries.next().await {
+		let entry = entry?;
+		let name = entry.file_name();
+		let Some(link_id) = name
+			.to_str()
+			.and_then(|name| name.strip_prefix("link-"))
+			.and_then(|name| name.strip_suffix(".sock"))
+			// link IDs are hex-encoded UIDs; leave anything else alone
+			.filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric()))
+		else {
+			continue;
+		};
+
+		if sessions.is_live(link_id) {
+			continue;
+		}
+
+		let path = entry.path();
+		info!("removing orphaned session socket: {}", path.display());
+		if let Err(err) = fs::remove_file(&path).await {
+			warn!("failed to remove orphaned session socket: {err}");
+		}
+	}
+
+	if let Some(artifacts_dir) = artifacts_dir {
+		unstage_artifacts(artifacts_dir, sessions).await?;
+	}
+
+	Ok(())
+}
+
+/// Moves the staging directories of links without a live session to
+/// where they'd have ended up had their session ended.
+async fn unstage_artifacts(artifacts_dir: &Path, sessions: &LiveSessions) -> Result<(), Error> {
+	let mut links = match fs::read_dir(artifacts_dir).await {
+		Ok(links) => links,
+		// No session has kept anything yet.
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(err) => return Err(err.into()),
+	};
+
+	while let Some(link) = links.next().await {
+		let link = link?;
+		let name = link.file_name();
+		let Some(link_id) = name.to_str() else {
+			continue;
+		};
+
+		if sessions.is_live(link_id) || !link.file_type().await?.is_dir() {
+			continue;
+		}
+
+		let mut entries = fs::read_dir(link.path()).await?;
+		while let Some(entry) = entries.next().await {
+			let entry = entry?;
+			let Some(dir) = entry
+				.file_name()
+				.to_str()
+				.and_then(|name| artifacts::unstaged_dir(artifacts_dir, link_id, name))
+			else {
+				continue;
+			};
+
+			let path = entry.path();
+			info!(
+				"keeping orphaned session artifacts: {} -> {}",
+				path.display(),
+				dir.display()
+			);
+			if let Some(parent) = dir.parent() {
+				fs::create_dir_all(parent).await?;
+			}
+			if let Err(err) = fs::rename(&path, &dir).await {
+				warn!("failed to keep orphaned session artifacts: {err}");
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Reconciles every `interval`, forever.
+pub(crate) async fn run_reconciler(
+	docker: Option<Docker>,
+	sessions: Arc<LiveSessions>,
+	socket_dir: &Path,
+	artifacts_dir: Option<PathBuf>,
+	interval: Duration,
+) -> ! {
+	loop {
+		async_std::task::sleep(interval).await;
+
+		debug!("reconciling runner containers and session sockets");
+		if let Err(err) = reconcile(
+			docker.as_ref(),
+			&sessions,
+			socket_dir,
+			artifacts_dir.as_deref(),
+		)
+		.await
+		{
+			error!("failed to reconcile runner containers and session sockets: {err}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		artifacts::tests::temp_root,
+		docker::{CreateContainer, Map, tests::stand_in},
+	};
+
+	#[async_std::test]
+	async fn removes_orphaned_containers_and_sockets() {
+		let (state, docker) = stand_in().await;
+
+		for (link_id, oro) in [("LIVE", "link"), ("DEAD", "link"), ("OTHER", "other")] {
+			docker
+				.create_container(&CreateContainer {
+					image: "oro/runner:latest".into(),
+					labels: Some(
+						Map::new()
+							.add("sh.oro".into(), oro.into())
+							.add("sh.oro.link".into(), link_id.into()),
+					),
+					..Default::default()
+				})
+				.await
+				.unwrap();
+		}
+
+		let socket_dir =
+			std::env::temp_dir().join(format!("link-daemon-reconcile-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&socket_dir);
+		std::fs::create_dir_all(&socket_dir).unwrap();
+		for name in [
+			"link-LIVE.sock",
+			"link-DEAD.sock",
+			"link-daemon-docker.sock",
+			"unrelated.sock",
+		] {
+			std::fs::write(socket_dir.join(name), b"").unwrap();
+		}
+
+		let artifacts_dir = temp_root("reconcile");
+		for link_id in ["LIVE", "DEAD"] {
+			let staging = artifacts_dir.join(link_id).join(".staging-1700000000123");
+			std::fs::create_dir_all(&staging).unwrap();
+			std::fs::write(staging.join("serial.raw"), b"login: ").unwrap();
+		}
+
+		let sessions = Arc::new(LiveSessions::default());
+		let live = sessions.register("LIVE");
+
+		reconcile(Some(&docker), &sessions, &socket_dir, Some(&artifacts_dir))
+			.await
+			.unwrap();
+
+		let mut remaining = state
+			.lock()
+			.await
+			.containers
+			.values()
+			.map(|c| c.labels["sh.oro.link"].clone())
+			.collect::<Vec<_>>();
+		remaining.sort();
+		assert_eq!(remaining, vec!["LIVE", "OTHER"]);
+
+		assert!(socket_dir.join("link-LIVE.sock").exists());
+		assert!(!socket_dir.join("link-DEAD.sock").exists());
+		assert!(socket_dir.join("link-daemon-docker.sock").exists());
+		assert!(socket_dir.join("unrelated.sock").exists());
+
+		assert!(artifacts_dir.join("LIVE/.staging-1700000000123").exists());
+		assert!(!artifacts_dir.join("DEAD/.staging-1700000000123").exists());
+		assert_eq!(
+			std::fs::read(artifacts_dir.join("DEAD/_/1700000000123/serial.raw")).unwrap(),
+			b"login: "
+		);
+
+		drop(live);
+		assert!(!sessions.is_live("LIVE"));
+
+		reconcile(Some(&docker), &sessions, &socket_dir, Some(&artifacts_dir))
+			.await
+			.unwrap();
+		assert_eq!(state.lock().await.containers.len(), 1);
+		assert!(!socket_dir.join("link-LIVE.sock").exists());
+		assert!(artifacts_dir.join("LIVE/_/1700000000123").exists());
+
+		std::fs::remove_dir_all(&socket_dir).unwrap();
+		std::fs::remove_dir_all(&artifacts_dir).unwrap();
+	}
+}
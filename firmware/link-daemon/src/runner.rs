@@ -0,0 +1,161 @@
+//! Runner backends, which host whatever drives a link session
+//! (e.g. a GitHub Actions runner) and give it access to the
+//! session's client socket.
+
+mod docker;
+mod noop;
+mod process;
+
+pub(crate) use self::{docker::DockerRunner, noop::NoopRunner, process::ProcessRunner};
+use crate::{
+	Error, fleet::FleetEntry, hardware::HardwareInfo, links::LinkProfile, session::LinkLog,
+};
+use link_protocol::SessionOutcome;
+use std::{future::Future, str::FromStr, time::Duration};
+
+/// Describes the session a runner is being started for.
+#[derive(Debug, Clone)]
+pub(crate) struct RunnerSession {
+	/// The (hex-encoded) UID of the link
+	pub link_id: String,
+	/// The path to the session's client socket on the host
+	pub socket_path: String,
+	/// The link's log scene
+	pub link_log: LinkLog,
+	/// The link's entry in the fleet registry
+	pub fleet: FleetEntry,
+	/// The link's configuration
+	pub profile: LinkProfile,
+	/// The link's self-reported hardware, if it sent a report
+	pub hardware: Option<HardwareInfo>,
+}
+
+/// A runner backend, driven through its lifecycle by the session:
+///
+/// `prepare` → `start` → `wait` (→ `stop`) → `cleanup`
+///
+/// `cleanup` is always called once `prepare` has been, even if
+/// any of the other steps failed.
+pub(crate) trait RunnerBackend: Send {
+	/// Prepares (but does not start) the runner for the given session.
+	fn prepare(
+		&mut self,
+		session: &RunnerSession,
+	) -> impl Future<Output = Result<(), Error>> + Send;
+
+	/// Starts the runner.
+	fn start(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+	/// Waits for the runner to exit on its own, returning how it exited.
+	/// Must be cancel-safe, as it is raced against the session finishing.
+	fn wait(&mut self) -> impl Future<Output = Result<RunnerExit, Error>> + Send;
+
+	/// Forcibly stops the runner.
+	fn stop(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+	/// Releases anything left over by the runner.
+	fn cleanup(&mut self) -> impl Future<Output = Result<(), Error>> + Send;
+
+	/// How long to wait for the runner to exit on its own after the client
+	/// has indicated the session is finished, after which it's stopped.
+	///
+	/// `None` if there's no runner to wait for, in which case the outcome
+	/// follows from what the client reported (see [`RunnerExit::Unknown`]).
+	fn exit_timeout(&self) -> Option<Duration> {
+		Some(Duration::from_secs(60))
+	}
+}
+
+/// How a runner exited on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunnerExit {
+	/// It exited with the given code
+	Code(i64),
+	/// It was killed by a signal
+	Signal,
+	/// It's gone, but how it exited isn't known (e.g. a Docker container
+	/// that was removed before it could be waited on). The session's
+	/// outcome then follows from what the client reported.
+	Unknown,
+}
+
+/// Determines the session's outcome from how a runner exited on its
+/// own; `None` if that doesn't tell.
+pub(crate) fn outcome_for_exit(exit: RunnerExit) -> Option<SessionOutcome> {
+	match exit {
+		RunnerExit::Code(0) => Some(SessionOutcome::Passed),
+		// Shells (and Docker) report death-by-signal as 128 + the signal.
+		RunnerExit::Signal | RunnerExit::Code(129..=192) => Some(SessionOutcome::Killed),
+		RunnerExit::Code(_) => Some(SessionOutcome::Failed),
+		RunnerExit::Unknown => None,
+	}
+}
+
+/// Which runner backend the daemon uses for sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunnerBackendKind {
+	/// Runs a GitHub Actions runner in a Docker container
+	Docker,
+	/// Runs a local command (`RUNNER_COMMAND`)
+	Process,
+	/// Runs nothing; the session socket is left for someone
+	/// to connect to manually.
+	None,
+}
+
+impl FromStr for RunnerBackendKind {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"docker" => Ok(Self::Docker),
+			"process" => Ok(Self::Process),
+			"none" => Ok(Self::None),
+			unknown => Err(format!(
+				"unknown runner backend (expected docker, process or none): {unknown}"
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::{
+		fleet::Fleet,
+		session::{LinkControl, LinkLog},
+	};
+	use std::sync::Arc;
+
+	/// A session for link `ABCD`, registered with a fleet of its own.
+	pub(crate) fn session(profile: LinkProfile) -> RunnerSession {
+		let fleet = Arc::new(Fleet::default());
+		let (control, _) = LinkControl::stand_in();
+
+		RunnerSession {
+			link_id: "ABCD".into(),
+			socket_path: "/tmp/oro-link-ABCD.sock".into(),
+			link_log: LinkLog::stand_in(),
+			fleet: fleet.register("ABCD", "0.1.0", "10.0.0.2:4242".parse().unwrap(), control),
+			profile,
+			hardware: None,
+		}
+	}
+
+	#[test]
+	fn maps_exits_to_outcomes() {
+		for (exit, outcome) in [
+			(RunnerExit::Code(0), Some(SessionOutcome::Passed)),
+			(RunnerExit::Code(1), Some(SessionOutcome::Failed)),
+			(RunnerExit::Code(128), Some(SessionOutcome::Failed)),
+			// SIGKILL, as reported by a shell
+			(RunnerExit::Code(137), Some(SessionOutcome::Killed)),
+			(RunnerExit::Code(193), Some(SessionOutcome::Failed)),
+			(RunnerExit::Code(-1), Some(SessionOutcome::Failed)),
+			(RunnerExit::Signal, Some(SessionOutcome::Killed)),
+			(RunnerExit::Unknown, None),
+		] {
+			assert_eq!(outcome_for_exit(exit), outcome, "{exit:?}");
+		}
+	}
+}
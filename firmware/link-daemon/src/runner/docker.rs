@@ -0,0 +1,351 @@
+use super::{RunnerBackend, RunnerExit, RunnerSession};
+use crate::{
+	Config, Error,
+	docker::{self, Args, Binds, CreateContainer, Docker, HostConfig, LogStream, Map},
+	github::GitHub,
+	image::RunnerImage,
+	limits::ContainerLimits,
+	session::LinkLog,
+};
+use async_std::task::{self, JoinHandle};
+use link_protocol::LogEntry;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use std::sync::Arc;
+
+/// Labels given to every runner.
+const BASE_LABELS: &[&str] = &["self-hosted", "oro", "oro-link"];
+
+/// Labels given to runners whose link neither reports its
+/// hardware nor configures any labels.
+const DEFAULT_LABELS: &[&str] = &["x64"];
+
+/// Runs a GitHub Actions runner in a Docker container, with the
+/// session socket bound to `/oro-link.sock`.
+///
+/// The organization access token never enters the container; a
+/// single-use registration token is requested for each session instead.
+///
+/// The container's output is logged by the daemon; lines matching
+/// `RUNNER_LOG_FORWARD` are also shown on the link's log scene.
+pub(crate) struct DockerRunner {
+	docker: Docker,
+	image: Arc<RunnerImage>,
+	github: GitHub,
+	gh_organization: String,
+	log_forward: Option<Regex>,
+	limits: ContainerLimits,
+	session: Option<RunnerSession>,
+	id: Option<String>,
+	logs: Option<JoinHandle<()>>,
+}
+
+impl DockerRunner {
+	pub fn new(
+		config: &Config,
+		image: Arc<RunnerImage>,
+		limits: ContainerLimits,
+	) -> Result<Self, Error> {
+		Ok(Self {
+			docker: Docker::new(
+				config
+					.docker_host
+					.as_deref()
+					.ok_or(Error::MissingConfig("DOCKER_HOST"))?,
+			)?,
+			image,
+			github: GitHub::new(
+				&config.gh_api_url,
+				config
+					.gh_access_token
+					.clone()
+					.ok_or(Error::MissingConfig("GH_ACCESS_TOKEN"))?,
+			)?,
+			gh_organization: config
+				.gh_organization
+				.clone()
+				.ok_or(Error::MissingConfig("GH_ORGANIZATION"))?,
+			log_forward: config.runner_log_forward.clone(),
+			limits,
+			session: None,
+			id: None,
+			logs: None,
+		})
+	}
+
+	fn id(&self) -> &str {
+		self.id
+			.as_deref()
+			.expect("docker runner used before being prepared")
+	}
+}
+
+impl RunnerBackend for DockerRunner {
+	async fn prepare(&mut self, session: &RunnerSession) -> Result<(), Error> {
+		let link_id = &session.link_id;
+
+		debug!("pruning all containers for this link: {link_id}");
+		let containers = self
+			.docker
+			.list_containers(Some(vec![("sh.oro.link".into(), link_id.clone())]))
+			.await?;
+		debug!("pruning {} containers:", containers.len());
+		for container in containers {
+			debug!("    - {} ({})", container.id, container.state);
+			self.docker.remove_container(&container.id, true).await?;
+		}
+
+		// Pin the exact image this session runs, even if the
+		// reference is updated while it's running.
+		let image = self.image.resolve().await?;
+		info!(
+			"using image {} for link {link_id}: {} ({})",
+			self.image.reference(),
+			image.id,
+			image.digest.as_deref().unwrap_or("no digest")
+		);
+
+		debug!("requesting runner registration token for link {link_id}");
+		let registration = self
+			.github
+			.registration_token(&self.gh_organization)
+			.await?;
+		debug!(
+			"got runner registration token for link {link_id} (expires {})",
+			registration.expires_at
+		);
+
+		let hardware_labels = match (&session.hardware, &session.profile.labels) {
+			(Some(hardware), _) => hardware.labels(),
+			(None, Some(_)) => vec![],
+			(None, None) => DEFAULT_LABELS.iter().map(|l| l.to_string()).collect(),
+		};
+		let labels = BASE_LABELS
+			.iter()
+			.map(|l| l.to_string())
+			.chain(hardware_labels)
+			.chain(session.profile.labels.iter().flatten().cloned())
+			.collect::<Vec<_>>()
+			.join(",");
+		let name = session.profile.name.as_ref().unwrap_or(link_id);
+		debug!("registering runner for link {link_id} as {name} with labels: {labels}");
+
+		let mut host_config = HostConfig {
+			binds: Some(Binds(vec![(
+				session.socket_path.clone(),
+				"/oro-link.sock".into(),
+				Some("rw".into()),
+			)])),
+			..Default::default()
+		};
+		self.limits.apply(&mut host_config);
+		debug!("container limits for link {link_id}: {:?}", self.limits);
+
+		let id = self
+			.docker
+			.create_container(&CreateContainer {
+				image: image.id.clone(),
+				labels: Some(
+					Map::new()
+						.add("sh.oro".into(), "link".into())
+						.add("sh.oro.link".into(), link_id.clone())
+						.add(
+							"sh.oro.image".into(),
+							image
+								.digest
+								.unwrap_or_else(|| self.image.reference().into()),
+						),
+				),
+				env: Some(
+					Args::new()
+						.add("REG_TOKEN".into(), registration.token)
+						.add("ORGANIZATION".into(), self.gh_organization.clone())
+						.add("LABELS".into(), labels)
+						.add("NAME".into(), name.clone()),
+				),
+				host_config: Some(host_config),
+				..Default::default()
+			})
+			.await?;
+
+		debug!("created actions runner container: {id}");
+		session.fleet.set_container(Some(id.clone()));
+		self.id = Some(id);
+		self.session = Some(session.clone());
+
+		Ok(())
+	}
+
+	async fn start(&mut self) -> Result<(), Error> {
+		let id = self.id();
+		debug!("starting the container: {id}");
+		self.docker.start_container(id).await?;
+
+		let session = self
+			.session
+			.clone()
+			.expect("docker runner used before being prepared");
+		self.logs = Some(task::spawn(forward_logs(
+			self.docker.clone(),
+			id.to_string(),
+			session,
+			self.log_forward.clone(),
+		)));
+
+		Ok(())
+	}
+
+	async fn wait(&mut self) -> Result<RunnerExit, Error> {
+		let id = self.id();
+		debug!("container started; waiting for exit: {id}");
+		match self.docker.wait_for_container(id).await {
+			Ok(code) => {
+				warn!("actions runner container exited with code {code}");
+				Ok(RunnerExit::Code(code))
+			}
+			Err(docker::Error::HttpStatus(surf::StatusCode::NotFound)) => {
+				// With `auto-remove`, the container may be gone before
+				// we get a chance to ask how it exited.
+				warn!("actions runner container exited and was removed: {id}");
+				Ok(RunnerExit::Unknown)
+			}
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	async fn stop(&mut self) -> Result<(), Error> {
+		let id = self.id();
+		warn!("killing container: {id}");
+		self.docker.kill_container(id).await?;
+		Ok(())
+	}
+
+	async fn cleanup(&mut self) -> Result<(), Error> {
+		if let Some(logs) = self.logs.take() {
+			logs.cancel().await;
+		}
+
+		if let Some(id) = self.id.as_deref() {
+			match self.docker.remove_container(id, true).await {
+				Ok(()) => info!("container removed: {id}"),
+				Err(docker::Error::HttpStatus(surf::StatusCode::NotFound)) => {
+					debug!("container was already removed: {id}");
+				}
+				Err(err) => return Err(err.into()),
+			}
+			self.id = None;
+
+			if let Some(session) = &self.session {
+				session.fleet.set_container(None);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Logs the container's output until it exits, forwarding the lines
+/// selected by `forward` to the link.
+///
+/// If `forward` has a capture group, only the first group is forwarded
+/// (e.g. `^::oro::(.*)` forwards `::oro::hello` as `hello`).
+async fn forward_logs(docker: Docker, id: String, session: RunnerSession, forward: Option<Regex>) {
+	let link_id = &session.link_id;
+
+	let result = docker
+		.follow_logs(&id, |stream, line| {
+			info!("[{link_id}] runner {stream}: {line}");
+
+			let Some(captures) = forward.as_ref().and_then(|re| re.captures(line)) else {
+				return;
+			};
+
+			let message = captures.get(1).unwrap_or_else(|| captures.get(0).unwrap());
+			let message = LinkLog::truncate(message.as_str());
+			let message = message.try_into().unwrap();
+
+			session.link_log.send(match stream {
+				LogStream::Stdout => LogEntry::Info(message),
+				LogStream::Stderr => LogEntry::Warn(message),
+			});
+		})
+		.await;
+
+	match result {
+		Ok(()) => debug!("[{link_id}] runner container output ended: {id}"),
+		Err(err) => warn!("[{link_id}] failed to follow runner container output: {err}"),
+	}
+}
+
+impl Drop for DockerRunner {
+	fn drop(&mut self) {
+		if let Some(id) = self.id.take() {
+			let docker = self.docker.clone();
+			debug!("dropping docker runner; killing container: {id}");
+			task::spawn(async move {
+				if let Err(err) = docker.remove_container(&id, true).await {
+					error!("failed to kill docker container: {:?}", err);
+				}
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::docker::tests::stand_in;
+	use envconfig::Envconfig;
+	use std::collections::HashMap;
+
+	async fn runner() -> (
+		Arc<async_std::sync::Mutex<docker::tests::StandIn>>,
+		DockerRunner,
+	) {
+		let config = Config::init_from_hashmap(&HashMap::from([
+			("DOCKER_HOST".into(), "unix:///var/run/docker.sock".into()),
+			("DOCKER_REF".into(), "oro/runner:latest".into()),
+			("GH_ACCESS_TOKEN".into(), "ghp_test".into()),
+			("GH_ORGANIZATION".into(), "oro-os".into()),
+		]))
+		.unwrap();
+
+		let (state, docker) = stand_in().await;
+		let image = Arc::new(RunnerImage::new(&config).unwrap());
+		let mut runner = DockerRunner::new(&config, image, ContainerLimits::default()).unwrap();
+		runner.docker = docker;
+
+		(state, runner)
+	}
+
+	#[async_std::test]
+	async fn reports_exit_codes() {
+		let (_, mut runner) = runner().await;
+
+		let id = runner
+			.docker
+			.create_container(&CreateContainer {
+				image: "oro/runner:latest".into(),
+				..Default::default()
+			})
+			.await
+			.unwrap();
+		runner.docker.start_container(&id).await.unwrap();
+		runner.id = Some(id);
+
+		assert_eq!(runner.wait().await.unwrap(), RunnerExit::Code(3));
+	}
+
+	#[async_std::test]
+	async fn exit_of_removed_container_is_unknown() {
+		let (state, mut runner) = runner().await;
+
+		// With `auto-remove`, the container is gone by the time it's waited on.
+		runner.id = Some("c404".into());
+		assert!(!state.lock().await.containers.contains_key("c404"));
+
+		assert_eq!(runner.wait().await.unwrap(), RunnerExit::Unknown);
+		// Nothing's left to remove.
+		runner.cleanup().await.unwrap();
+	}
+}
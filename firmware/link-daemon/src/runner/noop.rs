@@ -0,0 +1,41 @@
+use super::{RunnerBackend, RunnerExit, RunnerSession};
+use crate::Error;
+use log::info;
+use std::time::Duration;
+
+/// Runs nothing. The session socket is left open for someone to
+/// connect to by hand (e.g. during bench maintenance or development),
+/// and the session ends when they signal that it's finished.
+pub(crate) struct NoopRunner;
+
+impl RunnerBackend for NoopRunner {
+	async fn prepare(&mut self, session: &RunnerSession) -> Result<(), Error> {
+		info!(
+			"manual session for link {}; connect to {}",
+			session.link_id, session.socket_path
+		);
+		Ok(())
+	}
+
+	async fn start(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	async fn wait(&mut self) -> Result<RunnerExit, Error> {
+		async_std::future::pending().await
+	}
+
+	async fn stop(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	async fn cleanup(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn exit_timeout(&self) -> Option<Duration> {
+		// There's nothing to wait for; the session's outcome is
+		// whatever the client reported.
+		None
+	}
+}
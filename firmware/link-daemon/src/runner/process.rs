@@ -0,0 +1,124 @@
+use super::{RunnerBackend, RunnerExit, RunnerSession};
+use crate::{Config, Error};
+use async_process::{Child, Command};
+use log::{debug, info, warn};
+
+/// Runs a local command (via `sh -c`) for the session, passing it the
+/// session socket path in `ORO_LINK_SOCKET` and the link's UID
+/// in `ORO_LINK_ID`.
+pub(crate) struct ProcessRunner {
+	command: String,
+	prepared: Option<Command>,
+	child: Option<Child>,
+}
+
+impl ProcessRunner {
+	pub fn new(config: &Config) -> Result<Self, Error> {
+		Ok(Self {
+			command: config
+				.runner_command
+				.clone()
+				.ok_or(Error::MissingConfig("RUNNER_COMMAND"))?,
+			prepared: None,
+			child: None,
+		})
+	}
+
+	fn child(&mut self) -> &mut Child {
+		self.child
+			.as_mut()
+			.expect("process runner used before being started")
+	}
+}
+
+impl RunnerBackend for ProcessRunner {
+	async fn prepare(&mut self, session: &RunnerSession) -> Result<(), Error> {
+		let mut command = Command::new("sh");
+		command
+			.arg("-c")
+			.arg(&self.command)
+			.env("ORO_LINK_SOCKET", &session.socket_path)
+			.env("ORO_LINK_ID", &session.link_id)
+			.kill_on_drop(true);
+
+		self.prepared = Some(command);
+
+		Ok(())
+	}
+
+	async fn start(&mut self) -> Result<(), Error> {
+		let mut command = self
+			.prepared
+			.take()
+			.expect("process runner started before being prepared");
+
+		debug!("starting runner process: {}", self.command);
+		let child = command.spawn()?;
+		info!("started runner process: pid {}", child.id());
+		self.child = Some(child);
+
+		Ok(())
+	}
+
+	async fn wait(&mut self) -> Result<RunnerExit, Error> {
+		let status = self.child().status().await?;
+		warn!("runner process exited: {status}");
+		Ok(status
+			.code()
+			.map_or(RunnerExit::Signal, |code| RunnerExit::Code(code.into())))
+	}
+
+	async fn stop(&mut self) -> Result<(), Error> {
+		let child = self.child();
+		warn!("killing runner process: pid {}", child.id());
+		child.kill()?;
+		Ok(())
+	}
+
+	async fn cleanup(&mut self) -> Result<(), Error> {
+		// `kill_on_drop` takes care of anything still running.
+		self.prepared = None;
+		self.child = None;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::runner::tests::session;
+	use envconfig::Envconfig;
+	use std::collections::HashMap;
+
+	async fn start(command: &str) -> ProcessRunner {
+		let config =
+			Config::init_from_hashmap(&HashMap::from([("RUNNER_COMMAND".into(), command.into())]))
+				.unwrap();
+		let mut runner = ProcessRunner::new(&config).unwrap();
+		runner.prepare(&session(Default::default())).await.unwrap();
+		runner.start().await.unwrap();
+		runner
+	}
+
+	#[async_std::test]
+	async fn reports_how_the_process_exited() {
+		for (command, exit) in [
+			("exit 0", RunnerExit::Code(0)),
+			("exit 3", RunnerExit::Code(3)),
+			("test \"$ORO_LINK_ID\" = ABCD", RunnerExit::Code(0)),
+			("kill -KILL $$", RunnerExit::Signal),
+		] {
+			let mut runner = start(command).await;
+			assert_eq!(runner.wait().await.unwrap(), exit, "{command}");
+			runner.cleanup().await.unwrap();
+		}
+	}
+
+	#[async_std::test]
+	async fn stops_the_process() {
+		let mut runner = start("sleep 30").await;
+		runner.stop().await.unwrap();
+		assert_eq!(runner.wait().await.unwrap(), RunnerExit::Signal);
+		runner.cleanup().await.unwrap();
+	}
+}
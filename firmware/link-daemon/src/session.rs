@@ -1,17 +1,51 @@
-use crate::{Config, Error, docker::Docker};
+use crate::{
+	Daemon, Error,
+	artifacts::SessionArtifacts,
+	fleet::{Fleet, FleetEntry},
+	hardware::HardwareInfo,
+	runner::{
+		DockerRunner, NoopRunner, ProcessRunner, RunnerBackend, RunnerBackendKind, RunnerSession,
+		outcome_for_exit,
+	},
+	shutdown::Shutdown,
+};
 use async_std::{
 	channel::{Receiver, Sender, bounded as make_bounded_channel},
 	fs,
 	io::{BufReader, BufWriter, ErrorKind},
 	net::TcpStream,
-	os::unix::net::UnixListener,
+	os::unix::net::{UnixListener, UnixStream},
+	sync::Mutex,
 	task::{self, JoinHandle},
 };
-use futures::{prelude::*, select};
-use link_protocol::{Packet, PowerState, Scene, channel};
+use capture::{Capture, Direction};
+use connections::{Connections, Role};
+use expect::Expectations;
+use futures::{io::Cursor, prelude::*, select};
+use link_protocol::{LogEntry, Packet, Scene, SessionOutcome, channel};
 use log::{debug, error, info, trace, warn};
 use rand::rngs::OsRng;
-use std::{os::unix::fs::PermissionsExt, time::Duration};
+use report::Report;
+use state::{Action, Event, SessionMachine, StateTimeouts};
+use std::{
+	net::SocketAddr,
+	os::unix::fs::PermissionsExt,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+use watchdog::{Watchdog, WatchdogLimits};
+
+mod capture;
+mod connections;
+mod expect;
+mod json;
+mod report;
+mod state;
+mod watchdog;
+
+pub(crate) use expect::ExpectRule;
+pub(crate) use json::ClientProtocol;
+pub(crate) use state::SessionState;
 
 macro_rules! race_all_or_cancel {
 	($f1:expr) => {
@@ -31,13 +65,38 @@ macro_rules! race_all_or_cancel {
 	}};
 }
 
+/// Where session sockets (`link-{id}.sock`) are created.
+pub(crate) const SOCKET_DIR: &str = "/tmp";
+
+/// How often the link is pinged to measure its round-trip time.
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a link's hardware report after it says hello.
+const HARDWARE_REPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a rejected link is shown why before being disconnected.
+const REJECT_HOLD: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 enum ControlMessage {
-	EstablishedLink { id: String },
-	EstablishedServer { path: String },
+	EstablishedLink {
+		id: String,
+		fleet: FleetEntry,
+		hardware: Option<HardwareInfo>,
+	},
+	EstablishedServer {
+		path: String,
+	},
 	Packet(Packet),
+	/// A session event sent to the link, relayed to observers
+	Observed(Packet),
+	/// A rule for the serial output, from the client
+	Expect(ExpectRule),
+	/// How the runner ended the session; `None` if that isn't known
+	Outcome(Option<SessionOutcome>),
 	End,
+	Stop,
 }
 
 #[derive(Debug)]
@@ -45,31 +104,182 @@ enum ControlMessage {
 enum BrokerMessage {
 	Link(ControlMessage),
 	Client(ControlMessage),
+	Runner(ControlMessage),
+	Maintenance(ControlMessage),
+}
+
+/// Writes entries to the link's log scene on behalf of a runner.
+#[derive(Debug, Clone)]
+pub(crate) struct LinkLog(Sender<BrokerMessage>);
+
+impl LinkLog {
+	/// Sends a log entry to the link. Never blocks; if the broker is
+	/// backed up, the entry is dropped.
+	pub fn send(&self, entry: LogEntry) {
+		if self
+			.0
+			.try_send(BrokerMessage::Runner(ControlMessage::Packet(Packet::Log(
+				entry,
+			))))
+			.is_err()
+		{
+			trace!("dropped runner log entry; broker is busy");
+		}
+	}
+
+	/// Truncates a message to fit in a [`LogEntry`].
+	pub fn truncate(message: &str) -> &str {
+		let mut end = message.len().min(255);
+		while !message.is_char_boundary(end) {
+			end -= 1;
+		}
+		&message[..end]
+	}
+
+	/// Creates a handle whose entries are dropped.
+	#[cfg(test)]
+	pub fn stand_in() -> Self {
+		Self(make_bounded_channel(1).0)
+	}
+}
+
+/// Sends packets to the link on behalf of the maintenance API.
+#[derive(Debug, Clone)]
+pub(crate) struct LinkControl(Sender<BrokerMessage>);
+
+impl LinkControl {
+	pub async fn send(&self, packet: Packet) -> Result<(), Error> {
+		self.0
+			.send(BrokerMessage::Maintenance(ControlMessage::Packet(packet)))
+			.await?;
+		Ok(())
+	}
+
+	/// Creates a handle whose packets end up on the returned channel.
+	#[cfg(test)]
+	pub fn stand_in() -> (Self, Receiver<Packet>) {
+		let (sender, receiver) = make_bounded_channel(8);
+		let (packets, packet_receiver) = make_bounded_channel(8);
+
+		task::spawn(async move {
+			while let Ok(BrokerMessage::Maintenance(ControlMessage::Packet(packet))) =
+				receiver.recv().await
+			{
+				if packets.send(packet).await.is_err() {
+					break;
+				}
+			}
+		});
+
+		(Self(sender), packet_receiver)
+	}
+}
+
+/// What a [`SerialTap`] subscriber receives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SerialEvent {
+	/// Output from the SUT
+	Data(Vec<u8>),
+	/// The session's recent output has been replayed; what follows is live
+	Live,
 }
 
-pub(crate) async fn run_session(config: Config, link_stream: TcpStream) -> Result<(), Error> {
+/// Watches a session's serial output on behalf of the console.
+#[derive(Debug, Clone)]
+pub(crate) struct SerialTap(Connections);
+
+impl SerialTap {
+	/// Subscribes to the SUT's serial output, starting with the session's
+	/// recent output up to [`SerialEvent::Live`]. The stream ends with the
+	/// session; a subscriber that falls too far behind is dropped.
+	pub fn subscribe(self) -> impl Stream<Item = SerialEvent> + Unpin {
+		let Some(receiver) = self.0.attach(Role::Observer) else {
+			unreachable!("observers can always attach");
+		};
+
+		receiver.filter_map(|message| {
+			future::ready(match message {
+				ControlMessage::Packet(Packet::Serial(data)) => {
+					Some(SerialEvent::Data(data.to_vec()))
+				}
+				ControlMessage::Packet(Packet::BacklogReplayed) => Some(SerialEvent::Live),
+				_ => None,
+			})
+		})
+	}
+
+	/// Creates a tap along with a function that feeds it serial output.
+	#[cfg(test)]
+	pub fn stand_in() -> (Self, impl Fn(&[u8])) {
+		let connections = Connections::new();
+		let feed = {
+			let connections = connections.clone();
+			move |data: &[u8]| {
+				connections.publish(&ControlMessage::Packet(Packet::Serial(
+					data.try_into().unwrap(),
+				)));
+			}
+		};
+
+		(Self(connections), feed)
+	}
+}
+
+pub(crate) async fn run_session(daemon: Arc<Daemon>, link_stream: TcpStream) -> Result<(), Error> {
+	let config = &daemon.config;
+
 	let (broker_sender, broker_receiver) = make_bounded_channel(32);
 	let (link_sender, link_receiver) = make_bounded_channel(32);
 	let (client_sender, client_receiver) = make_bounded_channel(32);
-	let (docker_sender, docker_receiver) = make_bounded_channel(2);
+	let (runner_sender, runner_receiver) = make_bounded_channel(2);
 
 	let link_handle = task::spawn(handle_link(
 		link_stream,
+		daemon.fleet.clone(),
 		broker_sender.clone(),
 		link_receiver,
 	));
 
 	// wait for the link to indicate it's established a connection
-	let link_id = match broker_receiver.recv().await? {
-		BrokerMessage::Link(ControlMessage::EstablishedLink { id }) => id,
+	let (link_id, fleet, hardware) = match broker_receiver.recv().await? {
+		BrokerMessage::Link(ControlMessage::EstablishedLink {
+			id,
+			fleet,
+			hardware,
+		}) => (id, fleet, hardware),
 		_ => return Err(Error::NoHelloPacket),
 	};
 
+	let Some(profile) = daemon.links().profile(&link_id) else {
+		warn!("rejecting link {link_id}: not allowed by the link configuration");
+		fleet.set_rejected();
+		reject_link(
+			link_handle,
+			link_sender,
+			broker_receiver,
+			daemon.shutdown.clone(),
+		)
+		.await?;
+		return Err(Error::LinkRejected(link_id));
+	};
+	fleet.set_name(profile.name.clone());
+	fleet.set_maintenance(profile.maintenance.unwrap_or(false));
+
+	// keep the reconciler away from this link's socket and container
+	let _live = daemon.sessions.register(&link_id);
+
+	// The runner client and any observers connect over the session's
+	// lifetime; the console watches the session as an observer.
+	let connections = Connections::new();
+	fleet.set_serial(SerialTap(connections.clone()));
+
 	// start the UDS server for the github actions runner
 	let client_handle = task::spawn(handle_client(
 		link_id.clone(),
+		profile.client_protocol.unwrap_or(config.client_protocol),
 		broker_sender.clone(),
 		client_receiver,
+		connections,
 	));
 
 	// wait for the client to indicate it's established a connection
@@ -78,126 +288,346 @@ pub(crate) async fn run_session(config: Config, link_stream: TcpStream) -> Resul
 		_ => panic!("unexpected message from client"),
 	};
 
-	// start the docker container
-	let docker_handle = task::spawn(handle_docker(
-		config,
-		link_id.clone(),
-		client_path,
-		docker_receiver,
-	));
+	// start the runner
+	let session = RunnerSession {
+		link_id: link_id.clone(),
+		socket_path: client_path,
+		link_log: LinkLog(broker_sender.clone()),
+		fleet: fleet.clone(),
+		profile: profile.clone(),
+		hardware,
+	};
+	let runner_handle = match config.runner_backend {
+		RunnerBackendKind::Docker => task::spawn(handle_runner(
+			DockerRunner::new(
+				config,
+				daemon.image_for(profile.image.as_deref())?,
+				profile.container.or(&daemon.container_limits),
+			)?,
+			session,
+			broker_sender.clone(),
+			runner_receiver,
+		)),
+		RunnerBackendKind::Process => task::spawn(handle_runner(
+			ProcessRunner::new(config)?,
+			session,
+			broker_sender.clone(),
+			runner_receiver,
+		)),
+		RunnerBackendKind::None => task::spawn(handle_runner(
+			NoopRunner,
+			session,
+			broker_sender.clone(),
+			runner_receiver,
+		)),
+	};
+
+	let machine = SessionMachine::new(
+		StateTimeouts {
+			waiting_for_client: timeout_secs(config.session_client_timeout),
+			booting: timeout_secs(config.session_boot_timeout),
+			finishing: timeout_secs(config.session_finish_timeout),
+			outcome_hold: Duration::from_secs(
+				profile.outcome_hold.unwrap_or(config.session_outcome_hold),
+			),
+		},
+		Watchdog::new(WatchdogLimits {
+			session: timeout_secs(profile.session_timeout.unwrap_or(config.session_timeout)),
+			test: timeout_secs(profile.test_timeout.unwrap_or(config.session_test_timeout)),
+			serial_inactivity: timeout_secs(
+				profile
+					.serial_timeout
+					.unwrap_or(config.session_serial_timeout),
+			),
+		}),
+		Expectations::new(profile.expect.clone().unwrap_or_default()),
+	);
+
+	let artifacts = config
+		.artifacts_dir
+		.as_ref()
+		.map(|root| SessionArtifacts::new(root, &link_id));
+	let report = artifacts.clone().map(Report::new);
+	let capture = match artifacts {
+		Some(artifacts) => match Capture::create(artifacts).await {
+			Ok(capture) => Some(capture),
+			Err(err) => {
+				warn!("failed to start serial capture; not capturing this session: {err}");
+				None
+			}
+		},
+		None => None,
+	};
+	let files = Arc::new(Mutex::new(SessionFiles { report, capture }));
 
 	// start the broker
 	let broker_handle = task::spawn(handle_broker(
 		broker_receiver,
 		link_sender,
 		client_sender,
-		docker_sender,
+		runner_sender,
+		fleet,
+		machine,
+		files.clone(),
+		daemon.shutdown.clone(),
 	));
 
-	race_all_or_cancel!(link_handle, client_handle, docker_handle, broker_handle)
+	let result = race_all_or_cancel!(link_handle, client_handle, runner_handle, broker_handle);
+
+	// The broker finishes these once the outcome is shown, but the session
+	// may have ended before that (e.g. the link disconnected).
+	files.lock().await.finish().await;
+
+	result
+}
+
+/// A timeout in seconds, where 0 means none.
+fn timeout_secs(secs: u64) -> Option<Duration> {
+	(secs > 0).then(|| Duration::from_secs(secs))
 }
 
+/// Tells a link that it isn't allowed to run sessions, leaving the
+/// message up for a while before disconnecting it. It'll then reconnect,
+/// picking up any changes to the link configuration in the meantime.
+/// The daemon shutting down disconnects it right away.
+async fn reject_link(
+	link_handle: JoinHandle<Result<(), Error>>,
+	link: Sender<ControlMessage>,
+	broker: Receiver<BrokerMessage>,
+	shutdown: Shutdown,
+) -> Result<(), Error> {
+	let notify_handle = task::spawn(async move {
+		for packet in [
+			Packet::SetMonitorStandby(false),
+			Packet::SetScene(Scene::Log),
+			Packet::Log(LogEntry::Error(
+				"this link is not allowed to run sessions"
+					.try_into()
+					.unwrap(),
+			)),
+		] {
+			link.send(ControlMessage::Packet(packet)).await?;
+		}
+
+		select! {
+			() = task::sleep(REJECT_HOLD).fuse() => {},
+			() = shutdown.triggered().fuse() => {
+				debug!("daemon is shutting down; disconnecting rejected link early");
+			}
+		}
+		link.send(ControlMessage::End).await?;
+
+		async_std::future::pending::<Result<(), Error>>().await.ok();
+		unreachable!("hibernating");
+	});
+
+	// Nobody is listening to the link; don't let it back up.
+	let drain_handle = task::spawn(async move {
+		while broker.recv().await.is_ok() {}
+		Ok(())
+	});
+
+	race_all_or_cancel!(link_handle, notify_handle, drain_handle)
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_broker(
 	broker: Receiver<BrokerMessage>,
 	link: Sender<ControlMessage>,
 	client: Sender<ControlMessage>,
-	docker: Sender<ControlMessage>,
+	runner: Sender<ControlMessage>,
+	fleet: FleetEntry,
+	mut machine: SessionMachine,
+	files: Arc<Mutex<SessionFiles>>,
+	shutdown: Shutdown,
 ) -> Result<(), Error> {
 	debug!("starting broker");
 
-	let mut has_sent_bootfile_size = false;
-	let mut has_sent_test_session = false;
-	let mut has_started_test_session = false;
-	let mut has_started_first_test = false;
+	fleet.set_state(machine.state());
+
+	let mut shutting_down = false;
 
 	loop {
-		match broker.recv().await? {
-			BrokerMessage::Link(ControlMessage::Packet(Packet::Serial(data))) => {
-				client
-					.send(ControlMessage::Packet(Packet::Serial(data)))
-					.await?;
+		let deadline = machine.deadline();
+		let timeout = async move {
+			match deadline {
+				Some((at, expiry)) => {
+					task::sleep(at.saturating_duration_since(Instant::now())).await;
+					expiry
+				}
+				None => async_std::future::pending().await,
 			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::Serial(data))) => {
-				link.send(ControlMessage::Packet(Packet::Serial(data)))
-					.await?;
+		};
+
+		let wakeup = machine.wakeup();
+		let wakeup = async move {
+			match wakeup {
+				Some(at) => task::sleep(at.saturating_duration_since(Instant::now())).await,
+				None => async_std::future::pending().await,
 			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::BootfileSize { uefi, bios })) => {
-				link.send(ControlMessage::Packet(Packet::BootfileSize { uefi, bios }))
-					.await?;
-				has_sent_bootfile_size = true;
+		};
+
+		let shutdown_signal = {
+			let shutdown = &shutdown;
+			async move {
+				if shutting_down {
+					async_std::future::pending().await
+				} else {
+					shutdown.triggered().await
+				}
 			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::PressPower)) => {
-				link.send(ControlMessage::Packet(Packet::PressPower))
-					.await?;
+		};
+
+		let event = select! {
+			message = broker.recv().fuse() => match message? {
+				BrokerMessage::Link(ControlMessage::Packet(packet)) => {
+					if let Packet::Serial(data) = &packet {
+						files.lock().await.capture_serial(Direction::Output, data).await;
+					}
+					Event::Link(packet)
+				}
+				BrokerMessage::Client(ControlMessage::Packet(packet)) => Event::Client(packet),
+				BrokerMessage::Client(ControlMessage::End) => Event::ClientEnded,
+				BrokerMessage::Client(ControlMessage::Expect(rule)) => Event::Expect(rule),
+				BrokerMessage::Runner(ControlMessage::Outcome(outcome)) => {
+					info!("runner finished: {outcome:?}");
+					Event::RunnerExited(outcome)
+				}
+				// Runner logs and maintenance packets go to the link in any state.
+				BrokerMessage::Runner(ControlMessage::Packet(packet @ Packet::Log(_)))
+				| BrokerMessage::Maintenance(ControlMessage::Packet(packet)) => {
+					if let Packet::Serial(data) = &packet {
+						files.lock().await.capture_serial(Direction::Input, data).await;
+					}
+					link.send(ControlMessage::Packet(packet)).await?;
+					continue;
+				}
+				unknown => {
+					error!("unexpected message sent to broker: {unknown:?}");
+					return Err(Error::UnexpectedPacket);
+				}
+			},
+			() = wakeup.fuse() => Event::Wakeup,
+			expiry = timeout.fuse() => {
+				warn!("{expiry}; powering off and ending the session");
+				Event::Timeout(expiry)
+			},
+			() = shutdown_signal.fuse() => {
+				info!("daemon is shutting down; powering off and ending the session");
+				shutting_down = true;
+				Event::Shutdown
 			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::PressReset)) => {
-				link.send(ControlMessage::Packet(Packet::PressReset))
-					.await?;
+		};
+
+		let actions = match machine.handle(event) {
+			Ok(actions) => actions,
+			Err(rejected) => {
+				warn!("ignoring {rejected}");
+				continue;
 			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::StartTest { name })) => {
-				if !has_started_first_test {
-					has_started_first_test = true;
+		};
 
-					// Switch to testing scene
-					link.send(ControlMessage::Packet(Packet::SetScene(Scene::Test)))
-						.await?;
+		fleet.set_state(machine.state());
+
+		for action in actions {
+			match action {
+				Action::Link(packet) => {
+					files.lock().await.record(&packet).await;
+					if connections::is_session_event(&packet) {
+						client
+							.send(ControlMessage::Observed(packet.clone()))
+							.await?;
+					}
+					link.send(ControlMessage::Packet(packet)).await?;
 				}
+				Action::Client(packet) => client.send(ControlMessage::Packet(packet)).await?,
+				Action::FinishRunner => runner.send(ControlMessage::End).await?,
+				Action::StopRunner => runner.send(ControlMessage::Stop).await?,
+				Action::End(hold) => {
+					files.lock().await.finish().await;
 
-				link.send(ControlMessage::Packet(Packet::StartTest { name }))
-					.await?;
-			}
-			BrokerMessage::Client(ControlMessage::Packet(Packet::StartTestSession {
-				total_tests,
-				author,
-				title,
-				ref_id,
-			})) => {
-				link.send(ControlMessage::Packet(Packet::StartTestSession {
-					total_tests,
-					author,
-					title,
-					ref_id,
-				}))
-				.await?;
-				has_sent_test_session = true;
-			}
-			BrokerMessage::Client(ControlMessage::End) => {
-				docker.send(ControlMessage::End).await?;
+					// Leave the outcome up on the link for a while; it resets
+					// (and thus clears the display) once we disconnect.
+					debug!("holding link connection for {hold:?} to show outcome");
+					select! {
+						() = task::sleep(hold).fuse() => {},
+						() = shutdown.triggered().fuse() => {
+							debug!("daemon is shutting down; disconnecting link early");
+						}
+					}
+
+					link.send(ControlMessage::End).await?;
+				}
 			}
-			unknown => {
-				error!("unexpected message sent to broker: {unknown:?}");
-				return Err(Error::UnexpectedPacket);
+		}
+	}
+}
+
+/// What a session leaves in its artifacts directory, if anything. It's
+/// shared with the broker, so that it's finished however the session ends.
+struct SessionFiles {
+	report: Option<Report>,
+	capture: Option<Capture>,
+}
+
+impl SessionFiles {
+	/// Records a packet sent to the link.
+	async fn record(&mut self, packet: &Packet) {
+		if let Some(report) = &mut self.report {
+			report.record(packet);
+		}
+		match packet {
+			Packet::Serial(data) => self.capture_serial(Direction::Input, data).await,
+			Packet::StartTestSession { ref_id, .. } => {
+				if let Some(capture) = &mut self.capture {
+					capture.set_ref_id(ref_id.as_str());
+				}
 			}
+			_ => {}
 		}
+	}
 
-		if has_sent_bootfile_size && has_sent_test_session && !has_started_test_session {
-			has_started_test_session = true;
-
-			// Turn on the monitor
-			link.send(ControlMessage::Packet(Packet::SetMonitorStandby(false)))
-				.await?;
-			// Then set the scene to the logo
-			link.send(ControlMessage::Packet(Packet::SetScene(Scene::Logo)))
-				.await?;
-			// Turn on the machine
-			link.send(ControlMessage::Packet(Packet::SetPowerState(
-				PowerState::On,
-			)))
-			.await?;
-			// Press the power button
-			link.send(ControlMessage::Packet(Packet::PressPower))
-				.await?;
+	/// Records serial data in the capture, if any; a capture that
+	/// fails to write is dropped rather than ending the session.
+	async fn capture_serial(&mut self, direction: Direction, data: &[u8]) {
+		let Some(capture) = &mut self.capture else {
+			return;
+		};
+		if let Err(err) = capture.record(direction, data, Instant::now()).await {
+			warn!("failed to capture serial; no longer capturing this session: {err}");
+			self.capture = None;
+		}
+	}
+
+	/// Writes the report and moves the capture out of staging. Only the
+	/// first call does anything; the session is over by then.
+	async fn finish(&mut self) {
+		if let Some(mut report) = self.report.take() {
+			match report.write().await {
+				Ok(Some(dir)) => info!("wrote test report to {}", dir.display()),
+				Ok(None) => debug!("no test session was started; not writing a report"),
+				Err(err) => warn!("failed to write test report: {err}"),
+			}
+		}
+		if let Some(capture) = self.capture.take() {
+			match capture.finish().await {
+				Ok(dir) => info!("wrote serial capture to {}", dir.display()),
+				Err(err) => warn!("failed to write serial capture: {err}"),
+			}
 		}
 	}
 }
 
 async fn handle_link(
 	stream: TcpStream,
+	fleet: Arc<Fleet>,
 	broker: Sender<BrokerMessage>,
 	receiver: Receiver<ControlMessage>,
 ) -> Result<(), Error> {
 	info!("starting link connection");
 
+	let peer: SocketAddr = stream.peer_addr()?;
+
 	let (mut outgoing, mut incoming) = {
 		// create buffered readers/writers for stream
 		let sock_reader = BufReader::new(stream.clone());
@@ -209,30 +639,91 @@ async fn handle_link(
 
 	// wait for first packet - the hello packet - from the link
 	let hello = incoming.receive().await?;
-	if let Packet::LinkOnline { uid, version } = hello {
-		let id = hex::encode_upper(&uid[..]);
-		info!("link online: {id} (firmware version {version})");
-		broker
-			.send(BrokerMessage::Link(ControlMessage::EstablishedLink { id }))
-			.await?;
-	} else {
+	let Packet::LinkOnline { uid, version } = hello else {
 		error!("unexpected packet from link: {hello:?}");
 		return Err(Error::NoHelloPacket);
+	};
+
+	let id = hex::encode_upper(&uid[..]);
+	info!("link online: {id} (firmware version {version}) from {peer}");
+
+	// links that support it describe their hardware right after saying hello
+	let mut early_packet = None;
+	let hardware =
+		match async_std::future::timeout(HARDWARE_REPORT_TIMEOUT, incoming.receive()).await {
+			Ok(packet) => {
+				let packet = packet?;
+				let hardware = HardwareInfo::from_packet(&packet);
+				if hardware.is_none() {
+					early_packet = Some(packet);
+				}
+				hardware
+			}
+			Err(_) => None,
+		};
+
+	match &hardware {
+		Some(hardware) => info!("link {id} reported hardware: {hardware:?}"),
+		None => warn!("link {id} did not report its hardware"),
+	}
+
+	let fleet = fleet.register(&id, &version, peer, LinkControl(broker.clone()));
+	fleet.set_hardware(hardware.clone());
+	broker
+		.send(BrokerMessage::Link(ControlMessage::EstablishedLink {
+			id,
+			fleet: fleet.clone(),
+			hardware,
+		}))
+		.await?;
+
+	if let Some(packet) = early_packet {
+		trace!("link -> broker: {packet:?}");
+		broker
+			.send(BrokerMessage::Link(ControlMessage::Packet(packet)))
+			.await?;
 	}
 
 	debug!("link connection negotiated; waiting for packets");
 
+	let mut pings = async_std::stream::interval(PING_INTERVAL);
+	let mut ping: Option<(u32, Instant)> = None;
+	let mut next_ping = 0u32;
+
 	loop {
 		select! {
 			packet = incoming.receive().fuse() => {
+				let packet = packet?;
+				fleet.seen();
+
+				if let Packet::Pong(n) = packet {
+					match ping.take() {
+						Some((sent, at)) if sent == n => {
+							trace!("link round-trip time: {:?}", at.elapsed());
+							fleet.set_rtt(at.elapsed());
+						}
+						_ => debug!("ignoring unexpected pong from link: {n}"),
+					}
+					continue;
+				}
+
 				trace!("link -> broker: {packet:?}");
-				broker.send(BrokerMessage::Link(ControlMessage::Packet(packet?))).await?;
+				broker.send(BrokerMessage::Link(ControlMessage::Packet(packet))).await?;
+			},
+			_ = pings.next().fuse() => {
+				ping = Some((next_ping, Instant::now()));
+				outgoing.send(Packet::Ping(next_ping)).await?;
+				next_ping = next_ping.wrapping_add(1);
 			},
 			packet = receiver.recv().fuse() => match packet? {
 				ControlMessage::Packet(packet) => {
 					trace!("broker -> link: {packet:?}");
 					outgoing.send(packet).await?;
 				},
+				ControlMessage::End => {
+					info!("session finished; closing link connection");
+					return Ok(());
+				},
 				unknown => panic!("unexpected message from broker: {unknown:?}")
 			}
 		}
@@ -241,12 +732,14 @@ async fn handle_link(
 
 async fn handle_client(
 	link_id: String,
+	protocol: ClientProtocol,
 	broker: Sender<BrokerMessage>,
 	receiver: Receiver<ControlMessage>,
+	connections: Connections,
 ) -> Result<(), Error> {
 	info!("starting github actions runner server");
 
-	let socket_path = format!("/tmp/link-{link_id}.sock");
+	let socket_path = format!("{SOCKET_DIR}/link-{link_id}.sock");
 
 	match fs::remove_file(&socket_path).await {
 		Ok(()) => {
@@ -273,166 +766,328 @@ async fn handle_client(
 		}))
 		.await?;
 
-	let (stream, _) = server.accept().await?;
-	drop(server);
+	// relay the broker's messages to whoever is connected
+	let mut incoming = server.incoming();
 
-	info!("accepted connection from github actions runner");
+	loop {
+		select! {
+			stream = incoming.next().fuse() => match stream {
+				Some(Ok(stream)) => {
+					task::spawn(serve_connection(
+						stream,
+						protocol,
+						broker.clone(),
+						connections.clone(),
+					));
+				}
+				Some(Err(err)) => warn!("failed to accept session socket connection: {err}"),
+				None => unreachable!("unix listener stopped accepting connections"),
+			},
+			message = receiver.recv().fuse() => {
+				let message = message?;
 
-	let (mut outgoing, mut incoming) = {
+				// Until the client attaches, its serial output is only kept
+				// in the backlog, so a client that's slow to connect (or never
+				// does) doesn't hold up the session.
+				if let Some(client) = connections.publish(&message) {
+					// Fails once the client has disconnected, which is fine.
+					let _ = client.send(message).await;
+				}
+			}
+		}
+	}
+}
+
+/// Serves a single connection to the session socket, telling the broker
+/// once the runner client disconnects.
+async fn serve_connection(
+	stream: UnixStream,
+	protocol: ClientProtocol,
+	broker: Sender<BrokerMessage>,
+	connections: Connections,
+) {
+	info!("accepted connection on session socket");
+
+	let result = async {
 		let (sock_reader, sock_writer) = stream.split();
 		// create buffered readers/writers for stream
-		let sock_reader = BufReader::new(sock_reader);
+		let mut sock_reader = BufReader::new(sock_reader);
 		let sock_writer = BufWriter::new(sock_writer);
-		channel::negotiate(sock_writer, sock_reader, &mut OsRng, channel::Side::Server).await?
+
+		// replay whatever was read to work out the protocol
+		let (protocol, prefix) = match protocol {
+			ClientProtocol::Auto => json::probe(&mut sock_reader).await?,
+			protocol => (protocol, Vec::new()),
+		};
+		let sock_reader = Cursor::new(prefix).chain(sock_reader);
+
+		if protocol == ClientProtocol::Json {
+			debug!("session socket connection speaks JSON lines");
+			json::serve(sock_reader, sock_writer, &broker, connections).await
+		} else {
+			serve_channel(sock_reader, sock_writer, &broker, connections).await
+		}
+	}
+	.await;
+
+	match result {
+		Ok(Some(Role::Client)) => {
+			info!("github actions runner disconnected");
+			if broker
+				.send(BrokerMessage::Client(ControlMessage::End))
+				.await
+				.is_ok()
+			{
+				debug!("sent end control message to broker");
+			}
+		}
+		Ok(Some(Role::Observer)) => info!("observer disconnected"),
+		Ok(None) => debug!("session socket connection closed without attaching"),
+		Err(err) => warn!("session socket connection failed: {err}"),
+	}
+}
+
+/// Relays between a connection speaking the packet channel and the broker
+/// until it disconnects, returning what it attached as (if it got that far).
+///
+/// The first packet decides the connection's role: [`Packet::Observe`]
+/// makes it an observer, anything else makes it the runner client.
+async fn serve_channel<R, W>(
+	sock_reader: R,
+	sock_writer: W,
+	broker: &Sender<BrokerMessage>,
+	connections: Connections,
+) -> Result<Option<Role>, Error>
+where
+	R: AsyncRead + Unpin,
+	W: AsyncWrite + Unpin,
+{
+	let (mut outgoing, mut incoming) =
+		channel::negotiate(sock_writer, sock_reader, &mut OsRng, channel::Side::Server).await?;
+
+	let Ok(first) = incoming.receive().await else {
+		return Ok(None);
+	};
+
+	let role = match first {
+		Packet::Observe => Role::Observer,
+		_ => Role::Client,
+	};
+
+	let Some(receiver) = connections.attach(role) else {
+		warn!("rejecting session socket connection: the session already has a client");
+		return Ok(None);
 	};
 
+	if role == Role::Client {
+		trace!("client -> broker: {first:?}");
+		broker
+			.send(BrokerMessage::Client(ControlMessage::Packet(first)))
+			.await?;
+	}
+
 	loop {
 		select! {
 			packet = incoming.receive().fuse() => {
-				trace!("client -> broker: {packet:?}");
-				if let Ok(packet) = packet {
-					broker.send(BrokerMessage::Client(ControlMessage::Packet(packet))).await?;
-				} else {
-					warn!("github actions runner disconnected");
+				let Ok(packet) = packet else {
 					break;
+				};
+
+				if role == Role::Observer {
+					debug!("ignoring packet from observer: {:?}", packet.kind());
+					continue;
 				}
+
+				trace!("client -> broker: {packet:?}");
+				broker.send(BrokerMessage::Client(ControlMessage::Packet(packet))).await?;
 			},
-			packet = receiver.recv().fuse() => match packet? {
-				ControlMessage::Packet(packet) => {
-					trace!("broker -> client: {packet:?}");
+			packet = receiver.recv().fuse() => match packet {
+				Ok(ControlMessage::Packet(packet)) => {
+					trace!("broker -> {role:?}: {packet:?}");
 					if outgoing.send(packet).await.is_err() {
-						warn!("github actions runner disconnected");
 						break;
 					}
 				},
-				unknown => panic!("unexpected message from broker: {unknown:?}")
+				Err(_) => {
+					debug!("session ended; closing session socket connection");
+					break;
+				}
+				Ok(unknown) => panic!("unexpected message from broker: {unknown:?}")
 			}
 		}
 	}
 
-	broker
-		.send(BrokerMessage::Client(ControlMessage::End))
-		.await?;
-	debug!("sent end control message to broker; will now hibernate");
-
-	async_std::future::pending::<Result<(), Error>>().await.ok();
-	unreachable!("hibernating");
+	Ok(Some(role))
 }
 
-async fn handle_docker(
-	config: Config,
-	link_id: String,
-	socket_path: String,
+async fn handle_runner<B: RunnerBackend + 'static>(
+	mut backend: B,
+	session: RunnerSession,
+	broker: Sender<BrokerMessage>,
 	receiver: Receiver<ControlMessage>,
 ) -> Result<(), Error> {
-	let docker = Docker::new(&config.docker_host)?;
+	let result = drive_runner(&mut backend, &session, &receiver).await;
 
-	debug!("pruning all containers for this link: {link_id}");
-	let containers = docker
-		.list_containers(Some(vec![("sh.oro.link".into(), link_id.clone())]))
-		.await?;
-	debug!("pruning {} containers:", containers.len());
-	for (id, state) in containers {
-		debug!("    - {id} ({state})");
-		docker.remove_container(&id, true).await?;
+	if let Err(err) = backend.cleanup().await {
+		error!("failed to clean up runner: {err:?}");
 	}
 
-	let id = docker
-		.create_container(&crate::docker::CreateContainer {
-			image: config.docker_ref.clone(),
-			labels: Some(
-				crate::docker::Map::new()
-					.add("sh.oro".into(), "link".into())
-					.add("sh.oro.link".into(), link_id.clone()),
-			),
-			env: Some(
-				crate::docker::Args::new()
-					.add("ACCESS_TOKEN".into(), config.gh_access_token.clone())
-					.add("ORGANIZATION".into(), config.gh_organization.clone())
-					.add("LABELS".into(), "self-hosted,oro,oro-link,x64".into()) // TODO(qix-): use self-report functionality of link
-					.add("NAME".into(), link_id.clone()),
-			),
-			host_config: Some(crate::docker::HostConfig {
-				binds: Some(crate::docker::Binds(vec![(
-					socket_path,
-					"/oro-link.sock".into(),
-					Some("rw".into()),
-				)])),
-			}),
-			..Default::default()
-		})
+	broker
+		.send(BrokerMessage::Runner(ControlMessage::Outcome(result?)))
 		.await?;
+	debug!("sent outcome to broker; will now hibernate");
 
-	let mut container_guard = ContainerGuard {
-		docker: &docker,
-		id: Some(id.clone()),
-	};
+	async_std::future::pending::<Result<(), Error>>().await.ok();
+	unreachable!("hibernating");
+}
 
-	debug!("created actions runner container; starting the container: {id}");
-	docker.start_container(&id).await?;
-
-	debug!("container started; waiting for exit: {id}");
-	let (exit_sender, exit_receiver) = make_bounded_channel(1);
-	let exit_handle: JoinHandle<Result<(), Error>> = task::spawn({
-		let docker = docker.clone();
-		let id = id.clone();
-		async move {
-			docker.wait_for_container(&id).await?;
-			exit_sender.send(()).await?;
-			warn!("actions runner container exited");
-			Ok(())
-		}
-	});
+async fn drive_runner<B: RunnerBackend>(
+	backend: &mut B,
+	session: &RunnerSession,
+	receiver: &Receiver<ControlMessage>,
+) -> Result<Option<SessionOutcome>, Error> {
+	backend.prepare(session).await?;
+	backend.start().await?;
 
-	let should_wait = select! {
+	select! {
 			packet = receiver.recv().fuse() => match packet? {
 				ControlMessage::End => {
-					info!("test program indicated that the test suite is finished; waiting 60s for container to exit");
-					true
+					info!("test program indicated that the test suite is finished");
 				}
+				ControlMessage::Stop => return stop_runner(backend).await,
 				unknown => panic!("unexpected message from broker: {unknown:?}")
 			},
-			_ = exit_receiver.recv().fuse() => {
-				info!("container exited");
-				false
+			exit = backend.wait().fuse() => {
+				let exit = exit?;
+				info!("runner exited ({exit:?})");
+				return Ok(outcome_for_exit(exit));
 			}
 	};
 
-	if should_wait {
-		let timed_out = async_std::future::timeout(Duration::from_secs(60), exit_handle)
-			.await
-			.is_err();
+	let Some(exit_timeout) = backend.exit_timeout() else {
+		return Ok(None);
+	};
 
-		if timed_out {
-			warn!("container exit timed out after 60s; killing: {id}");
-		} else {
-			info!("container exited normally; removing: {id}");
-		}
+	let exit_timeout = session
+		.profile
+		.exit_timeout
+		.map_or(exit_timeout, Duration::from_secs);
+
+	info!("waiting {exit_timeout:?} for runner to exit");
+	select! {
+		exit = async_std::future::timeout(exit_timeout, backend.wait()).fuse() => match exit {
+			Ok(exit) => {
+				let exit = exit?;
+				info!("runner exited normally ({exit:?})");
+				Ok(outcome_for_exit(exit))
+			}
+			Err(_) => {
+				info!("runner did not exit within {exit_timeout:?}; stopping it");
+				backend.stop().await?;
+				Ok(Some(SessionOutcome::TimedOut))
+			}
+		},
+		packet = receiver.recv().fuse() => match packet? {
+			ControlMessage::Stop => stop_runner(backend).await,
+			unknown => panic!("unexpected message from broker: {unknown:?}")
+		},
 	}
+}
 
-	docker.remove_container(&id, true).await?;
-	container_guard.id = None;
+async fn stop_runner<B: RunnerBackend>(backend: &mut B) -> Result<Option<SessionOutcome>, Error> {
+	info!("stopping runner at the broker's request");
+	backend.stop().await?;
+	Ok(Some(SessionOutcome::Killed))
+}
 
-	info!("container removed: {id}");
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Config, links::LinkProfile, runner::tests::session};
+	use envconfig::Envconfig;
+	use std::collections::HashMap;
 
-	Ok(())
-}
+	fn process(command: &str) -> ProcessRunner {
+		let config =
+			Config::init_from_hashmap(&HashMap::from([("RUNNER_COMMAND".into(), command.into())]))
+				.unwrap();
+		ProcessRunner::new(&config).unwrap()
+	}
 
-struct ContainerGuard<'a> {
-	docker: &'a Docker,
-	id: Option<String>,
-}
+	/// Drives a runner through a session, sending it the given
+	/// message once it's started.
+	async fn drive<B: RunnerBackend>(
+		mut backend: B,
+		profile: LinkProfile,
+		message: ControlMessage,
+	) -> Option<SessionOutcome> {
+		let (sender, receiver) = make_bounded_channel(1);
+		sender.send(message).await.unwrap();
 
-impl<'a> Drop for ContainerGuard<'a> {
-	fn drop(&mut self) {
-		if let Some(id) = self.id.clone() {
-			let docker = self.docker.clone();
-			debug!("dropping container guard; killing container: {id}");
-			task::spawn(async move {
-				if let Err(err) = docker.remove_container(&id, true).await {
-					error!("failed to kill docker container: {:?}", err);
-				}
-			});
+		let outcome = drive_runner(&mut backend, &session(profile), &receiver).await;
+		backend.cleanup().await.unwrap();
+		outcome.unwrap()
+	}
+
+	#[async_std::test]
+	async fn runner_exit_decides_the_outcome() {
+		let profile = LinkProfile {
+			exit_timeout: Some(30),
+			..Default::default()
+		};
+
+		for (command, outcome) in [
+			("exit 0", SessionOutcome::Passed),
+			("exit 1", SessionOutcome::Failed),
+			("kill -KILL $$", SessionOutcome::Killed),
+		] {
+			assert_eq!(
+				drive(process(command), profile.clone(), ControlMessage::End).await,
+				Some(outcome),
+				"{command}"
+			);
 		}
 	}
+
+	#[async_std::test]
+	async fn stops_runners_that_dont_exit_in_time() {
+		let profile = LinkProfile {
+			exit_timeout: Some(0),
+			..Default::default()
+		};
+
+		let started = Instant::now();
+		assert_eq!(
+			drive(process("sleep 30"), profile, ControlMessage::End).await,
+			Some(SessionOutcome::TimedOut)
+		);
+		assert!(started.elapsed() < Duration::from_secs(10));
+	}
+
+	#[async_std::test]
+	async fn stops_runners_when_asked() {
+		let started = Instant::now();
+		assert_eq!(
+			drive(
+				process("sleep 30"),
+				Default::default(),
+				ControlMessage::Stop
+			)
+			.await,
+			Some(SessionOutcome::Killed)
+		);
+		assert!(started.elapsed() < Duration::from_secs(10));
+	}
+
+	#[async_std::test]
+	async fn manual_sessions_leave_the_outcome_to_the_client() {
+		assert_eq!(
+			drive(NoopRunner, Default::default(), ControlMessage::End).await,
+			None
+		);
+		assert_eq!(
+			drive(NoopRunner, Default::default(), ControlMessage::Stop).await,
+			Some(SessionOutcome::Killed)
+		);
+	}
 }
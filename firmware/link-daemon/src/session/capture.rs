@@ -0,0 +1,308 @@
+//! Records a session's serial stream, in both directions, to the session's
+//! artifacts directory (see [`crate::artifacts`]):
+//!
+//! - `serial.raw`: the bytes the SUT sent, as is
+//! - `serial-input.raw`: the bytes sent to the SUT, as is
+//! - `serial.log`: both directions as timestamped lines, e.g.
+//!   `[    1.250] < login: ` for output and `[    2.000] > root` for input
+//! - `serial.cast`: both directions as an [asciicast v2] recording, for
+//!   `asciinema play`
+//!
+//! Timestamps are relative to the start of the session, and taken when the
+//! broker receives the serial data from the link (or sends it there).
+//!
+//! [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+use super::json::Utf8Decoder;
+use crate::artifacts::SessionArtifacts;
+use async_std::{
+	fs::{self, File},
+	io::BufWriter,
+};
+use futures::prelude::*;
+use serde_json::json;
+use std::{
+	io,
+	path::PathBuf,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const FILES: [&str; 4] = [
+	"serial.raw",
+	"serial-input.raw",
+	"serial.log",
+	"serial.cast",
+];
+
+/// The terminal size recorded in the asciicast header; serial consoles
+/// don't have one, so this is just a sensible default for players.
+const CAST_SIZE: (u16, u16) = (80, 24);
+
+/// Which way serial data went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+	/// From the SUT
+	Output,
+	/// To the SUT
+	Input,
+}
+
+impl Direction {
+	fn marker(self) -> char {
+		match self {
+			Self::Output => '<',
+			Self::Input => '>',
+		}
+	}
+
+	fn cast_code(self) -> &'static str {
+		match self {
+			Self::Output => "o",
+			Self::Input => "i",
+		}
+	}
+}
+
+/// One direction's text, split into lines for the log.
+#[derive(Default)]
+struct Lines {
+	decoder: Utf8Decoder,
+	line: String,
+	/// When the line in progress started
+	started: Option<Duration>,
+	/// Whether the last character ended a line with `\r`, so that a
+	/// following `\n` doesn't end another (empty) one.
+	after_cr: bool,
+}
+
+impl Lines {
+	/// Adds text, returning the lines it completed and when each started.
+	fn push(&mut self, text: &str, at: Duration) -> Vec<(Duration, String)> {
+		let mut lines = Vec::new();
+
+		for c in text.chars() {
+			let after_cr = std::mem::replace(&mut self.after_cr, c == '\r');
+			match c {
+				'\n' if after_cr => {}
+				'\r' | '\n' => lines.push((
+					self.started.take().unwrap_or(at),
+					std::mem::take(&mut self.line),
+				)),
+				c => {
+					self.started.get_or_insert(at);
+					self.line.push(c);
+				}
+			}
+		}
+
+		lines
+	}
+
+	/// The line in progress, if any.
+	fn take(&mut self) -> Option<(Duration, String)> {
+		let started = self.started.take()?;
+		Some((started, std::mem::take(&mut self.line)))
+	}
+}
+
+/// A serial capture in progress.
+pub(crate) struct Capture {
+	artifacts: SessionArtifacts,
+	staging: PathBuf,
+	started: Instant,
+	ref_id: Option<String>,
+	output: BufWriter<File>,
+	input: BufWriter<File>,
+	log: BufWriter<File>,
+	cast: BufWriter<File>,
+	output_lines: Lines,
+	input_lines: Lines,
+}
+
+impl Capture {
+	/// Starts capturing into the session's staging directory.
+	pub async fn create(artifacts: SessionArtifacts) -> io::Result<Self> {
+		let staging = artifacts.staging_dir();
+		fs::create_dir_all(&staging).await?;
+
+		let [output, input, log, cast] = FILES;
+		let mut capture = Self {
+			output: BufWriter::new(File::create(staging.join(output)).await?),
+			input: BufWriter::new(File::create(staging.join(input)).await?),
+			log: BufWriter::new(File::create(staging.join(log)).await?),
+			cast: BufWriter::new(File::create(staging.join(cast)).await?),
+			artifacts,
+			staging,
+			started: Instant::now(),
+			ref_id: None,
+			output_lines: Lines::default(),
+			input_lines: Lines::default(),
+		};
+
+		let header = json!({
+			"version": 2,
+			"width": CAST_SIZE.0,
+			"height": CAST_SIZE.1,
+			"timestamp": SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap_or_default()
+				.as_secs(),
+			"title": capture.artifacts.link_id(),
+		});
+		capture
+			.cast
+			.write_all(format!("{header}\n").as_bytes())
+			.await?;
+
+		Ok(capture)
+	}
+
+	/// Sets the ref ID the capture is filed under once it finishes.
+	pub fn set_ref_id(&mut self, ref_id: impl Into<String>) {
+		self.ref_id = Some(ref_id.into());
+	}
+
+	/// Records serial data that went one way at the given time.
+	pub async fn record(
+		&mut self,
+		direction: Direction,
+		data: &[u8],
+		at: Instant,
+	) -> io::Result<()> {
+		if data.is_empty() {
+			return Ok(());
+		}
+
+		let at = at.saturating_duration_since(self.started);
+		let (raw, lines) = match direction {
+			Direction::Output => (&mut self.output, &mut self.output_lines),
+			Direction::Input => (&mut self.input, &mut self.input_lines),
+		};
+		raw.write_all(data).await?;
+
+		let text = lines.decoder.decode(data);
+		if text.is_empty() {
+			return Ok(());
+		}
+
+		for (started, line) in lines.push(&text, at) {
+			write_line(&mut self.log, direction, started, &line).await?;
+		}
+
+		let event = json!([at.as_secs_f64(), direction.cast_code(), text]);
+		self.cast.write_all(format!("{event}\n").as_bytes()).await
+	}
+
+	/// Ends the capture and moves it into the session's directory,
+	/// returning that.
+	pub async fn finish(mut self) -> io::Result<PathBuf> {
+		for direction in [Direction::Output, Direction::Input] {
+			let lines = match direction {
+				Direction::Output => &mut self.output_lines,
+				Direction::Input => &mut self.input_lines,
+			};
+			if let Some((started, line)) = lines.take() {
+				write_line(&mut self.log, direction, started, &line).await?;
+			}
+		}
+
+		for writer in [
+			&mut self.output,
+			&mut self.input,
+			&mut self.log,
+			&mut self.cast,
+		] {
+			writer.flush().await?;
+		}
+
+		let dir = self.artifacts.dir(self.ref_id.as_deref().unwrap_or(""));
+		fs::create_dir_all(&dir).await?;
+		for file in FILES {
+			fs::rename(self.staging.join(file), dir.join(file)).await?;
+		}
+		fs::remove_dir(&self.staging).await?;
+
+		Ok(dir)
+	}
+}
+
+async fn write_line(
+	log: &mut BufWriter<File>,
+	direction: Direction,
+	started: Duration,
+	line: &str,
+) -> io::Result<()> {
+	let line = format!(
+		"[{:>9.3}] {} {line}\n",
+		started.as_secs_f64(),
+		direction.marker()
+	);
+	log.write_all(line.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::artifacts::tests::temp_root;
+
+	#[async_std::test]
+	async fn records_both_directions() {
+		let root = temp_root("capture");
+
+		let mut capture = Capture::create(SessionArtifacts::new(&root, "ABCD"))
+			.await
+			.unwrap();
+		let start = capture.started;
+		let at = |ms| start + Duration::from_millis(ms);
+
+		for (direction, data, ms) in [
+			(Direction::Output, &b"Booting\r\nlogin: "[..], 500),
+			(Direction::Input, b"ro", 1_000),
+			(Direction::Input, b"ot\r", 1_250),
+			(Direction::Output, b"root\r\n\xc3", 1_300),
+			(Direction::Output, b"\xa9t\xc3\xa9\r\n# ", 2_000),
+		] {
+			capture.record(direction, data, at(ms)).await.unwrap();
+		}
+		capture.set_ref_id("refs/heads/main");
+
+		let dir = capture.finish().await.unwrap();
+		assert!(dir.starts_with(root.join("ABCD/refs_heads_main")));
+		// Nothing is left behind in staging.
+		assert_eq!(std::fs::read_dir(root.join("ABCD")).unwrap().count(), 1);
+
+		let read = |file| std::fs::read(dir.join(file)).unwrap();
+		assert_eq!(
+			read("serial.raw"),
+			b"Booting\r\nlogin: root\r\n\xc3\xa9t\xc3\xa9\r\n# "
+		);
+		assert_eq!(read("serial-input.raw"), b"root\r");
+		assert_eq!(
+			String::from_utf8(read("serial.log")).unwrap(),
+			"[    0.500] < Booting\n\
+			 [    1.000] > root\n\
+			 [    0.500] < login: root\n\
+			 [    2.000] < été\n\
+			 [    2.000] < # \n"
+		);
+
+		let cast = String::from_utf8(read("serial.cast")).unwrap();
+		let mut cast = cast
+			.lines()
+			.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap());
+		let header = cast.next().unwrap();
+		assert_eq!(header["version"], 2);
+		assert_eq!(header["title"], "ABCD");
+		assert_eq!(
+			cast.collect::<Vec<_>>(),
+			[
+				json!([0.5, "o", "Booting\r\nlogin: "]),
+				json!([1.0, "i", "ro"]),
+				json!([1.25, "i", "ot\r"]),
+				json!([1.3, "o", "root\r\n"]),
+				json!([2.0, "o", "été\r\n# "]),
+			]
+		);
+
+		std::fs::remove_dir_all(root).unwrap();
+	}
+}
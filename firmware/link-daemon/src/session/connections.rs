@@ -0,0 +1,248 @@
+//! Connections to the session socket: the runner client, which drives the
+//! session, and any number of observers, which only watch it.
+use super::ControlMessage;
+use async_std::channel::{Receiver, Sender, bounded as make_bounded_channel};
+use link_protocol::Packet;
+use log::{debug, trace};
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+};
+
+/// How many bytes of recent serial output are kept for connections that
+/// attach later, so that e.g. a client that's slow to connect doesn't miss
+/// the SUT's early boot output.
+const SERIAL_BACKLOG: usize = 64 * 1024;
+
+/// How many messages an observer may fall behind by before it's dropped.
+const OBSERVER_BACKLOG: usize = 64;
+
+/// How many messages the client may fall behind by before the session
+/// waits on it.
+const CLIENT_BACKLOG: usize = 32;
+
+/// What a connection to the session socket is for, as declared by its
+/// first message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+	/// Drives the session; there's only ever one
+	Client,
+	/// Watches the session; receives serial output and session events,
+	/// but can't send anything to the link
+	Observer,
+}
+
+/// Whether a packet from the broker describes the session's progress,
+/// and is thus relayed to observers (see [`ControlMessage::Observed`]).
+pub(crate) fn is_session_event(packet: &Packet) -> bool {
+	matches!(
+		packet,
+		Packet::StartTestSession { .. }
+			| Packet::StartTest { .. }
+			| Packet::TestResult { .. }
+			| Packet::SessionOutcome(_)
+	)
+}
+
+#[derive(Debug, Default)]
+struct Hub {
+	/// The most recent serial output, replayed to connections as they attach
+	serial: VecDeque<u8>,
+	/// The client's channel, once it has attached
+	client: Option<Sender<ControlMessage>>,
+	observers: Vec<Sender<ControlMessage>>,
+}
+
+/// Hands out the broker's messages to whoever is connected.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Connections(Arc<Mutex<Hub>>);
+
+impl Connections {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Attaches a connection, returning where its messages arrive; `None`
+	/// if it wants to be the client but the client already connected.
+	/// The serial backlog is replayed first, followed by
+	/// [`Packet::BacklogReplayed`].
+	///
+	/// Consumes the handle so that connections don't keep each other's
+	/// channels open once the session (and thus the hub) is gone.
+	pub fn attach(self, role: Role) -> Option<Receiver<ControlMessage>> {
+		let mut hub = self.0.lock().unwrap();
+		if role == Role::Client && hub.client.is_some() {
+			return None;
+		}
+
+		let (front, back) = hub.serial.as_slices();
+		let replay: Vec<_> = front
+			.chunks(256)
+			.chain(back.chunks(256))
+			.map(|chunk| ControlMessage::Packet(Packet::Serial(chunk.try_into().unwrap())))
+			.chain([ControlMessage::Packet(Packet::BacklogReplayed)])
+			.collect();
+
+		let live = match role {
+			Role::Client => CLIENT_BACKLOG,
+			Role::Observer => OBSERVER_BACKLOG,
+		};
+		let (sender, receiver) = make_bounded_channel(replay.len() + live);
+		debug!(
+			"{role:?} attached to session; replaying {} bytes of serial output",
+			hub.serial.len()
+		);
+		for message in replay {
+			// Can't fail; the channel has room for the whole replay.
+			let _ = sender.try_send(message);
+		}
+
+		match role {
+			Role::Client => hub.client = Some(sender),
+			Role::Observer => hub.observers.push(sender),
+		}
+
+		Some(receiver)
+	}
+
+	/// Relays a message to every observer and keeps serial output for
+	/// connections that attach later. Never blocks; observers that have
+	/// fallen behind (or disconnected) are dropped.
+	///
+	/// Returns the client's channel if the message is for the client and
+	/// it has attached; the caller sends it on, waiting if need be. Until
+	/// the client attaches, the backlog is all it gets.
+	pub fn publish(&self, message: &ControlMessage) -> Option<Sender<ControlMessage>> {
+		let mut hub = self.0.lock().unwrap();
+
+		let packet = match message {
+			ControlMessage::Packet(packet @ Packet::Serial(data)) => {
+				let serial = &mut hub.serial;
+				serial.extend(data.iter());
+				let excess = serial.len().saturating_sub(SERIAL_BACKLOG);
+				serial.drain(..excess);
+				packet
+			}
+			ControlMessage::Observed(packet) => packet,
+			_ => return None,
+		};
+
+		hub.observers.retain(|observer| {
+			let kept = observer
+				.try_send(ControlMessage::Packet(packet.clone()))
+				.is_ok();
+			if !kept {
+				trace!("dropping observer that fell behind or disconnected");
+			}
+			kept
+		});
+
+		match message {
+			ControlMessage::Packet(_) => hub.client.clone(),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn serial(data: &[u8]) -> ControlMessage {
+		ControlMessage::Packet(Packet::Serial(data.try_into().unwrap()))
+	}
+
+	/// Receives serial output up to the end of the backlog.
+	async fn replayed(receiver: &Receiver<ControlMessage>) -> Vec<u8> {
+		let mut replayed = Vec::new();
+		loop {
+			match receiver.recv().await.unwrap() {
+				ControlMessage::Packet(Packet::Serial(data)) => replayed.extend_from_slice(&data),
+				ControlMessage::Packet(Packet::BacklogReplayed) => return replayed,
+				unknown => panic!("unexpected message: {unknown:?}"),
+			}
+		}
+	}
+
+	#[async_std::test]
+	async fn attaches_one_client_and_many_observers() {
+		let connections = Connections::new();
+
+		let observers = [
+			connections.clone().attach(Role::Observer).unwrap(),
+			connections.clone().attach(Role::Observer).unwrap(),
+		];
+		let client = connections.clone().attach(Role::Client).unwrap();
+		assert!(connections.clone().attach(Role::Client).is_none());
+
+		assert_eq!(replayed(&client).await, b"");
+		let sender = connections.publish(&serial(b"boot")).unwrap();
+		sender.send(serial(b"boot")).await.unwrap();
+		assert!(
+			connections
+				.publish(&ControlMessage::Observed(Packet::StartTest {
+					name: "boots".try_into().unwrap(),
+				}))
+				.is_none()
+		);
+
+		assert!(matches!(
+			client.recv().await.unwrap(),
+			ControlMessage::Packet(Packet::Serial(data)) if data == b"boot"
+		));
+		assert!(client.is_empty());
+
+		for observer in &observers {
+			assert_eq!(replayed(observer).await, b"");
+			assert!(matches!(
+				observer.recv().await.unwrap(),
+				ControlMessage::Packet(Packet::Serial(data)) if data == b"boot"
+			));
+			assert!(matches!(
+				observer.recv().await.unwrap(),
+				ControlMessage::Packet(Packet::StartTest { name }) if name == "boots"
+			));
+		}
+
+		// Observers that fall behind are dropped rather than holding up the session.
+		drop(observers);
+		connections.publish(&serial(b"."));
+		let slow = connections.clone().attach(Role::Observer).unwrap();
+		for _ in 0..=OBSERVER_BACKLOG {
+			connections.publish(&serial(b"."));
+		}
+		assert_eq!(replayed(&slow).await, b"boot.");
+		assert_eq!(slow.len(), OBSERVER_BACKLOG);
+		assert!(connections.0.lock().unwrap().observers.is_empty());
+	}
+
+	#[async_std::test]
+	async fn replays_recent_serial_output() {
+		let connections = Connections::new();
+
+		// Nobody is connected yet; none of this waits on the client.
+		let line = [b'.'; 255];
+		for n in 0..(SERIAL_BACKLOG / line.len() + 1) {
+			let mut data = line;
+			data[line.len() - 8..].copy_from_slice(format!("{n:08}").as_bytes());
+			assert!(connections.publish(&serial(&data)).is_none());
+		}
+
+		let client = connections.clone().attach(Role::Client).unwrap();
+		let backlog = replayed(&client).await;
+		assert_eq!(backlog.len(), SERIAL_BACKLOG);
+		assert!(backlog.ends_with(b"00000257"));
+		assert!(!backlog.windows(8).any(|window| window == b"00000000"));
+
+		// The client gets live output from here on, as does an observer
+		// that attaches later, after the same backlog.
+		let sender = connections.publish(&serial(b"live")).unwrap();
+		sender.send(serial(b"live")).await.unwrap();
+		let observer = connections.clone().attach(Role::Observer).unwrap();
+		assert_eq!(replayed(&observer).await[SERIAL_BACKLOG - 4..], *b"live");
+		assert!(matches!(
+			client.recv().await.unwrap(),
+			ControlMessage::Packet(Packet::Serial(data)) if data == b"live"
+		));
+	}
+}
@@ -0,0 +1,539 @@
+//! Rules that watch the SUT's serial output and react to it, so that e.g.
+//! a flaky boot is retried without the runner having to know about it.
+//! Rules come from the link configuration (`expect`) and from JSON clients
+//! (an `expect` command), and only apply while the SUT is powered on.
+//!
+//! ```toml
+//! # Answer the login prompt
+//! [[links.ABCD.expect]]
+//! pattern = "login: $"
+//! action = { send = "root\n" }
+//!
+//! # Power-cycle boot loops, but only three times
+//! [[links.ABCD.expect]]
+//! pattern = "Boot failed|PXE-E\\d+"
+//! action = "power-cycle"
+//! retries = 3
+//!
+//! # Reset if the kernel hasn't come up within two minutes
+//! [[links.ABCD.expect]]
+//! pattern = "Oro kernel booted"
+//! timeout = 120
+//! on-timeout = "reset"
+//! retries = 2
+//! ```
+//!
+//! Patterns are matched against each line of output (without its line
+//! ending), as well as the line in progress so that prompts match; output
+//! that matched in a line in progress isn't matched again. A rule with a
+//! `timeout` (in seconds) waits for its pattern, taking its `on-timeout`
+//! action (failing the session, unless given) if the pattern doesn't show
+//! up in time; it waits again whenever the SUT is reset or power-cycled.
+//! A rule takes its action at most `retries` times (unless unset) and
+//! then fails the session instead.
+use super::json::Utf8Decoder;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, de::Error as _};
+use std::{
+	fmt,
+	time::{Duration, Instant},
+};
+
+/// How long the SUT is left off when power-cycling it.
+pub(crate) const POWER_CYCLE_OFF: Duration = Duration::from_secs(5);
+
+/// How long a line in progress may grow before its start is dropped.
+const MAX_LINE: usize = 4096;
+
+/// A regular expression, compared by its source.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern(Regex);
+
+impl PartialEq for Pattern {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.as_str() == other.0.as_str()
+	}
+}
+
+impl fmt::Display for Pattern {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "`{}`", self.0.as_str())
+	}
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let source = String::deserialize(deserializer)?;
+		Regex::new(&source).map(Self).map_err(D::Error::custom)
+	}
+}
+
+/// What a rule does once it's triggered.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) enum RuleAction {
+	/// Types text into the SUT
+	Send(String),
+	/// Presses the reset button
+	Reset,
+	/// Powers the SUT off and (after [`POWER_CYCLE_OFF`]) back on
+	PowerCycle,
+	/// Fails the session
+	Fail,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RuleSpec {
+	pattern: Pattern,
+	action: Option<RuleAction>,
+	timeout: Option<u64>,
+	#[serde(alias = "on_timeout")]
+	on_timeout: Option<RuleAction>,
+	retries: Option<u32>,
+}
+
+/// A single expectation; see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RuleSpec")]
+pub(crate) struct ExpectRule {
+	pub pattern: Pattern,
+	/// Taken whenever the pattern matches
+	pub action: Option<RuleAction>,
+	/// How long to wait for the pattern, and what to do if it doesn't show up
+	pub timeout: Option<(Duration, RuleAction)>,
+	/// How many times the rule may take an action before failing the session
+	pub retries: Option<u32>,
+}
+
+impl TryFrom<RuleSpec> for ExpectRule {
+	type Error = &'static str;
+
+	fn try_from(spec: RuleSpec) -> Result<Self, Self::Error> {
+		let timeout = match (spec.timeout, spec.on_timeout) {
+			(Some(secs), action) => Some((
+				Duration::from_secs(secs),
+				action.unwrap_or(RuleAction::Fail),
+			)),
+			(None, Some(_)) => return Err("on-timeout requires a timeout"),
+			(None, None) => None,
+		};
+
+		if spec.action.is_none() && timeout.is_none() {
+			return Err("a rule needs an action, a timeout or both");
+		}
+
+		Ok(Self {
+			pattern: spec.pattern,
+			action: spec.action,
+			timeout,
+			retries: spec.retries,
+		})
+	}
+}
+
+/// What the session should do about the serial output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reaction {
+	Send(String),
+	Reset,
+	PowerOff,
+	/// Powers the SUT back on after [`Reaction::PowerOff`]
+	PowerOn,
+	/// Fails the session, saying why
+	Fail(String),
+}
+
+struct Armed {
+	rule: ExpectRule,
+	/// How many actions the rule has taken
+	taken: u32,
+	/// When a rule with a timeout gives up on its pattern; `None` once
+	/// it's been seen (or while the SUT is off)
+	deadline: Option<Instant>,
+}
+
+impl Armed {
+	/// Takes an action (or fails the session, if the rule is out of retries).
+	fn take(&mut self, action: &RuleAction, why: impl FnOnce() -> String) -> Reaction {
+		if self
+			.rule
+			.retries
+			.is_some_and(|retries| self.taken >= retries)
+		{
+			return Reaction::Fail(format!("{} (retried {} times)", why(), self.taken));
+		}
+		self.taken += 1;
+
+		match action {
+			RuleAction::Send(text) => Reaction::Send(text.clone()),
+			RuleAction::Reset => Reaction::Reset,
+			RuleAction::PowerCycle => Reaction::PowerOff,
+			RuleAction::Fail => Reaction::Fail(why()),
+		}
+	}
+}
+
+/// The engine applying a session's rules to its serial output.
+pub(crate) struct Expectations {
+	rules: Vec<Armed>,
+	decoder: Utf8Decoder,
+	/// The line in progress
+	line: String,
+	/// Whether the SUT is on; rules only apply while it is
+	powered: bool,
+	/// When the SUT is powered back on while power-cycling
+	power_on_at: Option<Instant>,
+	/// Whether the session failed; nothing happens after that
+	failed: bool,
+}
+
+impl Expectations {
+	pub fn new(rules: impl IntoIterator<Item = ExpectRule>) -> Self {
+		let mut expectations = Self {
+			rules: Vec::new(),
+			decoder: Utf8Decoder::default(),
+			line: String::new(),
+			powered: false,
+			power_on_at: None,
+			failed: false,
+		};
+
+		for rule in rules {
+			expectations.add(rule, Instant::now());
+		}
+
+		expectations
+	}
+
+	/// Adds a rule; if the SUT is on, its timeout starts now.
+	pub fn add(&mut self, rule: ExpectRule, now: Instant) {
+		let deadline = rule
+			.timeout
+			.as_ref()
+			.filter(|_| self.powered)
+			.map(|(timeout, _)| now + *timeout);
+
+		self.rules.push(Armed {
+			rule,
+			taken: 0,
+			deadline,
+		});
+	}
+
+	/// The SUT was powered on (or reset); rules with a timeout wait
+	/// for their pattern from now on.
+	pub fn powered_on(&mut self, now: Instant) {
+		self.powered = true;
+		self.line.clear();
+
+		for armed in &mut self.rules {
+			armed.deadline = armed
+				.rule
+				.timeout
+				.as_ref()
+				.map(|(timeout, _)| now + *timeout);
+		}
+	}
+
+	/// When [`Expectations::poll`] should be called next.
+	pub fn wakeup(&self) -> Option<Instant> {
+		if self.failed {
+			return None;
+		}
+
+		self.power_on_at
+			.or_else(|| self.rules.iter().filter_map(|armed| armed.deadline).min())
+	}
+
+	/// Handles rules whose timeout passed (and power cycles that are due).
+	pub fn poll(&mut self, now: Instant) -> Vec<Reaction> {
+		if self.failed {
+			return vec![];
+		}
+
+		if let Some(at) = self.power_on_at {
+			if at > now {
+				return vec![];
+			}
+
+			self.power_on_at = None;
+			self.powered_on(now);
+			return vec![Reaction::PowerOn];
+		}
+
+		let Some(armed) = self
+			.rules
+			.iter_mut()
+			.find(|armed| armed.deadline.is_some_and(|at| at <= now))
+		else {
+			return vec![];
+		};
+
+		let (timeout, action) = armed.rule.timeout.clone().unwrap();
+		armed.deadline = Some(now + timeout);
+
+		let pattern = armed.rule.pattern.clone();
+		let reaction = armed.take(&action, || {
+			format!("{pattern} not seen on serial within {timeout:?}")
+		});
+		self.react(&reaction, now);
+		vec![reaction]
+	}
+
+	/// Matches serial output from the SUT against the rules.
+	pub fn feed(&mut self, data: &[u8], now: Instant) -> Vec<Reaction> {
+		if !self.powered || self.failed {
+			return vec![];
+		}
+
+		let text = self.decoder.decode(data);
+		let mut reactions = Vec::new();
+
+		for piece in text.split_inclusive('\n') {
+			self.line.push_str(piece);
+
+			// Don't let a line without an end grow forever.
+			if self.line.len() > MAX_LINE {
+				let mut cut = self.line.len() - MAX_LINE;
+				while !self.line.is_char_boundary(cut) {
+					cut += 1;
+				}
+				self.line.drain(..cut);
+			}
+
+			let complete = self.line.ends_with('\n');
+			let line = self.line.trim_end_matches(['\r', '\n']).to_string();
+			if complete {
+				self.line.clear();
+			}
+
+			let mut matched_until = 0;
+			for i in 0..self.rules.len() {
+				let armed = &mut self.rules[i];
+				let Some(found) = armed.rule.pattern.0.find(&line) else {
+					continue;
+				};
+				matched_until = matched_until.max(found.end());
+
+				// A rule waiting for its pattern is satisfied by it.
+				armed.deadline = None;
+
+				let Some(action) = armed.rule.action.clone() else {
+					continue;
+				};
+				let pattern = armed.rule.pattern.clone();
+				let reaction = armed.take(&action, || format!("serial output matched {pattern}"));
+
+				self.react(&reaction, now);
+				let interrupts = !matches!(reaction, Reaction::Send(_));
+				reactions.push(reaction);
+				if interrupts {
+					// The SUT is going away; whatever else it said doesn't matter.
+					self.line.clear();
+					return reactions;
+				}
+			}
+
+			if !complete {
+				self.line.drain(..matched_until);
+			}
+		}
+
+		reactions
+	}
+
+	/// Keeps track of what a reaction does to the SUT.
+	fn react(&mut self, reaction: &Reaction, now: Instant) {
+		match reaction {
+			Reaction::Reset => self.powered_on(now),
+			Reaction::PowerOff => {
+				self.powered = false;
+				self.power_on_at = Some(now + POWER_CYCLE_OFF);
+				for armed in &mut self.rules {
+					armed.deadline = None;
+				}
+			}
+			Reaction::Fail(_) => self.failed = true,
+			Reaction::Send(_) | Reaction::PowerOn => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Serial output recorded from a SUT stuck in a boot loop (which
+	/// firmware then gives up on), split as the link sent it.
+	const BOOT_LOOP: &[&[u8]] = &[
+		b"\x1b[2J\x1b[01;01H>>Checking Media Presence......\r\n>>Media Pres",
+		b"ent......\r\n>>Start PXE over IPv4.\r\n  PXE-E18: Server response timeout.\r\n",
+		b"BdsDxe: failed to load Boot0001 \"UEFI PXEv4 (MAC:525400123456)\" from PciRoot(0x0)/Pci(0x3,0x0)/MAC(525400123456,0x1)/IPv4(0.0.0.0): Not Found\r\n",
+		b">>Checking Media Presence......\r\n>>Media Present......\r\n>>Start PXE over IPv4.\r\n",
+		b"  PXE-E18: Server response timeout.\r\nBdsDxe: No bootable option or device was found.\r\n",
+	];
+
+	/// Serial output recorded from a SUT booting to a login prompt.
+	const BOOT_TO_LOGIN: &[&[u8]] = &[
+		b"Oro kernel 0.1.0 (x86_64)\r\n[   0.001] mem: 2048 MiB\r\n[   0.",
+		b"104] smp: 4 cores online\r\n[   0.230] Oro kernel booted\r\n",
+		b"\r\noro log",
+		b"in: ",
+		b"root\r\n# ",
+	];
+
+	fn parse(toml: &str) -> Result<Vec<ExpectRule>, toml::de::Error> {
+		#[derive(Deserialize)]
+		struct Rules {
+			expect: Vec<ExpectRule>,
+		}
+
+		Ok(toml::from_str::<Rules>(toml)?.expect)
+	}
+
+	fn rules(toml: &str) -> Vec<ExpectRule> {
+		parse(toml).unwrap()
+	}
+
+	fn replay(expectations: &mut Expectations, stream: &[&[u8]], now: Instant) -> Vec<Reaction> {
+		stream
+			.iter()
+			.flat_map(|data| expectations.feed(data, now))
+			.collect()
+	}
+
+	#[test]
+	fn parses_rules() {
+		let parsed = rules(
+			r#"
+			[[expect]]
+			pattern = "login: $"
+			action = { send = "root\n" }
+
+			[[expect]]
+			pattern = "booted"
+			timeout = 60
+			retries = 2
+			"#,
+		);
+		assert_eq!(parsed[0].action, Some(RuleAction::Send("root\n".into())));
+		assert_eq!(
+			parsed[1].timeout,
+			Some((Duration::from_secs(60), RuleAction::Fail))
+		);
+		assert_eq!(parsed[1].retries, Some(2));
+
+		for invalid in [
+			"[[expect]]\npattern = \"booted\"",
+			"[[expect]]\npattern = \"(\"\naction = \"reset\"",
+			"[[expect]]\npattern = \"booted\"\non-timeout = \"reset\"",
+			"[[expect]]\npattern = \"booted\"\naction = \"explode\"",
+		] {
+			assert!(parse(invalid).is_err(), "{invalid}");
+		}
+	}
+
+	#[test]
+	fn answers_prompts_once() {
+		let now = Instant::now();
+		let mut expectations = Expectations::new(rules(
+			r#"
+			[[expect]]
+			pattern = "login: $"
+			action = { send = "root\n" }
+			"#,
+		));
+
+		// Nothing applies until the SUT is on.
+		assert!(replay(&mut expectations, BOOT_TO_LOGIN, now).is_empty());
+
+		expectations.powered_on(now);
+		assert_eq!(
+			replay(&mut expectations, BOOT_TO_LOGIN, now),
+			vec![Reaction::Send("root\n".into())]
+		);
+	}
+
+	#[test]
+	fn power_cycles_boot_loops_until_out_of_retries() {
+		let now = Instant::now();
+		let mut expectations = Expectations::new(rules(
+			r#"
+			[[expect]]
+			pattern = "PXE-E\\d+"
+			action = "power-cycle"
+			retries = 1
+			"#,
+		));
+		expectations.powered_on(now);
+
+		// The rest of the output is ignored once the SUT is powered off.
+		assert_eq!(
+			replay(&mut expectations, BOOT_LOOP, now),
+			vec![Reaction::PowerOff]
+		);
+		assert_eq!(expectations.wakeup(), Some(now + POWER_CYCLE_OFF));
+		assert!(expectations.poll(now).is_empty());
+
+		let later = now + POWER_CYCLE_OFF;
+		assert_eq!(expectations.poll(later), vec![Reaction::PowerOn]);
+		assert_eq!(expectations.wakeup(), None);
+
+		let reactions = replay(&mut expectations, BOOT_LOOP, later);
+		assert!(matches!(
+			&reactions[..],
+			[Reaction::Fail(message)]
+				if message == "serial output matched `PXE-E\\d+` (retried 1 times)"
+		));
+		assert!(replay(&mut expectations, BOOT_LOOP, later).is_empty());
+	}
+
+	#[test]
+	fn waits_for_patterns() {
+		let now = Instant::now();
+		let mut expectations = Expectations::new(rules(
+			r#"
+			[[expect]]
+			pattern = "Oro kernel booted"
+			timeout = 120
+			on-timeout = "reset"
+			retries = 1
+			"#,
+		));
+		assert_eq!(expectations.wakeup(), None);
+
+		expectations.powered_on(now);
+		let deadline = now + Duration::from_secs(120);
+		assert_eq!(expectations.wakeup(), Some(deadline));
+
+		// The boot loop never gets there; the SUT is reset once...
+		assert!(replay(&mut expectations, BOOT_LOOP, now).is_empty());
+		assert!(expectations.poll(now).is_empty());
+		assert_eq!(expectations.poll(deadline), vec![Reaction::Reset]);
+		assert_eq!(
+			expectations.wakeup(),
+			Some(deadline + Duration::from_secs(120))
+		);
+
+		// ...and then the session fails.
+		let reactions = expectations.poll(deadline + Duration::from_secs(120));
+		assert!(matches!(
+			&reactions[..],
+			[Reaction::Fail(message)]
+				if message.starts_with("`Oro kernel booted` not seen on serial within 120s")
+		));
+		assert_eq!(expectations.wakeup(), None);
+
+		// A good boot, on the other hand, satisfies the rule.
+		let mut expectations = Expectations::new([]);
+		expectations.powered_on(now);
+		expectations.add(
+			rules("[[expect]]\npattern = \"Oro kernel booted\"\ntimeout = 120").remove(0),
+			now,
+		);
+		assert_eq!(expectations.wakeup(), Some(deadline));
+		assert!(replay(&mut expectations, BOOT_TO_LOGIN, now).is_empty());
+		assert_eq!(expectations.wakeup(), None);
+	}
+}
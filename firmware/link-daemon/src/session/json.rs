@@ -0,0 +1,656 @@
+//! A plain-text alternative to the encrypted packet channel on the session
+//! socket: newline-delimited JSON commands (from the client) and events (to
+//! the client), mirroring the [`Packet`] variants a client may send or
+//! receive. For example:
+//!
+//! ```text
+//! > {"type": "bootfile_size", "uefi": 1048576, "bios": 0}
+//! > {"type": "start_test_session", "total_tests": 1, "author": "", "title": "", "ref_id": ""}
+//! > {"type": "start_test", "name": "boots"}
+//! < {"type":"serial","data":"Oro kernel booted\r\n"}
+//! > {"type": "test_result", "name": "boots", "outcome": "passed", "duration_ms": 1200}
+//! > {"type": "serial", "data": "shutdown\n"}
+//! > {"type": "end_test_session"}
+//! ```
+//!
+//! Serial data is text; bytes that aren't valid UTF-8 are replaced. A test
+//! result's `outcome` is one of `passed`, `failed` or `skipped`; its
+//! `duration_ms` and `message` may be left out. The session ends with
+//! `end_test_session`, or when the client closes the socket.
+//!
+//! Clients may also add rules for the session's serial output (see
+//! [`super::expect`]), which apply for the rest of the session:
+//!
+//! ```text
+//! > {"type": "expect", "pattern": "login: $", "action": {"send": "root\n"}}
+//! > {"type": "expect", "pattern": "Oro kernel booted", "timeout": 120, "on_timeout": "power-cycle"}
+//! ```
+//!
+//! A connection whose first command is `{"type": "observe"}` watches the
+//! session instead: it receives serial output along with `start_test_session`,
+//! `start_test`, `test_result` and `session_outcome` events, and can't send
+//! any commands.
+//!
+//! Either way, a connection first receives the session's recent serial
+//! output (including whatever the SUT printed before it connected),
+//! followed by `{"type":"backlog_replayed"}`; serial output after that is
+//! live.
+use super::{
+	BrokerMessage, ControlMessage,
+	connections::{Connections, Role},
+	expect::ExpectRule,
+	report,
+};
+use crate::Error;
+use async_std::channel::{Receiver, Sender};
+use futures::{prelude::*, select};
+use link_protocol::Packet;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How a client talks to the daemon over the session socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ClientProtocol {
+	/// Detected from the first bytes the client sends
+	Auto,
+	/// The encrypted packet channel (see [`link_protocol::channel`])
+	Channel,
+	/// Newline-delimited JSON
+	Json,
+}
+
+impl FromStr for ClientProtocol {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"auto" => Ok(Self::Auto),
+			"channel" => Ok(Self::Channel),
+			"json" => Ok(Self::Json),
+			unknown => Err(format!(
+				"unknown client protocol (expected auto, channel or json): {unknown}"
+			)),
+		}
+	}
+}
+
+/// The length of the public key a channel client opens with.
+const CHANNEL_KEY_LEN: usize = 32;
+
+/// Works out which protocol a client speaks, returning the bytes read
+/// while doing so (which must be replayed to whatever handles the client).
+///
+/// A channel client opens by sending its 32 byte public key. A JSON client
+/// opens with a line of text starting with `{`. Reading up to a newline
+/// or 32 bytes (whichever comes first) thus never waits on a channel
+/// client, and a random key passing for text is vanishingly unlikely.
+pub(crate) async fn probe<R: AsyncRead + Unpin>(
+	reader: &mut R,
+) -> Result<(ClientProtocol, Vec<u8>), Error> {
+	let mut prefix = Vec::with_capacity(CHANNEL_KEY_LEN);
+	let mut byte = [0u8];
+
+	while prefix.len() < CHANNEL_KEY_LEN {
+		reader.read_exact(&mut byte).await?;
+		prefix.push(byte[0]);
+
+		if prefix[0] != b'{' {
+			return Ok((ClientProtocol::Channel, prefix));
+		}
+
+		if byte[0] == b'\n' {
+			break;
+		}
+	}
+
+	let protocol = if looks_like_text(&prefix) {
+		ClientProtocol::Json
+	} else {
+		ClientProtocol::Channel
+	};
+
+	Ok((protocol, prefix))
+}
+
+fn looks_like_text(bytes: &[u8]) -> bool {
+	// The probe may have stopped in the middle of a character.
+	let text = match std::str::from_utf8(bytes) {
+		Ok(text) => text,
+		Err(err) if err.error_len().is_none() => {
+			std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap()
+		}
+		Err(_) => return false,
+	};
+
+	text.chars()
+		.all(|c| !c.is_control() || matches!(c, '\t' | '\r' | '\n'))
+}
+
+/// A command from a JSON client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum Command {
+	BootfileSize {
+		uefi: u64,
+		bios: u64,
+	},
+	StartTestSession {
+		total_tests: u32,
+		author: String,
+		title: String,
+		ref_id: String,
+	},
+	StartTest {
+		name: String,
+	},
+	TestResult {
+		name: String,
+		outcome: String,
+		#[serde(default)]
+		duration_ms: u64,
+		#[serde(default)]
+		message: String,
+	},
+	EndTestSession,
+	PressPower,
+	PressReset,
+	Serial {
+		data: String,
+	},
+	/// Add a rule for the session's serial output
+	Expect(ExpectRule),
+	/// Watch the session rather than drive it; must be the first command
+	Observe,
+}
+
+impl Command {
+	fn into_messages(self) -> Result<Vec<ControlMessage>, String> {
+		fn text<T: for<'a> TryFrom<&'a str>>(field: &str, value: String) -> Result<T, String> {
+			value
+				.as_str()
+				.try_into()
+				.map_err(|_| format!("{field} is too long (at most 255 bytes)"))
+		}
+
+		Ok(vec![ControlMessage::Packet(match self {
+			Self::BootfileSize { uefi, bios } => Packet::BootfileSize { uefi, bios },
+			Self::StartTestSession {
+				total_tests,
+				author,
+				title,
+				ref_id,
+			} => Packet::StartTestSession {
+				total_tests,
+				author: text("author", author)?,
+				title: text("title", title)?,
+				ref_id: text("ref_id", ref_id)?,
+			},
+			Self::StartTest { name } => Packet::StartTest {
+				name: text("name", name)?,
+			},
+			Self::TestResult {
+				name,
+				outcome,
+				duration_ms,
+				message,
+			} => Packet::TestResult {
+				name: text("name", name)?,
+				outcome: report::parse_test_outcome(&outcome).ok_or_else(|| {
+					format!("unknown test outcome (expected passed, failed or skipped): {outcome}")
+				})?,
+				duration_ms,
+				message: text("message", message)?,
+			},
+			Self::EndTestSession => Packet::EndTestSession,
+			Self::PressPower => Packet::PressPower,
+			Self::PressReset => Packet::PressReset,
+			Self::Serial { data } => {
+				return Ok(data
+					.as_bytes()
+					.chunks(256)
+					.map(|chunk| ControlMessage::Packet(Packet::Serial(chunk.try_into().unwrap())))
+					.collect());
+			}
+			Self::Expect(rule) => return Ok(vec![ControlMessage::Expect(rule)]),
+			Self::Observe => return Err("observe must be the first command".into()),
+		})])
+	}
+}
+
+/// An event sent to a JSON client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+	Serial {
+		data: String,
+	},
+	StartTestSession {
+		total_tests: u32,
+		author: String,
+		title: String,
+		ref_id: String,
+	},
+	StartTest {
+		name: String,
+	},
+	TestResult {
+		name: String,
+		outcome: &'static str,
+		duration_ms: u64,
+		message: String,
+	},
+	SessionOutcome {
+		outcome: &'static str,
+	},
+	/// The serial output from before the connection attached has been
+	/// replayed; what follows is live
+	BacklogReplayed,
+	/// A command couldn't be understood (or wasn't allowed)
+	Error {
+		message: String,
+	},
+}
+
+impl Event {
+	/// The event for any other packet from the broker (e.g. a session
+	/// event relayed to observers), if any.
+	fn from_session_event(packet: Packet) -> Option<Self> {
+		Some(match packet {
+			Packet::StartTestSession {
+				total_tests,
+				author,
+				title,
+				ref_id,
+			} => Self::StartTestSession {
+				total_tests,
+				author: author.as_str().into(),
+				title: title.as_str().into(),
+				ref_id: ref_id.as_str().into(),
+			},
+			Packet::StartTest { name } => Self::StartTest {
+				name: name.as_str().into(),
+			},
+			Packet::TestResult {
+				name,
+				outcome,
+				duration_ms,
+				message,
+			} => Self::TestResult {
+				name: name.as_str().into(),
+				outcome: report::test_outcome_name(outcome),
+				duration_ms,
+				message: message.as_str().into(),
+			},
+			Packet::SessionOutcome(outcome) => Self::SessionOutcome {
+				outcome: report::session_outcome_name(outcome),
+			},
+			Packet::BacklogReplayed => Self::BacklogReplayed,
+			_ => return None,
+		})
+	}
+}
+
+/// Decodes serial output as UTF-8, holding back characters that are
+/// split across packets until the rest of them arrives.
+#[derive(Default)]
+pub(super) struct Utf8Decoder {
+	pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+	pub fn decode(&mut self, bytes: &[u8]) -> String {
+		self.pending.extend_from_slice(bytes);
+
+		let complete = match std::str::from_utf8(&self.pending) {
+			Ok(_) => self.pending.len(),
+			Err(err) if err.error_len().is_none() => err.valid_up_to(),
+			// Invalid (rather than incomplete) sequences are replaced below.
+			Err(_) => self.pending.len(),
+		};
+
+		let rest = self.pending.split_off(complete);
+		let text = String::from_utf8_lossy(&self.pending).into_owned();
+		self.pending = rest;
+		text
+	}
+}
+
+/// Relays between a JSON connection and the broker until it disconnects,
+/// returning what it attached as (if it got that far).
+pub(crate) async fn serve<R, W>(
+	reader: R,
+	mut writer: W,
+	broker: &Sender<BrokerMessage>,
+	connections: Connections,
+) -> Result<Option<Role>, Error>
+where
+	R: AsyncBufRead + Unpin,
+	W: AsyncWrite + Unpin,
+{
+	let mut lines = reader.lines();
+	let mut decoder = Utf8Decoder::default();
+	// The connection's role is decided by its first valid command.
+	let mut connections = Some(connections);
+	let mut attached: Option<(Role, Receiver<ControlMessage>)> = None;
+
+	loop {
+		let message = async {
+			match &attached {
+				Some((_, receiver)) => receiver.recv().await,
+				None => future::pending().await,
+			}
+		};
+
+		let event = select! {
+			line = lines.next().fuse() => {
+				let line = match line {
+					Some(Ok(line)) => line,
+					Some(Err(err)) => {
+						warn!("failed to read from JSON connection: {err}");
+						break;
+					}
+					None => break,
+				};
+
+				if line.trim().is_empty() {
+					continue;
+				}
+
+				trace!("client -> broker: {line}");
+
+				let command = serde_json::from_str::<Command>(&line).map_err(|err| err.to_string());
+
+				let messages = match (command, &attached) {
+					(Ok(Command::Observe), None) => {
+						attached = connections
+							.take()
+							.and_then(|connections| connections.attach(Role::Observer))
+							.map(|receiver| (Role::Observer, receiver));
+						continue;
+					}
+					(Ok(_), Some((Role::Observer, _))) => {
+						Err("observers can't control the session".into())
+					}
+					(Ok(command), None) => {
+						let Some(receiver) = connections
+							.take()
+							.and_then(|connections| connections.attach(Role::Client))
+						else {
+							warn!("rejecting JSON connection: the session already has a client");
+							let _ = write_event(&mut writer, &Event::Error {
+								message: "the session already has a client; send {\"type\": \"observe\"} to watch it".into(),
+							}).await;
+							return Ok(None);
+						};
+						attached = Some((Role::Client, receiver));
+						command.into_messages()
+					}
+					(command, _) => command.and_then(Command::into_messages),
+				};
+
+				match messages {
+					Ok(messages) => {
+						for message in messages {
+							broker.send(BrokerMessage::Client(message)).await?;
+						}
+						continue;
+					}
+					Err(message) => {
+						debug!("invalid command from JSON connection: {message}");
+						Event::Error { message }
+					}
+				}
+			},
+			message = message.fuse() => match message {
+				Err(_) => {
+					debug!("session ended; closing JSON connection");
+					break;
+				}
+				Ok(ControlMessage::Packet(Packet::Serial(data))) => {
+					let data = decoder.decode(&data);
+					if data.is_empty() {
+						continue;
+					}
+					Event::Serial { data }
+				}
+				Ok(ControlMessage::Packet(packet)) => match Event::from_session_event(packet) {
+					Some(event) => event,
+					None => continue,
+				},
+				Ok(unknown) => panic!("unexpected message from broker: {unknown:?}")
+			}
+		};
+
+		if write_event(&mut writer, &event).await.is_err() {
+			warn!("JSON connection closed");
+			break;
+		}
+	}
+
+	Ok(attached.map(|(role, _)| role))
+}
+
+async fn write_event<W: AsyncWrite + Unpin>(writer: &mut W, event: &Event) -> std::io::Result<()> {
+	let mut line = serde_json::to_vec(event).unwrap();
+	line.push(b'\n');
+	writer.write_all(&line).await?;
+	writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::session::expect::RuleAction;
+	use async_std::{
+		channel::bounded as make_bounded_channel, io::BufReader, os::unix::net::UnixStream, task,
+	};
+	use futures::io::Cursor;
+	use link_protocol::{SessionOutcome, TestOutcome};
+
+	#[async_std::test]
+	async fn probes_client_protocol() {
+		let probe_bytes = async |bytes: &[u8]| {
+			let mut reader = Cursor::new(bytes.to_vec());
+			probe(&mut reader).await.unwrap()
+		};
+
+		let (protocol, prefix) = probe_bytes(b"{\"type\": \"press_power\"}\n...").await;
+		assert_eq!(protocol, ClientProtocol::Json);
+		assert_eq!(prefix, b"{\"type\": \"press_power\"}\n");
+
+		let long = b"{\"type\": \"start_test\", \"name\": \"some long test name\"}\n";
+		let (protocol, prefix) = probe_bytes(long).await;
+		assert_eq!(protocol, ClientProtocol::Json);
+		assert_eq!(prefix.len(), CHANNEL_KEY_LEN);
+
+		let (protocol, prefix) = probe_bytes(&[0x42; 40]).await;
+		assert_eq!(protocol, ClientProtocol::Channel);
+		assert_eq!(prefix, [0x42]);
+
+		let mut key = [0x7b; CHANNEL_KEY_LEN];
+		key[5] = 0x01;
+		let (protocol, prefix) = probe_bytes(&key).await;
+		assert_eq!(protocol, ClientProtocol::Channel);
+		assert_eq!(prefix, key);
+	}
+
+	#[test]
+	fn holds_back_split_characters() {
+		let mut decoder = Utf8Decoder::default();
+		let bytes = "→ ok".as_bytes();
+
+		assert_eq!(decoder.decode(&bytes[..2]), "");
+		assert_eq!(decoder.decode(&bytes[2..]), "→ ok");
+		assert_eq!(decoder.decode(b"\xff!"), "\u{fffd}!");
+	}
+
+	#[async_std::test]
+	async fn relays_commands_and_events() {
+		let (broker, broker_receiver) = make_bounded_channel(8);
+		let connections = Connections::new();
+		let (ours, theirs) = UnixStream::pair().unwrap();
+
+		// Sent before the client connects, so it's replayed once it does.
+		connections.publish(&ControlMessage::Packet(Packet::Serial(
+			b"boot\n".as_slice().try_into().unwrap(),
+		)));
+
+		let serving = task::spawn({
+			let connections = connections.clone();
+			async move { serve(BufReader::new(&theirs), &theirs, &broker, connections).await }
+		});
+
+		(&ours)
+			.write_all(
+				concat!(
+					"{\"type\": \"bootfile_size\", \"uefi\": 1, \"bios\": 2}\n",
+					"\n",
+					"{\"type\": \"press_the_button\"}\n",
+					"{\"type\": \"serial\", \"data\": \"reboot\\n\"}\n",
+					"{\"type\": \"test_result\", \"name\": \"boots\", \"outcome\": \"passed\"}\n",
+					"{\"type\": \"end_test_session\"}\n",
+					"{\"type\": \"expect\", \"pattern\": \"login: $\", \"action\": {\"send\": \"root\\n\"}}\n",
+				)
+				.as_bytes(),
+			)
+			.await
+			.unwrap();
+
+		for expected in [
+			Packet::BootfileSize { uefi: 1, bios: 2 },
+			Packet::Serial(b"reboot\n".as_slice().try_into().unwrap()),
+			Packet::TestResult {
+				name: "boots".try_into().unwrap(),
+				outcome: TestOutcome::Passed,
+				duration_ms: 0,
+				message: "".try_into().unwrap(),
+			},
+			Packet::EndTestSession,
+		] {
+			match broker_receiver.recv().await.unwrap() {
+				BrokerMessage::Client(ControlMessage::Packet(packet)) => {
+					assert_eq!(format!("{packet:?}"), format!("{expected:?}"));
+				}
+				unknown => panic!("unexpected message: {unknown:?}"),
+			}
+		}
+
+		assert!(matches!(
+			broker_receiver.recv().await.unwrap(),
+			BrokerMessage::Client(ControlMessage::Expect(ExpectRule {
+				action: Some(RuleAction::Send(text)),
+				..
+			})) if text == "root\n"
+		));
+
+		let hello =
+			ControlMessage::Packet(Packet::Serial(b"hello\n".as_slice().try_into().unwrap()));
+		let client = connections.publish(&hello).unwrap();
+		client.send(hello).await.unwrap();
+
+		// The error is written as the command is read, so it may come
+		// before or after the replay.
+		let mut lines = BufReader::new(&ours).lines();
+		let mut events = Vec::new();
+		for _ in 0..4 {
+			let event: serde_json::Value =
+				serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+			events.push(event);
+		}
+		let error = events.remove(
+			events
+				.iter()
+				.position(|event| event["type"] == "error")
+				.unwrap(),
+		);
+		assert!(
+			error["message"]
+				.as_str()
+				.unwrap()
+				.contains("press_the_button")
+		);
+
+		assert_eq!(
+			events,
+			[
+				serde_json::json!({"type": "serial", "data": "boot\n"}),
+				serde_json::json!({"type": "backlog_replayed"}),
+				serde_json::json!({"type": "serial", "data": "hello\n"}),
+			]
+		);
+
+		ours.shutdown(std::net::Shutdown::Both).unwrap();
+		assert_eq!(serving.await.unwrap(), Some(Role::Client));
+	}
+
+	#[async_std::test]
+	async fn observers_are_read_only() {
+		let (broker, broker_receiver) = make_bounded_channel(8);
+		let connections = Connections::new();
+		let (ours, theirs) = UnixStream::pair().unwrap();
+
+		let serving = task::spawn({
+			let connections = connections.clone();
+			async move { serve(BufReader::new(&theirs), &theirs, &broker, connections).await }
+		});
+
+		(&ours)
+			.write_all(b"{\"type\": \"observe\"}\n{\"type\": \"press_power\"}\n")
+			.await
+			.unwrap();
+
+		let mut lines = BufReader::new(&ours).lines();
+		let mut next_event = async || -> serde_json::Value {
+			serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap()
+		};
+
+		// The (empty) replay and the error may come in either order.
+		let mut first = [next_event().await, next_event().await];
+		first.sort_by_key(|event| event["type"].to_string());
+		assert_eq!(
+			first,
+			[
+				serde_json::json!({"type": "backlog_replayed"}),
+				serde_json::json!({"type": "error", "message": "observers can't control the session"}),
+			]
+		);
+
+		for packet in [
+			Packet::Serial(b"login: ".as_slice().try_into().unwrap()),
+			Packet::TestResult {
+				name: "boots".try_into().unwrap(),
+				outcome: TestOutcome::Skipped,
+				duration_ms: 0,
+				message: "".try_into().unwrap(),
+			},
+			Packet::SessionOutcome(SessionOutcome::TimedOut),
+		] {
+			connections.publish(&ControlMessage::Observed(packet));
+		}
+
+		assert_eq!(
+			next_event().await,
+			serde_json::json!({"type": "serial", "data": "login: "})
+		);
+		assert_eq!(
+			next_event().await,
+			serde_json::json!({
+				"type": "test_result",
+				"name": "boots",
+				"outcome": "skipped",
+				"duration_ms": 0,
+				"message": "",
+			})
+		);
+		assert_eq!(
+			next_event().await,
+			serde_json::json!({"type": "session_outcome", "outcome": "timed_out"})
+		);
+
+		ours.shutdown(std::net::Shutdown::Both).unwrap();
+		assert_eq!(serving.await.unwrap(), Some(Role::Observer));
+		assert!(broker_receiver.is_empty());
+	}
+}
@@ -0,0 +1,523 @@
+//! A report of how a session's tests went, built up from the session events
+//! the broker sends to the link and written to the session's artifacts
+//! directory (see [`crate::artifacts`]) once the session ends:
+//!
+//! - `junit.xml`, for CI systems to pick up
+//! - `summary.json`, with the session's details and every test's result
+//!
+//! Tests that were started but never reported a result (e.g. because the
+//! session timed out) are reported as incomplete, which JUnit calls an error.
+use crate::{artifacts::SessionArtifacts, fleet::unix_millis};
+use async_std::fs;
+use link_protocol::{Packet, SessionOutcome, TestOutcome};
+use serde::Serialize;
+use std::{
+	fmt::Write,
+	io,
+	path::PathBuf,
+	time::{Duration, Instant, SystemTime},
+};
+
+/// The name of a session outcome, as used in reports and JSON events.
+pub(super) fn session_outcome_name(outcome: SessionOutcome) -> &'static str {
+	match outcome {
+		SessionOutcome::Passed => "passed",
+		SessionOutcome::Failed => "failed",
+		SessionOutcome::TimedOut => "timed_out",
+		SessionOutcome::Killed => "killed",
+		_ => "unknown",
+	}
+}
+
+/// The name of a test outcome, as used in reports and JSON events.
+pub(super) fn test_outcome_name(outcome: TestOutcome) -> &'static str {
+	match outcome {
+		TestOutcome::Passed => "passed",
+		TestOutcome::Failed => "failed",
+		TestOutcome::Skipped => "skipped",
+		_ => "unknown",
+	}
+}
+
+/// The test outcome with the given name (see [`test_outcome_name`]).
+pub(super) fn parse_test_outcome(name: &str) -> Option<TestOutcome> {
+	match name {
+		"passed" => Some(TestOutcome::Passed),
+		"failed" => Some(TestOutcome::Failed),
+		"skipped" => Some(TestOutcome::Skipped),
+		_ => None,
+	}
+}
+
+/// How a test in the report went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+	Passed,
+	Failed,
+	Skipped,
+	/// Started, but never reported a result
+	Incomplete,
+}
+
+#[derive(Debug, Serialize)]
+struct TestCase {
+	name: String,
+	status: Status,
+	duration_ms: u64,
+	#[serde(skip_serializing_if = "String::is_empty")]
+	message: String,
+}
+
+/// The session's details, from `StartTestSession`.
+#[derive(Debug)]
+struct SessionInfo {
+	total_tests: u32,
+	author: String,
+	title: String,
+	ref_id: String,
+	started_at: SystemTime,
+	started: Instant,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+	link: &'a str,
+	author: &'a str,
+	title: &'a str,
+	ref_id: &'a str,
+	/// How many tests the client said it would run
+	total_tests: u32,
+	/// In milliseconds since the Unix epoch
+	started_at: u64,
+	duration_ms: u64,
+	outcome: Option<&'static str>,
+	passed: usize,
+	failed: usize,
+	skipped: usize,
+	incomplete: usize,
+	tests: &'a [TestCase],
+}
+
+/// The report for a single session.
+#[derive(Debug)]
+pub(crate) struct Report {
+	/// Where the report is written
+	artifacts: SessionArtifacts,
+	session: Option<SessionInfo>,
+	tests: Vec<TestCase>,
+	/// The test that's running, and when it started
+	running: Option<(String, Instant)>,
+	outcome: Option<SessionOutcome>,
+	duration: Duration,
+}
+
+impl Report {
+	pub fn new(artifacts: SessionArtifacts) -> Self {
+		Self {
+			artifacts,
+			session: None,
+			tests: Vec::new(),
+			running: None,
+			outcome: None,
+			duration: Duration::ZERO,
+		}
+	}
+
+	/// Records a packet sent to the link; anything but session
+	/// events is ignored.
+	pub fn record(&mut self, packet: &Packet) {
+		match packet {
+			Packet::StartTestSession {
+				total_tests,
+				author,
+				title,
+				ref_id,
+			} => {
+				self.session = Some(SessionInfo {
+					total_tests: *total_tests,
+					author: author.as_str().into(),
+					title: title.as_str().into(),
+					ref_id: ref_id.as_str().into(),
+					started_at: SystemTime::now(),
+					started: Instant::now(),
+				});
+			}
+			Packet::StartTest { name } => {
+				self.end_running_test();
+				self.running = Some((name.as_str().into(), Instant::now()));
+			}
+			Packet::TestResult {
+				name,
+				outcome,
+				duration_ms,
+				message,
+			} => {
+				// A result for some other test leaves the running one incomplete.
+				if self
+					.running
+					.as_ref()
+					.is_some_and(|(running, _)| running == name.as_str())
+				{
+					self.running = None;
+				}
+				self.end_running_test();
+
+				self.tests.push(TestCase {
+					name: name.as_str().into(),
+					status: match outcome {
+						TestOutcome::Passed => Status::Passed,
+						TestOutcome::Skipped => Status::Skipped,
+						_ => Status::Failed,
+					},
+					duration_ms: *duration_ms,
+					message: message.as_str().into(),
+				});
+			}
+			Packet::SessionOutcome(outcome) => {
+				self.end_running_test();
+				self.outcome = Some(*outcome);
+				if let Some(session) = &self.session {
+					self.duration = session.started.elapsed();
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Records the running test (if any) as incomplete.
+	fn end_running_test(&mut self) {
+		if let Some((name, started)) = self.running.take() {
+			self.tests.push(TestCase {
+				name,
+				status: Status::Incomplete,
+				duration_ms: started.elapsed().as_millis() as u64,
+				message: String::new(),
+			});
+		}
+	}
+
+	fn count(&self, status: Status) -> usize {
+		self.tests
+			.iter()
+			.filter(|test| test.status == status)
+			.count()
+	}
+
+	/// The report as JUnit XML; `None` if no session was started.
+	pub fn junit(&self) -> Option<String> {
+		let session = self.session.as_ref()?;
+
+		let suite = if session.title.is_empty() {
+			self.artifacts.link_id()
+		} else {
+			&session.title
+		};
+		let counts = format!(
+			r#"tests="{}" failures="{}" errors="{}" skipped="{}" time="{}""#,
+			self.tests.len(),
+			self.count(Status::Failed),
+			self.count(Status::Incomplete),
+			self.count(Status::Skipped),
+			seconds(self.duration.as_millis() as u64),
+		);
+
+		let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+		let _ = writeln!(xml, r#"<testsuites name="{}" {counts}>"#, escape(suite));
+		let _ = writeln!(
+			xml,
+			r#"  <testsuite name="{}" hostname="{}" {counts}>"#,
+			escape(suite),
+			escape(self.artifacts.link_id()),
+		);
+
+		xml.push_str("    <properties>\n");
+		for (name, value) in [
+			("author", session.author.as_str()),
+			("ref_id", &session.ref_id),
+			("outcome", self.outcome.map_or("", session_outcome_name)),
+		] {
+			let _ = writeln!(
+				xml,
+				r#"      <property name="{name}" value="{}"/>"#,
+				escape(value)
+			);
+		}
+		xml.push_str("    </properties>\n");
+
+		for test in &self.tests {
+			let _ = write!(
+				xml,
+				r#"    <testcase name="{}" classname="{}" time="{}""#,
+				escape(&test.name),
+				escape(suite),
+				seconds(test.duration_ms),
+			);
+
+			let message = escape(&test.message);
+			match test.status {
+				Status::Passed => xml.push_str("/>\n"),
+				Status::Failed => {
+					let _ = writeln!(
+						xml,
+						r#">
+      <failure message="{message}">{message}</failure>
+    </testcase>"#
+					);
+				}
+				Status::Skipped => {
+					let _ = writeln!(
+						xml,
+						r#">
+      <skipped message="{message}"/>
+    </testcase>"#
+					);
+				}
+				Status::Incomplete => {
+					xml.push_str(
+						r#">
+      <error message="test did not report a result"/>
+    </testcase>
+"#,
+					);
+				}
+			}
+		}
+
+		xml.push_str("  </testsuite>\n</testsuites>\n");
+		Some(xml)
+	}
+
+	/// The report as a JSON summary; `None` if no session was started.
+	pub fn summary(&self) -> Option<serde_json::Value> {
+		let session = self.session.as_ref()?;
+
+		Some(
+			serde_json::to_value(Summary {
+				link: self.artifacts.link_id(),
+				author: &session.author,
+				title: &session.title,
+				ref_id: &session.ref_id,
+				total_tests: session.total_tests,
+				started_at: unix_millis(session.started_at),
+				duration_ms: self.duration.as_millis() as u64,
+				outcome: self.outcome.map(session_outcome_name),
+				passed: self.count(Status::Passed),
+				failed: self.count(Status::Failed),
+				skipped: self.count(Status::Skipped),
+				incomplete: self.count(Status::Incomplete),
+				tests: &self.tests,
+			})
+			.unwrap(),
+		)
+	}
+
+	/// Writes the report to the session's artifacts directory, returning
+	/// the directory; `None` if no session was started. If the session
+	/// ended without an outcome (e.g. the link disconnected), the running
+	/// test is recorded as incomplete first.
+	pub async fn write(&mut self) -> io::Result<Option<PathBuf>> {
+		if self.outcome.is_none() {
+			self.end_running_test();
+			if let Some(session) = &self.session {
+				self.duration = session.started.elapsed();
+			}
+		}
+
+		let (Some(session), Some(junit), Some(summary)) =
+			(&self.session, self.junit(), self.summary())
+		else {
+			return Ok(None);
+		};
+
+		let dir = self.artifacts.dir(&session.ref_id);
+		fs::create_dir_all(&dir).await?;
+		fs::write(dir.join("junit.xml"), junit).await?;
+		fs::write(
+			dir.join("summary.json"),
+			serde_json::to_string_pretty(&summary).unwrap(),
+		)
+		.await?;
+
+		Ok(Some(dir))
+	}
+}
+
+/// Milliseconds as (fractional) seconds, as JUnit wants them.
+fn seconds(ms: u64) -> String {
+	format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// Escapes text for use in XML attributes and text, dropping
+/// characters XML can't represent at all.
+fn escape(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			'\t' | '\n' | '\r' => {
+				let _ = write!(escaped, "&#{};", c as u32);
+			}
+			c if c.is_control() => {}
+			c => escaped.push(c),
+		}
+	}
+
+	escaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::artifacts::tests::temp_root;
+	use std::path::Path;
+
+	fn result(name: &str, outcome: TestOutcome, message: &str) -> Packet {
+		Packet::TestResult {
+			name: name.try_into().unwrap(),
+			outcome,
+			duration_ms: 1500,
+			message: message.try_into().unwrap(),
+		}
+	}
+
+	fn start_test(name: &str) -> Packet {
+		Packet::StartTest {
+			name: name.try_into().unwrap(),
+		}
+	}
+
+	fn recorded(root: &Path) -> Report {
+		let mut report = Report::new(SessionArtifacts::new(root, "ABCD"));
+
+		for packet in [
+			Packet::StartTestSession {
+				total_tests: 4,
+				author: "author".try_into().unwrap(),
+				title: "kernel <tests>".try_into().unwrap(),
+				ref_id: "refs/heads/main".try_into().unwrap(),
+			},
+			start_test("boots"),
+			result("boots", TestOutcome::Passed, ""),
+			start_test("shell"),
+			result("shell", TestOutcome::Failed, "expected \"$\"\n"),
+			start_test("smp"),
+			result("smp", TestOutcome::Skipped, "single core"),
+			start_test("shutdown"),
+			Packet::SessionOutcome(SessionOutcome::TimedOut),
+		] {
+			report.record(&packet);
+		}
+
+		report
+	}
+
+	#[test]
+	fn writes_junit() {
+		assert_eq!(
+			Report::new(SessionArtifacts::new("/", "ABCD")).junit(),
+			None
+		);
+
+		let junit = recorded(Path::new("/")).junit().unwrap();
+
+		assert!(junit.contains(
+			r#"<testsuite name="kernel &lt;tests&gt;" hostname="ABCD" tests="4" failures="1" errors="1" skipped="1""#
+		));
+		assert!(junit.contains(r#"<property name="outcome" value="timed_out"/>"#));
+		assert!(
+			junit.contains(
+				r#"<testcase name="boots" classname="kernel &lt;tests&gt;" time="1.500"/>"#
+			)
+		);
+		assert!(junit.contains(
+			r#"<failure message="expected &quot;$&quot;&#10;">expected &quot;$&quot;&#10;</failure>"#
+		));
+		assert!(junit.contains(r#"<skipped message="single core"/>"#));
+		assert!(junit.contains(r#"<error message="test did not report a result"/>"#));
+		assert!(junit.ends_with("</testsuites>\n"));
+	}
+
+	#[test]
+	fn summarizes() {
+		let summary = recorded(Path::new("/")).summary().unwrap();
+
+		assert_eq!(summary["link"], "ABCD");
+		assert_eq!(summary["ref_id"], "refs/heads/main");
+		assert_eq!(summary["total_tests"], 4);
+		assert_eq!(summary["outcome"], "timed_out");
+		assert_eq!(
+			[
+				&summary["passed"],
+				&summary["failed"],
+				&summary["skipped"],
+				&summary["incomplete"]
+			],
+			[1, 1, 1, 1]
+		);
+		assert_eq!(
+			summary["tests"][1],
+			serde_json::json!({
+				"name": "shell",
+				"status": "failed",
+				"duration_ms": 1500,
+				"message": "expected \"$\"\n",
+			})
+		);
+		assert_eq!(summary["tests"][3]["status"], "incomplete");
+	}
+
+	#[async_std::test]
+	async fn writes_to_the_artifacts_directory() {
+		let root = temp_root("report");
+		let mut report = recorded(&root);
+
+		let dir = report.write().await.unwrap().unwrap();
+		assert!(dir.starts_with(root.join("ABCD/refs_heads_main")));
+		assert!(dir.join("junit.xml").exists());
+
+		let summary: serde_json::Value =
+			serde_json::from_slice(&std::fs::read(dir.join("summary.json")).unwrap()).unwrap();
+		assert_eq!(summary, report.summary().unwrap());
+
+		std::fs::remove_dir_all(root).unwrap();
+		assert_eq!(
+			Report::new(SessionArtifacts::new("/", "ABCD"))
+				.write()
+				.await
+				.unwrap(),
+			None
+		);
+	}
+
+	#[async_std::test]
+	async fn writes_sessions_that_were_cut_short() {
+		let root = temp_root("report");
+		let mut report = Report::new(SessionArtifacts::new(&root, "ABCD"));
+		for packet in [
+			Packet::StartTestSession {
+				total_tests: 1,
+				author: "".try_into().unwrap(),
+				title: "".try_into().unwrap(),
+				ref_id: "".try_into().unwrap(),
+			},
+			start_test("boots"),
+		] {
+			report.record(&packet);
+		}
+
+		// The link disconnected before the session had an outcome.
+		report.write().await.unwrap().unwrap();
+		let summary = report.summary().unwrap();
+		assert_eq!(summary["outcome"], serde_json::Value::Null);
+		assert_eq!(summary["tests"][0]["name"], "boots");
+		assert_eq!(summary["tests"][0]["status"], "incomplete");
+
+		std::fs::remove_dir_all(root).unwrap();
+	}
+}
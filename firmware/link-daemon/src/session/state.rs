@@ -0,0 +1,1069 @@
+//! The session state machine driven by the broker.
+//!
+//! ```text
+//! WaitingForClient ──▶ Configured ──▶ Booting ──▶ Running ──▶ Finishing ──▶ Done
+//!        │              │    │           │           ▲            ▲
+//!        │              │    └───────────┼───────────┘            │
+//!        │              └────────────────┴────────────────────────┤
+//!        └────────────────────────────────────────────────────────┘
+//! ```
+//!
+//! - **WaitingForClient**: the runner is starting up; waiting for its client
+//!   to connect and send both `BootfileSize` and `StartTestSession`.
+//! - **Configured**: the client has configured the session and the SUT has
+//!   been powered on; waiting for its first serial output, which moves to
+//!   `Booting` (or the first `StartTest`, for SUTs that stay quiet).
+//! - **Booting**: the SUT is booting; waiting for the first `StartTest`,
+//!   which moves to `Running`. The boot timeout counts from power-on, so it
+//!   covers `Configured` too.
+//! - **Running**: tests are running, each reporting a `TestResult`.
+//! - **Finishing**: the client has finished the session, either with
+//!   `EndTestSession` or by disconnecting (or a deadline passed); waiting
+//!   for the runner to exit.
+//! - **Done**: the runner has exited and its outcome is shown on the link.
+//!
+//! The runner may exit at any point, which moves straight to `Done`. The
+//! client may also finish (or disconnect) in any state before `Finishing`.
+//! A state's deadline (or one of the [`Watchdog`]'s) passing powers the
+//! SUT off, stops the runner and moves to `Finishing`; the session then
+//! ends as timed out, without waiting on a runner that doesn't stop within
+//! another finishing timeout. The daemon shutting down does the same, except
+//! that the outcome is only shown briefly before the link is disconnected.
+//!
+//! From power-on until the session finishes, the SUT's serial output is
+//! also matched against the session's [`Expectations`], which may send it
+//! text, reset or power-cycle it, or fail the session.
+//!
+//! Packets that don't make sense in the current state are rejected
+//! (and logged by the broker) rather than ending the session, as are any
+//! transitions not shown above.
+use super::{
+	LinkLog,
+	expect::{ExpectRule, Expectations, Reaction},
+	watchdog::{Expiry, Watchdog},
+};
+use link_protocol::{LogEntry, Packet, PowerState, Scene, SessionOutcome, TestOutcome};
+use serde::Serialize;
+use std::{
+	fmt,
+	time::{Duration, Instant},
+};
+
+/// Where a session is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SessionState {
+	WaitingForClient,
+	Configured,
+	Booting,
+	Running,
+	Finishing,
+	Done,
+}
+
+/// Something that happened in the session.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Event {
+	/// A packet from the link
+	Link(Packet),
+	/// A packet from the runner client
+	Client(Packet),
+	/// The client finished the session (or disconnected)
+	ClientEnded,
+	/// The runner exited, with the outcome derived from how it exited;
+	/// `None` if that isn't known, in which case the outcome follows from
+	/// what the client reported
+	RunnerExited(Option<SessionOutcome>),
+	/// A deadline passed (see [`SessionMachine::deadline`])
+	Timeout(Expiry),
+	/// The client added a rule for the serial output
+	Expect(ExpectRule),
+	/// The expectations asked to be woken up (see [`SessionMachine::wakeup`])
+	Wakeup,
+	/// The daemon is shutting down
+	Shutdown,
+}
+
+/// What the broker should do in response to an event.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Action {
+	/// Send a packet to the link
+	Link(Packet),
+	/// Send a packet to the runner client
+	Client(Packet),
+	/// Tell the runner the session is finished; it's given
+	/// some time to exit on its own
+	FinishRunner,
+	/// Stop the runner right away
+	StopRunner,
+	/// Leave the outcome up on the link for the given time, then disconnect it
+	End(Duration),
+}
+
+/// How long the session may stay in each state; `None` for no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StateTimeouts {
+	pub waiting_for_client: Option<Duration>,
+	pub booting: Option<Duration>,
+	pub finishing: Option<Duration>,
+	/// How long the outcome is shown once done
+	pub outcome_hold: Duration,
+}
+
+/// A packet (or other event) that isn't allowed in the current state.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Rejected {
+	pub state: SessionState,
+	pub event: String,
+}
+
+impl fmt::Display for Rejected {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} not allowed while {:?}", self.event, self.state)
+	}
+}
+
+/// How long the outcome is shown when the daemon is shutting down.
+const SHUTDOWN_OUTCOME_HOLD: Duration = Duration::from_secs(2);
+
+pub(crate) struct SessionMachine {
+	state: SessionState,
+	entered_at: Instant,
+	/// When the SUT was powered on, which the boot timeout counts from
+	powered_on_at: Option<Instant>,
+	timeouts: StateTimeouts,
+	watchdog: Watchdog,
+	expectations: Expectations,
+	has_bootfile_size: bool,
+	has_test_session: bool,
+	/// Whether the client ended the session with `EndTestSession`, in which
+	/// case its disconnecting afterwards is expected
+	client_ended_session: bool,
+	/// Whether the client ended the session with `EndTestSession` at all
+	ended_by_client: bool,
+	/// Whether any test the client reported failed
+	test_failed: bool,
+	/// Overrides the runner's outcome (e.g. when a deadline passed)
+	outcome: Option<SessionOutcome>,
+	/// Whether the runner was told to stop; it's only waited on for
+	/// one more finishing timeout after that
+	runner_stopped: bool,
+	shutting_down: bool,
+}
+
+impl SessionMachine {
+	pub fn new(timeouts: StateTimeouts, watchdog: Watchdog, expectations: Expectations) -> Self {
+		Self {
+			state: SessionState::WaitingForClient,
+			entered_at: Instant::now(),
+			powered_on_at: None,
+			timeouts,
+			watchdog,
+			expectations,
+			has_bootfile_size: false,
+			has_test_session: false,
+			client_ended_session: false,
+			ended_by_client: false,
+			test_failed: false,
+			outcome: None,
+			runner_stopped: false,
+			shutting_down: false,
+		}
+	}
+
+	pub fn state(&self) -> SessionState {
+		self.state
+	}
+
+	/// The next deadline, if any, and what passing it means. Once the
+	/// session is finishing, only the state's own deadline applies.
+	pub fn deadline(&self) -> Option<(Instant, Expiry)> {
+		let (timeout, since) = match self.state {
+			SessionState::WaitingForClient => (self.timeouts.waiting_for_client, self.entered_at),
+			SessionState::Configured | SessionState::Booting => (
+				self.timeouts.booting,
+				self.powered_on_at.unwrap_or(self.entered_at),
+			),
+			SessionState::Finishing => (self.timeouts.finishing, self.entered_at),
+			SessionState::Running | SessionState::Done => (None, self.entered_at),
+		};
+		let state = timeout.map(|timeout| (since + timeout, Expiry::State(self.state)));
+
+		let watchdog = match self.state {
+			SessionState::Finishing | SessionState::Done => None,
+			_ => self.watchdog.deadline(),
+		};
+
+		[state, watchdog]
+			.into_iter()
+			.flatten()
+			.min_by_key(|(at, _)| *at)
+	}
+
+	/// When the expectations need to be polled with [`Event::Wakeup`],
+	/// if at all; they only apply from power-on until the session finishes.
+	pub fn wakeup(&self) -> Option<Instant> {
+		match self.state {
+			SessionState::Configured | SessionState::Booting | SessionState::Running => {
+				self.expectations.wakeup()
+			}
+			_ => None,
+		}
+	}
+
+	/// Moves to `state`, refusing transitions the machine doesn't allow.
+	fn transition(&mut self, state: SessionState) -> Result<(), Rejected> {
+		if !self.allows(state) {
+			log::error!(
+				"invalid session state transition: {:?} -> {state:?}",
+				self.state
+			);
+			return Err(Rejected {
+				state: self.state,
+				event: format!("moving to {state:?}"),
+			});
+		}
+
+		log::debug!("session state: {:?} -> {state:?}", self.state);
+		self.state = state;
+		self.entered_at = Instant::now();
+		Ok(())
+	}
+
+	/// Whether the current state may transition to `next`.
+	fn allows(&self, next: SessionState) -> bool {
+		use SessionState::*;
+
+		matches!(
+			(self.state, next),
+			(WaitingForClient, Configured)
+				| (Configured, Booting)
+				| (Configured | Booting, Running)
+				| (WaitingForClient | Configured | Booting | Running, Finishing)
+				| (
+					WaitingForClient | Configured | Booting | Running | Finishing,
+					Done
+				)
+		)
+	}
+
+	fn reject(&self, event: impl Into<String>) -> Result<Vec<Action>, Rejected> {
+		Err(Rejected {
+			state: self.state,
+			event: event.into(),
+		})
+	}
+
+	/// Handles an event, returning what the broker should do.
+	pub fn handle(&mut self, event: Event) -> Result<Vec<Action>, Rejected> {
+		use SessionState::*;
+
+		match (self.state, event) {
+			(Done, Event::Shutdown) => {
+				// The broker cuts the outcome hold short itself.
+				self.shutting_down = true;
+				Ok(vec![])
+			}
+			// The SUT may still print while the outcome is shown.
+			(Done, Event::Link(Packet::Serial(_))) => Ok(vec![]),
+			(Done, event) => self.reject(format!("{event:?}")),
+
+			(_, Event::RunnerExited(outcome)) => self.done(outcome),
+
+			(_, Event::Link(Packet::Serial(data))) => {
+				self.watchdog.serial_seen();
+
+				// The SUT has shown signs of life.
+				if self.state == Configured && !data.is_empty() {
+					self.transition(Booting)?;
+				}
+
+				let reactions = match self.state {
+					Booting | Running => self.expectations.feed(&data, Instant::now()),
+					_ => vec![],
+				};
+
+				let mut actions = vec![Action::Client(Packet::Serial(data))];
+				actions.extend(self.react(reactions)?);
+				Ok(actions)
+			}
+			(_, Event::Link(packet)) => self.reject(format!("link packet {:?}", packet.kind())),
+
+			(Finishing, Event::ClientEnded) if self.client_ended_session => {
+				self.client_ended_session = false;
+				Ok(vec![])
+			}
+			(Finishing, Event::ClientEnded) => self.reject("client ending the session"),
+			(_, Event::ClientEnded) => self.finish(),
+
+			(Finishing, Event::Timeout(_)) if self.runner_stopped => {
+				// Don't keep the link waiting on a runner that won't stop.
+				log::warn!("runner didn't stop in time; ending the session without it");
+				self.outcome.get_or_insert(SessionOutcome::TimedOut);
+				self.done(None)
+			}
+			(Finishing, Event::Timeout(_)) => {
+				// The runner was already told to finish; stop it.
+				self.outcome = Some(SessionOutcome::TimedOut);
+				self.runner_stopped = true;
+				self.entered_at = Instant::now();
+				Ok(vec![Action::StopRunner])
+			}
+			(_, Event::Timeout(expiry)) => {
+				self.abort(SessionOutcome::TimedOut, &expiry.to_string())
+			}
+
+			(Finishing, Event::Expect(_)) => self.reject("client adding an expectation"),
+			(_, Event::Expect(rule)) => {
+				self.expectations.add(rule, Instant::now());
+				Ok(vec![])
+			}
+
+			(Configured | Booting | Running, Event::Wakeup) => {
+				let reactions = self.expectations.poll(Instant::now());
+				self.react(reactions)
+			}
+			// The expectations were put on hold in the meantime.
+			(_, Event::Wakeup) => Ok(vec![]),
+
+			(_, Event::Shutdown) => {
+				if self.state != Finishing {
+					self.transition(Finishing)?;
+				}
+				self.shutting_down = true;
+				// Whatever already decided the session's outcome still does.
+				self.outcome.get_or_insert(SessionOutcome::Killed);
+
+				let mut actions = vec![
+					Action::Link(Packet::SetPowerState(PowerState::Off)),
+					Action::Link(Packet::SetScene(Scene::Log)),
+					Action::Link(Packet::Log(LogEntry::Warn(
+						"link daemon is shutting down; session ended"
+							.try_into()
+							.unwrap(),
+					))),
+				];
+				if !self.runner_stopped {
+					self.runner_stopped = true;
+					actions.push(Action::StopRunner);
+				}
+				Ok(actions)
+			}
+
+			(_, Event::Client(packet)) => self.handle_client(packet),
+		}
+	}
+
+	fn handle_client(&mut self, packet: Packet) -> Result<Vec<Action>, Rejected> {
+		use SessionState::*;
+
+		let kind = packet.kind();
+
+		match (self.state, packet) {
+			(Finishing, _) => self.reject(format!("client packet {kind:?}")),
+
+			(_, packet @ Packet::Serial(_)) => Ok(vec![Action::Link(packet)]),
+
+			(
+				Configured | Booting | Running,
+				packet @ (Packet::PressPower | Packet::PressReset),
+			) => Ok(vec![Action::Link(packet)]),
+
+			(WaitingForClient, packet @ Packet::BootfileSize { .. }) => {
+				self.has_bootfile_size = true;
+				self.configure(packet)
+			}
+			(WaitingForClient, packet @ Packet::StartTestSession { .. }) => {
+				self.has_test_session = true;
+				self.configure(packet)
+			}
+
+			(Configured | Booting, packet @ Packet::StartTest { .. }) => {
+				self.transition(Running)?;
+				self.watchdog.test_started();
+				Ok(vec![
+					// Switch to the testing scene
+					Action::Link(Packet::SetScene(Scene::Test)),
+					Action::Link(packet),
+				])
+			}
+			(Running, packet @ Packet::StartTest { .. }) => {
+				self.watchdog.test_started();
+				Ok(vec![Action::Link(packet)])
+			}
+			(Running, packet @ Packet::TestResult { outcome, .. }) => {
+				self.watchdog.test_finished();
+				self.test_failed |= outcome == TestOutcome::Failed;
+				Ok(vec![Action::Link(packet)])
+			}
+
+			(WaitingForClient | Configured | Booting | Running, Packet::EndTestSession) => {
+				let actions = self.finish()?;
+				self.client_ended_session = true;
+				self.ended_by_client = true;
+				Ok(actions)
+			}
+
+			_ => self.reject(format!("client packet {kind:?}")),
+		}
+	}
+
+	/// Powers the SUT off and stops the runner, ending the session
+	/// with the given outcome.
+	fn abort(&mut self, outcome: SessionOutcome, message: &str) -> Result<Vec<Action>, Rejected> {
+		self.transition(SessionState::Finishing)?;
+		self.outcome = Some(outcome);
+		self.runner_stopped = true;
+
+		let message = LinkLog::truncate(message);
+
+		Ok(vec![
+			Action::Link(Packet::SetPowerState(PowerState::Off)),
+			Action::Link(Packet::Log(LogEntry::Error(message.try_into().unwrap()))),
+			Action::StopRunner,
+		])
+	}
+
+	/// Carries out what the expectations decided.
+	fn react(&mut self, reactions: Vec<Reaction>) -> Result<Vec<Action>, Rejected> {
+		let mut actions = Vec::new();
+
+		for reaction in reactions {
+			log::info!("serial expectation: {reaction:?}");
+
+			match reaction {
+				Reaction::Send(text) => {
+					actions.extend(
+						text.as_bytes()
+							.chunks(256)
+							.map(|chunk| Action::Link(Packet::Serial(chunk.try_into().unwrap()))),
+					);
+				}
+				Reaction::Reset => actions.push(Action::Link(Packet::PressReset)),
+				Reaction::PowerOff => {
+					actions.push(Action::Link(Packet::SetPowerState(PowerState::Off)));
+				}
+				Reaction::PowerOn => {
+					self.watchdog.powered_on();
+					actions.extend([
+						Action::Link(Packet::SetPowerState(PowerState::On)),
+						Action::Link(Packet::PressPower),
+					]);
+				}
+				Reaction::Fail(message) => {
+					actions.extend(self.abort(SessionOutcome::Failed, &message)?);
+					// The SUT is off and the session is over; nothing
+					// after this applies.
+					break;
+				}
+			}
+		}
+
+		Ok(actions)
+	}
+
+	/// The outcome as far as the client reported it, for when the runner's
+	/// exit doesn't tell: it passed if the client ended the session without
+	/// any test failing.
+	fn reported_outcome(&self) -> SessionOutcome {
+		if self.ended_by_client && !self.test_failed {
+			SessionOutcome::Passed
+		} else {
+			SessionOutcome::Failed
+		}
+	}
+
+	/// Shows the session's outcome on the link, then disconnects it; the
+	/// runner's `outcome` only counts if nothing else decided it.
+	fn done(&mut self, outcome: Option<SessionOutcome>) -> Result<Vec<Action>, Rejected> {
+		self.transition(SessionState::Done)?;
+		let outcome = self
+			.outcome
+			.or(outcome)
+			.unwrap_or_else(|| self.reported_outcome());
+
+		let hold = if self.shutting_down {
+			SHUTDOWN_OUTCOME_HOLD
+		} else {
+			self.timeouts.outcome_hold
+		};
+
+		Ok(vec![
+			Action::Link(Packet::SetScene(Scene::Test)),
+			Action::Link(Packet::SessionOutcome(outcome)),
+			Action::End(hold),
+		])
+	}
+
+	/// Tells the runner to finish once the client is done with the session.
+	fn finish(&mut self) -> Result<Vec<Action>, Rejected> {
+		self.transition(SessionState::Finishing)?;
+		Ok(vec![Action::FinishRunner])
+	}
+
+	/// Forwards a configuration packet, powering the SUT on once
+	/// the session is fully configured.
+	fn configure(&mut self, packet: Packet) -> Result<Vec<Action>, Rejected> {
+		let mut actions = vec![Action::Link(packet)];
+
+		if self.has_bootfile_size && self.has_test_session {
+			self.transition(SessionState::Configured)?;
+
+			actions.extend([
+				// Turn on the monitor
+				Action::Link(Packet::SetMonitorStandby(false)),
+				// Then set the scene to the logo
+				Action::Link(Packet::SetScene(Scene::Logo)),
+				// Turn on the machine
+				Action::Link(Packet::SetPowerState(PowerState::On)),
+				// Press the power button
+				Action::Link(Packet::PressPower),
+			]);
+
+			let now = Instant::now();
+			self.powered_on_at = Some(now);
+			self.watchdog.powered_on();
+			self.expectations.powered_on(now);
+		}
+
+		Ok(actions)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::session::watchdog::WatchdogLimits;
+	use link_protocol::PacketKind;
+
+	/// The packet kinds of the actions that send packets to the link,
+	/// for terse assertions.
+	fn link_kinds(actions: &[Action]) -> Vec<PacketKind> {
+		actions
+			.iter()
+			.filter_map(|action| match action {
+				Action::Link(packet) => Some(packet.kind()),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// A machine without any watchdog limits or expectations.
+	fn new_machine(timeouts: StateTimeouts) -> SessionMachine {
+		SessionMachine::new(
+			timeouts,
+			Watchdog::new(WatchdogLimits::default()),
+			Expectations::new([]),
+		)
+	}
+
+	fn serial(data: &[u8]) -> Event {
+		Event::Link(Packet::Serial(data.try_into().unwrap()))
+	}
+
+	fn configured(timeouts: StateTimeouts) -> SessionMachine {
+		let mut machine = new_machine(timeouts);
+		configure(&mut machine);
+		machine
+	}
+
+	fn configure(machine: &mut SessionMachine) {
+		machine
+			.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+			.unwrap();
+		machine
+			.handle(Event::Client(Packet::StartTestSession {
+				total_tests: 2,
+				author: "author".try_into().unwrap(),
+				title: "title".try_into().unwrap(),
+				ref_id: "ref".try_into().unwrap(),
+			}))
+			.unwrap();
+	}
+
+	fn start_test() -> Event {
+		Event::Client(Packet::StartTest {
+			name: "test".try_into().unwrap(),
+		})
+	}
+
+	#[test]
+	fn runs_a_session_to_completion() {
+		let mut machine = new_machine(StateTimeouts::default());
+		assert_eq!(machine.state(), SessionState::WaitingForClient);
+
+		let actions = machine
+			.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+			.unwrap();
+		assert_eq!(link_kinds(&actions), vec![PacketKind::BootfileSize]);
+		assert_eq!(machine.state(), SessionState::WaitingForClient);
+
+		let mut machine = configured(StateTimeouts::default());
+		assert_eq!(machine.state(), SessionState::Configured);
+
+		// The SUT shows signs of life
+		let actions = machine.handle(serial(b"BdsDxe: loading")).unwrap();
+		assert!(matches!(actions[..], [Action::Client(Packet::Serial(_))]));
+		assert_eq!(machine.state(), SessionState::Booting);
+
+		let actions = machine.handle(start_test()).unwrap();
+		assert_eq!(
+			link_kinds(&actions),
+			vec![PacketKind::SetScene, PacketKind::StartTest]
+		);
+		assert_eq!(machine.state(), SessionState::Running);
+
+		let actions = machine.handle(start_test()).unwrap();
+		assert_eq!(link_kinds(&actions), vec![PacketKind::StartTest]);
+
+		let actions = machine.handle(Event::ClientEnded).unwrap();
+		assert!(matches!(actions[..], [Action::FinishRunner]));
+		assert_eq!(machine.state(), SessionState::Finishing);
+
+		let actions = machine
+			.handle(Event::RunnerExited(Some(SessionOutcome::Passed)))
+			.unwrap();
+		assert!(matches!(
+			actions[..],
+			[
+				Action::Link(Packet::SetScene(Scene::Test)),
+				Action::Link(Packet::SessionOutcome(SessionOutcome::Passed)),
+				Action::End(_),
+			]
+		));
+		assert_eq!(machine.state(), SessionState::Done);
+	}
+
+	#[test]
+	fn reports_results_and_ends_the_session() {
+		let mut machine = SessionMachine::new(
+			StateTimeouts::default(),
+			Watchdog::new(WatchdogLimits {
+				test: Some(Duration::from_secs(60)),
+				..Default::default()
+			}),
+			Expectations::new([]),
+		);
+		machine
+			.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+			.unwrap();
+		machine
+			.handle(Event::Client(Packet::StartTestSession {
+				total_tests: 1,
+				author: "".try_into().unwrap(),
+				title: "".try_into().unwrap(),
+				ref_id: "".try_into().unwrap(),
+			}))
+			.unwrap();
+
+		let result = || {
+			Event::Client(Packet::TestResult {
+				name: "test".try_into().unwrap(),
+				outcome: TestOutcome::Passed,
+				duration_ms: 12,
+				message: "".try_into().unwrap(),
+			})
+		};
+
+		// Results only make sense once a test has started
+		assert!(machine.handle(result()).is_err());
+
+		machine.handle(start_test()).unwrap();
+		assert!(machine.deadline().is_some());
+
+		let actions = machine.handle(result()).unwrap();
+		assert_eq!(link_kinds(&actions), vec![PacketKind::TestResult]);
+		assert_eq!(machine.deadline(), None);
+
+		let actions = machine
+			.handle(Event::Client(Packet::EndTestSession))
+			.unwrap();
+		assert!(matches!(actions[..], [Action::FinishRunner]));
+		assert_eq!(machine.state(), SessionState::Finishing);
+
+		// The client then disconnects, which is expected (but only once)
+		assert!(machine.handle(Event::ClientEnded).unwrap().is_empty());
+		assert!(machine.handle(Event::ClientEnded).is_err());
+		assert!(
+			machine
+				.handle(Event::Client(Packet::EndTestSession))
+				.is_err()
+		);
+	}
+
+	#[test]
+	fn unknown_exits_take_the_reported_outcome() {
+		let outcome = |results: &[TestOutcome], end: Event| {
+			let mut machine = configured(StateTimeouts::default());
+			machine.handle(start_test()).unwrap();
+			for &outcome in results {
+				machine
+					.handle(Event::Client(Packet::TestResult {
+						name: "test".try_into().unwrap(),
+						outcome,
+						duration_ms: 12,
+						message: "".try_into().unwrap(),
+					}))
+					.unwrap();
+			}
+			machine.handle(end).unwrap();
+
+			match machine.handle(Event::RunnerExited(None)).unwrap()[..] {
+				[_, Action::Link(Packet::SessionOutcome(outcome)), _] => outcome,
+				ref actions => panic!("unexpected actions: {actions:?}"),
+			}
+		};
+		let end_session = || Event::Client(Packet::EndTestSession);
+
+		assert_eq!(
+			outcome(&[TestOutcome::Passed, TestOutcome::Skipped], end_session()),
+			SessionOutcome::Passed
+		);
+		assert_eq!(
+			outcome(&[TestOutcome::Passed, TestOutcome::Failed], end_session()),
+			SessionOutcome::Failed
+		);
+		// The client went away without ending the session.
+		assert_eq!(
+			outcome(&[TestOutcome::Passed], Event::ClientEnded),
+			SessionOutcome::Failed
+		);
+	}
+
+	#[test]
+	fn powers_on_once_configured() {
+		let mut machine = new_machine(StateTimeouts::default());
+		machine
+			.handle(Event::Client(Packet::StartTestSession {
+				total_tests: 0,
+				author: "".try_into().unwrap(),
+				title: "".try_into().unwrap(),
+				ref_id: "".try_into().unwrap(),
+			}))
+			.unwrap();
+
+		let actions = machine
+			.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+			.unwrap();
+		assert_eq!(
+			link_kinds(&actions),
+			vec![
+				PacketKind::BootfileSize,
+				PacketKind::SetMonitorStandby,
+				PacketKind::SetScene,
+				PacketKind::SetPowerState,
+				PacketKind::PressPower,
+			]
+		);
+		assert_eq!(machine.state(), SessionState::Configured);
+
+		// SUTs that don't print anything go straight to running tests
+		machine.handle(start_test()).unwrap();
+		assert_eq!(machine.state(), SessionState::Running);
+	}
+
+	#[test]
+	fn rejects_out_of_order_packets() {
+		let mut machine = new_machine(StateTimeouts::default());
+
+		assert_eq!(
+			machine.handle(start_test()).unwrap_err(),
+			Rejected {
+				state: SessionState::WaitingForClient,
+				event: "client packet StartTest".into(),
+			}
+		);
+		assert!(machine.handle(Event::Client(Packet::PressReset)).is_err());
+		assert!(machine.handle(Event::Link(Packet::PressPower)).is_err());
+		assert_eq!(machine.state(), SessionState::WaitingForClient);
+
+		// Serial is always passed through
+		assert!(
+			machine
+				.handle(Event::Client(Packet::Serial(Default::default())))
+				.is_ok()
+		);
+
+		let mut machine = configured(StateTimeouts::default());
+		assert!(
+			machine
+				.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+				.is_err()
+		);
+		assert!(machine.handle(Event::Client(Packet::PressReset)).is_ok());
+		assert_eq!(machine.state(), SessionState::Configured);
+
+		machine.handle(Event::ClientEnded).unwrap();
+		assert!(machine.handle(start_test()).is_err());
+		assert!(machine.handle(Event::ClientEnded).is_err());
+	}
+
+	#[test]
+	fn refuses_invalid_transitions() {
+		let mut machine = new_machine(StateTimeouts::default());
+
+		assert_eq!(
+			machine.transition(SessionState::Running),
+			Err(Rejected {
+				state: SessionState::WaitingForClient,
+				event: "moving to Running".into(),
+			})
+		);
+		assert_eq!(machine.state(), SessionState::WaitingForClient);
+
+		machine.transition(SessionState::Done).unwrap();
+		for state in [SessionState::Configured, SessionState::Finishing] {
+			assert!(machine.transition(state).is_err());
+		}
+		assert_eq!(machine.state(), SessionState::Done);
+	}
+
+	#[test]
+	fn times_out() {
+		let timeouts = StateTimeouts {
+			booting: Some(Duration::from_secs(60)),
+			..Default::default()
+		};
+		assert_eq!(new_machine(timeouts).deadline(), None);
+
+		let mut machine = configured(timeouts);
+		let (at, expiry) = machine.deadline().unwrap();
+		assert_eq!(expiry, Expiry::State(SessionState::Configured));
+
+		// The boot timeout counts from power-on, not from the first output.
+		machine.handle(serial(b"booting")).unwrap();
+		assert_eq!(
+			machine.deadline(),
+			Some((at, Expiry::State(SessionState::Booting)))
+		);
+
+		let actions = machine
+			.handle(Event::Timeout(Expiry::State(SessionState::Booting)))
+			.unwrap();
+		assert_eq!(
+			link_kinds(&actions),
+			vec![PacketKind::SetPowerState, PacketKind::Log]
+		);
+		assert!(matches!(actions.last(), Some(Action::StopRunner)));
+		assert_eq!(machine.state(), SessionState::Finishing);
+
+		// However the runner exits, the session timed out
+		let actions = machine
+			.handle(Event::RunnerExited(Some(SessionOutcome::Killed)))
+			.unwrap();
+		assert!(matches!(
+			actions[1],
+			Action::Link(Packet::SessionOutcome(SessionOutcome::TimedOut))
+		));
+	}
+
+	#[test]
+	fn runner_may_exit_early() {
+		let mut machine = new_machine(StateTimeouts::default());
+		let actions = machine
+			.handle(Event::RunnerExited(Some(SessionOutcome::Failed)))
+			.unwrap();
+		assert!(matches!(
+			actions[1],
+			Action::Link(Packet::SessionOutcome(SessionOutcome::Failed))
+		));
+		assert_eq!(machine.state(), SessionState::Done);
+
+		// The SUT's output is dropped quietly; anything else is rejected.
+		assert!(machine.handle(serial(b"login: ")).unwrap().is_empty());
+		assert!(machine.handle(Event::ClientEnded).is_err());
+	}
+
+	#[test]
+	fn stops_the_runner_once() {
+		let timeouts = StateTimeouts {
+			finishing: Some(Duration::from_secs(60)),
+			..Default::default()
+		};
+		let mut machine = configured(timeouts);
+		machine.handle(Event::ClientEnded).unwrap();
+
+		let expiry = Expiry::State(SessionState::Finishing);
+		let actions = machine.handle(Event::Timeout(expiry)).unwrap();
+		assert!(matches!(actions[..], [Action::StopRunner]));
+		assert_eq!(machine.state(), SessionState::Finishing);
+		assert!(machine.deadline().is_some());
+
+		// It still hasn't exited; the session ends without it.
+		let actions = machine.handle(Event::Timeout(expiry)).unwrap();
+		assert!(matches!(
+			actions[..],
+			[
+				_,
+				Action::Link(Packet::SessionOutcome(SessionOutcome::TimedOut)),
+				Action::End(_),
+			]
+		));
+		assert_eq!(machine.state(), SessionState::Done);
+	}
+
+	#[test]
+	fn watchdog_applies_until_finishing() {
+		let mut machine = SessionMachine::new(
+			StateTimeouts::default(),
+			Watchdog::new(WatchdogLimits {
+				test: Some(Duration::from_secs(60)),
+				..Default::default()
+			}),
+			Expectations::new([]),
+		);
+		machine
+			.handle(Event::Client(Packet::BootfileSize { uefi: 1, bios: 2 }))
+			.unwrap();
+		machine
+			.handle(Event::Client(Packet::StartTestSession {
+				total_tests: 1,
+				author: "".try_into().unwrap(),
+				title: "".try_into().unwrap(),
+				ref_id: "".try_into().unwrap(),
+			}))
+			.unwrap();
+		assert_eq!(machine.deadline(), None);
+
+		machine.handle(start_test()).unwrap();
+		let (_, expiry) = machine.deadline().unwrap();
+		assert_eq!(expiry, Expiry::Test(Duration::from_secs(60)));
+
+		let actions = machine.handle(Event::Timeout(expiry)).unwrap();
+		assert!(matches!(
+			&actions[1],
+			Action::Link(Packet::Log(LogEntry::Error(message)))
+				if message.as_str() == "test exceeded its time limit of 60s"
+		));
+		assert_eq!(machine.state(), SessionState::Finishing);
+		assert_eq!(machine.deadline(), None);
+	}
+
+	#[test]
+	fn applies_expectations() {
+		let rule = |rule| serde_json::from_value::<ExpectRule>(rule).unwrap();
+		let mut machine = SessionMachine::new(
+			StateTimeouts::default(),
+			Watchdog::new(WatchdogLimits::default()),
+			Expectations::new([rule(serde_json::json!({
+				"pattern": "PXE-E\\d+",
+				"action": "power-cycle",
+			}))]),
+		);
+
+		// Nothing happens until the SUT is on
+		let actions = machine.handle(serial(b"PXE-E18\r\n")).unwrap();
+		assert!(matches!(actions[..], [Action::Client(_)]));
+		assert_eq!(machine.wakeup(), None);
+
+		configure(&mut machine);
+		machine
+			.handle(Event::Expect(rule(serde_json::json!({
+				"pattern": "login: $",
+				"action": {"send": "root\n"},
+			}))))
+			.unwrap();
+
+		let actions = machine.handle(serial(b"oro login: ")).unwrap();
+		assert!(matches!(
+			&actions[..],
+			[Action::Client(_), Action::Link(Packet::Serial(data))] if data == b"root\n"
+		));
+
+		let actions = machine.handle(serial(b"  PXE-E18: timeout\r\n")).unwrap();
+		assert_eq!(link_kinds(&actions), vec![PacketKind::SetPowerState]);
+		assert!(machine.wakeup().is_some());
+		// The SUT stays off for a while
+		assert!(machine.handle(Event::Wakeup).unwrap().is_empty());
+
+		let mut machine = SessionMachine::new(
+			StateTimeouts::default(),
+			Watchdog::new(WatchdogLimits::default()),
+			Expectations::new([rule(serde_json::json!({
+				"pattern": "kernel panic",
+				"action": "fail",
+			}))]),
+		);
+		configure(&mut machine);
+
+		let actions = machine.handle(serial(b"kernel panic!\r\n")).unwrap();
+		assert_eq!(
+			link_kinds(&actions),
+			vec![PacketKind::SetPowerState, PacketKind::Log]
+		);
+		assert!(matches!(actions.last(), Some(Action::StopRunner)));
+		assert_eq!(machine.state(), SessionState::Finishing);
+		assert!(
+			machine
+				.handle(Event::Expect(rule(serde_json::json!({
+					"pattern": "x",
+					"action": "reset",
+				}))))
+				.is_err()
+		);
+
+		let actions = machine
+			.handle(Event::RunnerExited(Some(SessionOutcome::Killed)))
+			.unwrap();
+		assert!(matches!(
+			actions[1],
+			Action::Link(Packet::SessionOutcome(SessionOutcome::Failed))
+		));
+	}
+
+	#[test]
+	fn shuts_down() {
+		let mut machine = configured(StateTimeouts {
+			outcome_hold: Duration::from_secs(30),
+			..Default::default()
+		});
+
+		let actions = machine.handle(Event::Shutdown).unwrap();
+		assert_eq!(
+			link_kinds(&actions),
+			vec![
+				PacketKind::SetPowerState,
+				PacketKind::SetScene,
+				PacketKind::Log
+			]
+		);
+		assert!(matches!(actions.last(), Some(Action::StopRunner)));
+		assert_eq!(machine.state(), SessionState::Finishing);
+
+		let actions = machine
+			.handle(Event::RunnerExited(Some(SessionOutcome::Killed)))
+			.unwrap();
+		assert!(matches!(
+			actions[..],
+			[
+				_,
+				Action::Link(Packet::SessionOutcome(SessionOutcome::Killed)),
+				Action::End(SHUTDOWN_OUTCOME_HOLD),
+			]
+		));
+	}
+
+	#[test]
+	fn shutting_down_keeps_a_decided_outcome() {
+		let mut machine = configured(StateTimeouts::default());
+		machine
+			.handle(Event::Timeout(Expiry::State(SessionState::Booting)))
+			.unwrap();
+
+		// The runner was already stopped.
+		let actions = machine.handle(Event::Shutdown).unwrap();
+		assert!(!actions.iter().any(|a| matches!(a, Action::StopRunner)));
+
+		let actions = machine.handle(Event::RunnerExited(None)).unwrap();
+		assert!(matches!(
+			actions[..],
+			[
+				_,
+				Action::Link(Packet::SessionOutcome(SessionOutcome::TimedOut)),
+				Action::End(SHUTDOWN_OUTCOME_HOLD),
+			]
+		));
+	}
+}
@@ -0,0 +1,136 @@
+//! Deadlines that bound a session as a whole, regardless of its state:
+//! how long the session may take, how long a single test may take, and
+//! how long the SUT may go without any serial output once powered on.
+use super::SessionState;
+use std::{
+	fmt,
+	time::{Duration, Instant},
+};
+
+/// The watchdog's limits; `None` for no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct WatchdogLimits {
+	/// How long the whole session may take
+	pub session: Option<Duration>,
+	/// How long a single test may take
+	pub test: Option<Duration>,
+	/// How long the SUT may go without serial output after being powered on
+	pub serial_inactivity: Option<Duration>,
+}
+
+/// Why a session timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expiry {
+	/// The session stayed in a state for too long
+	State(SessionState),
+	Session(Duration),
+	Test(Duration),
+	SerialInactivity(Duration),
+}
+
+impl fmt::Display for Expiry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::State(state) => write!(f, "session timed out while {state:?}"),
+			Self::Session(limit) => write!(f, "session exceeded its time limit of {limit:?}"),
+			Self::Test(limit) => write!(f, "test exceeded its time limit of {limit:?}"),
+			Self::SerialInactivity(limit) => {
+				write!(f, "no serial output from the SUT for {limit:?}")
+			}
+		}
+	}
+}
+
+pub(crate) struct Watchdog {
+	limits: WatchdogLimits,
+	started_at: Instant,
+	test_started_at: Option<Instant>,
+	/// When serial output was last seen; `None` until the SUT is powered on
+	serial_seen_at: Option<Instant>,
+}
+
+impl Watchdog {
+	pub fn new(limits: WatchdogLimits) -> Self {
+		Self {
+			limits,
+			started_at: Instant::now(),
+			test_started_at: None,
+			serial_seen_at: None,
+		}
+	}
+
+	/// The SUT was powered on; arms the serial inactivity timeout.
+	pub fn powered_on(&mut self) {
+		self.serial_seen_at = Some(Instant::now());
+	}
+
+	pub fn test_started(&mut self) {
+		self.test_started_at = Some(Instant::now());
+	}
+
+	/// The current test reported its result; nothing is timed until the
+	/// next one starts.
+	pub fn test_finished(&mut self) {
+		self.test_started_at = None;
+	}
+
+	pub fn serial_seen(&mut self) {
+		if self.serial_seen_at.is_some() {
+			self.serial_seen_at = Some(Instant::now());
+		}
+	}
+
+	/// The earliest deadline, and what happens when it passes.
+	pub fn deadline(&self) -> Option<(Instant, Expiry)> {
+		let session = self
+			.limits
+			.session
+			.map(|limit| (self.started_at + limit, Expiry::Session(limit)));
+		let test = self
+			.limits
+			.test
+			.zip(self.test_started_at)
+			.map(|(limit, at)| (at + limit, Expiry::Test(limit)));
+		let serial = self
+			.limits
+			.serial_inactivity
+			.zip(self.serial_seen_at)
+			.map(|(limit, at)| (at + limit, Expiry::SerialInactivity(limit)));
+
+		[session, test, serial]
+			.into_iter()
+			.flatten()
+			.min_by_key(|(at, _)| *at)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn arms_deadlines_as_the_session_progresses() {
+		let limits = WatchdogLimits {
+			session: Some(Duration::from_secs(3600)),
+			test: Some(Duration::from_secs(600)),
+			serial_inactivity: Some(Duration::from_secs(60)),
+		};
+
+		let mut watchdog = Watchdog::new(limits);
+		assert!(matches!(watchdog.deadline(), Some((_, Expiry::Session(_)))));
+
+		// Serial output before the SUT is powered on doesn't arm anything.
+		watchdog.serial_seen();
+		watchdog.test_started();
+		assert!(matches!(watchdog.deadline(), Some((_, Expiry::Test(_)))));
+
+		watchdog.powered_on();
+		let (at, expiry) = watchdog.deadline().unwrap();
+		assert_eq!(expiry, Expiry::SerialInactivity(Duration::from_secs(60)));
+
+		watchdog.serial_seen();
+		assert!(watchdog.deadline().unwrap().0 >= at);
+
+		assert_eq!(Watchdog::new(WatchdogLimits::default()).deadline(), None);
+	}
+}
@@ -0,0 +1,55 @@
+//! Tells sessions that the daemon is shutting down.
+use async_std::channel::{Receiver, Sender, bounded as make_bounded_channel};
+use std::sync::{Arc, Mutex};
+
+/// A one-shot broadcast, triggered once the daemon receives `SIGINT`
+/// or `SIGTERM`. Cloning it gives another handle to the same signal.
+#[derive(Debug, Clone)]
+pub(crate) struct Shutdown {
+	trigger: Arc<Mutex<Option<Sender<()>>>>,
+	signal: Receiver<()>,
+}
+
+impl Default for Shutdown {
+	fn default() -> Self {
+		let (trigger, signal) = make_bounded_channel(1);
+		Self {
+			trigger: Arc::new(Mutex::new(Some(trigger))),
+			signal,
+		}
+	}
+}
+
+impl Shutdown {
+	/// Starts shutting down; wakes everything waiting on the signal.
+	pub fn trigger(&self) {
+		// Nothing is ever sent; closing the channel wakes all receivers.
+		self.trigger.lock().unwrap().take();
+	}
+
+	/// Waits until shutdown is triggered; returns immediately if it already was.
+	pub async fn triggered(&self) {
+		let _ = self.signal.recv().await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[async_std::test]
+	async fn wakes_all_handles() {
+		let shutdown = Shutdown::default();
+		let waiter = async_std::task::spawn({
+			let shutdown = shutdown.clone();
+			async move { shutdown.triggered().await }
+		});
+
+		assert!(!shutdown.signal.is_closed());
+		shutdown.clone().trigger();
+		waiter.await;
+
+		// Once triggered, it stays triggered.
+		shutdown.triggered().await;
+	}
+}
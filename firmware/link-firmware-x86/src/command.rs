@@ -41,4 +41,8 @@ pub enum Command {
 	},
 	/// Starts a new test
 	StartTest { name: String<255> },
+	/// Ends the current test with the given outcome
+	EndTest(uc::TestOutcome),
+	/// Shows the session's outcome
+	ShowOutcome(uc::SessionOutcome),
 }
@@ -29,6 +29,25 @@ use uc::{
 	DebugLed, Monitor, PowerState, ResetManager, Rng, Scene, SystemUnderTest, UniqueId, WallClock,
 };
 
+/// The firmware features reported to the daemon.
+fn firmware_features() -> heapless::String<128> {
+	let mut features = heapless::String::new();
+
+	for (enabled, feature) in [
+		(cfg!(feature = "helper-monitor-three-indicators-oled-256x64"), "monitor"),
+		(cfg!(feature = "helper-three-indicators"), "indicators"),
+	] {
+		if enabled {
+			if !features.is_empty() {
+				features.push(',').unwrap();
+			}
+			features.push_str(feature).unwrap();
+		}
+	}
+
+	features
+}
+
 #[defmt::panic_handler]
 fn defmt_panic() -> ! {
 	#[allow(clippy::empty_loop)]
@@ -272,6 +291,33 @@ pub async fn main(spawner: Spawner) -> ! {
 			Command::IncomingPacket(Packet::StartTest { name }) => {
 				monitor_sender.send(Command::StartTest { name }).await
 			}
+			Command::IncomingPacket(Packet::TestResult { outcome, .. }) => {
+				monitor_sender
+					.send(Command::EndTest(match outcome {
+						proto::TestOutcome::Passed => uc::TestOutcome::Passed,
+						proto::TestOutcome::Failed => uc::TestOutcome::Failed,
+						proto::TestOutcome::Skipped => uc::TestOutcome::Skipped,
+						unknown => {
+							warn!("daemon: reported unknown test outcome: {:?}", unknown);
+							continue;
+						}
+					}))
+					.await
+			}
+			Command::IncomingPacket(Packet::SessionOutcome(outcome)) => {
+				monitor_sender
+					.send(Command::ShowOutcome(match outcome {
+						proto::SessionOutcome::Passed => uc::SessionOutcome::Passed,
+						proto::SessionOutcome::Failed => uc::SessionOutcome::Failed,
+						proto::SessionOutcome::TimedOut => uc::SessionOutcome::TimedOut,
+						proto::SessionOutcome::Killed => uc::SessionOutcome::Killed,
+						unknown => {
+							warn!("daemon: reported unknown session outcome: {:?}", unknown);
+							continue;
+						}
+					}))
+					.await
+			}
 			Command::IncomingPacket(Packet::SetPowerState(state)) => {
 				debug!("broker: transitioning to power state: {:?}", state);
 				system.transition_power_state(match state {
@@ -300,6 +346,11 @@ pub async fn main(spawner: Spawner) -> ! {
 					.send(Command::IncomingPacket(Packet::Serial(data)))
 					.await;
 			}
+			Command::IncomingPacket(Packet::Ping(n)) => {
+				daemon_sender
+					.send(Command::OutgoingPacket(Packet::Pong(n)))
+					.await;
+			}
 			Command::IncomingPacket(Packet::DebugUsbKey(key)) => {
 				usb_sender
 					.send(Command::IncomingPacket(Packet::DebugUsbKey(key)))
@@ -317,6 +368,18 @@ pub async fn main(spawner: Spawner) -> ! {
 						version: env!("CARGO_PKG_VERSION").try_into().unwrap(),
 					}))
 					.await;
+				daemon_sender
+					.send(Command::OutgoingPacket(Packet::HardwareReport {
+						board: uc::HARDWARE.board.try_into().unwrap(),
+						arch: proto::SutArch::X86_64,
+						usb_hid: uc::HARDWARE.usb_hid,
+						// TODO(qix-): report `true` once system ethernet capture is re-enabled
+						capture: false,
+						sd_mux: uc::HARDWARE.sd_mux,
+						serial_ports: uc::HARDWARE.serial_ports,
+						features: firmware_features(),
+					}))
+					.await;
 			}
 			#[allow(clippy::diverging_sub_expression)]
 			Command::DaemonDisconnected => {
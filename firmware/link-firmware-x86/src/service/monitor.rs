@@ -24,6 +24,8 @@ pub async fn run<M: Monitor, Rm: RawMutex, const SZ: usize>(
 						ref_id,
 					} => monitor.start_test_run(total_tests, author, title, ref_id),
 					Command::StartTest { name } => monitor.start_test(name),
+					Command::EndTest(outcome) => monitor.end_test(outcome),
+					Command::ShowOutcome(outcome) => monitor.end_test_run(outcome),
 					unknown => warn!("monitor: ignoring unknown command: {:?}", unknown),
 				}
 			}
@@ -5,6 +5,9 @@ mod stm32;
 #[cfg(feature = "stm32")]
 pub use stm32::*;
 
+#[cfg(not(feature = "stm32"))]
+compile_error!("no uC selected; enable exactly one of the uC features (e.g. `stm32f479vg`)");
+
 use embassy_executor::Spawner;
 pub use embassy_net::driver::Driver as EthernetDriver;
 use embassy_time::{block_for, Duration};
@@ -44,6 +47,20 @@ pub enum PowerState {
 	On,
 }
 
+/// What a board variant provides for the system under test, as reported
+/// to the daemon after connecting. Each uC module defines its own as
+/// `HARDWARE`.
+pub struct Hardware {
+	/// The board variant (e.g. `stm32f479vg`)
+	pub board: &'static str,
+	/// Whether the board acts as a USB HID device for the SUT
+	pub usb_hid: bool,
+	/// Whether the board can switch the SUT's SD card
+	pub sd_mux: bool,
+	/// How many SUT serial ports the board bridges
+	pub serial_ports: u8,
+}
+
 /// Controller for the system under test (switches, CPU, etc.)
 #[allow(dead_code)]
 pub trait SystemUnderTest {
@@ -158,6 +175,23 @@ pub enum Scene {
 	Test,
 }
 
+/// How a single test went.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TestOutcome {
+	Passed,
+	Failed,
+	Skipped,
+}
+
+/// How a test session ended.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SessionOutcome {
+	Passed,
+	Failed,
+	TimedOut,
+	Killed,
+}
+
 /// A log frame's severity level.
 /// All log frames are considered important if the firmware pushes them;
 /// implementations of [`Monitor`] should not perform any filtering.
@@ -231,6 +265,16 @@ pub trait Monitor {
 	/// Indicates the start of a new test
 	fn start_test(&mut self, name: String<255>);
 
+	/// Indicates the end of the current test. Passes and failures
+	/// are tallied for the rest of the test run.
+	fn end_test(&mut self, outcome: TestOutcome);
+
+	/// Ends the test run, showing its outcome until the next
+	/// test run is started.
+	///
+	/// NOTE: This does NOT change the scene!
+	fn end_test_run(&mut self, outcome: SessionOutcome);
+
 	/// Should be called frequently - at least 60 times a second, but can be called
 	/// faster. Must be passed a monotonic millisecond instance.
 	fn tick(&mut self, millis: u64);
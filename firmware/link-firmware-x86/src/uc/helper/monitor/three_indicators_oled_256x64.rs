@@ -10,9 +10,10 @@ use crate::{
 			oro_logo::OroLogo,
 			three_indicators::{Color, IndicatorLights},
 		},
-		LogFrame, LogSeverity, Monitor, Scene,
+		LogFrame, LogSeverity, Monitor, Scene, SessionOutcome, TestOutcome,
 	},
 };
+use core::fmt::Write;
 use embedded_graphics::{
 	draw_target::DrawTarget, pixelcolor::Gray4, primitives::Rectangle, Drawable,
 };
@@ -133,6 +134,14 @@ where
 	fn start_test(&mut self, name: String<255>) {
 		self.test_renderer.start_test(name);
 	}
+
+	fn end_test(&mut self, outcome: TestOutcome) {
+		self.test_renderer.end_test(outcome);
+	}
+
+	fn end_test_run(&mut self, outcome: SessionOutcome) {
+		self.test_renderer.end_test_run(outcome);
+	}
 }
 
 struct OroLogoRenderer {
@@ -290,6 +299,9 @@ struct TestRenderer {
 	title: String<255>,
 	ref_id: String<255>,
 	current_test: String<255>,
+	passed: usize,
+	failed: usize,
+	outcome: Option<SessionOutcome>,
 	dirty: bool,
 }
 
@@ -298,7 +310,7 @@ impl TestRenderer {
 		&mut self,
 		_millis: u64,
 		target: &mut D,
-		_lights: &mut I,
+		lights: &mut I,
 	) {
 		if !self.dirty {
 			return;
@@ -311,9 +323,37 @@ impl TestRenderer {
 		face::TermBold::draw_chars(self.author.chars(), target, 0, 0, WHITE, BLACK);
 		face::TermNormal::draw_chars(self.title.chars(), target, 0, 16, WHITE, BLACK);
 		face::TermNormal::draw_chars(self.ref_id.chars(), target, 0, 32, LIGHT_GRAY, BLACK);
-		face::TermNormal::draw_chars(self.current_test.chars(), target, 0, 48, DARK_GRAY, BLACK);
 
-		let pct = ((self.count as i32 - 1).max(0) * 100) as usize / self.total;
+		// Once the run is over, its outcome replaces the current test
+		// and is mirrored on the indicator lights.
+		match self.outcome {
+			None => {
+				face::TermNormal::draw_chars(
+					self.current_test.chars(),
+					target,
+					0,
+					48,
+					DARK_GRAY,
+					BLACK,
+				);
+			}
+			Some(outcome) => {
+				let (message, light) = match outcome {
+					SessionOutcome::Passed => ("PASSED", 0x00FF00FF),
+					SessionOutcome::Failed => ("FAILED", 0xFF0000FF),
+					SessionOutcome::TimedOut => ("TIMED OUT", 0xFFFF00FF),
+					SessionOutcome::Killed => ("KILLED", 0xFF00FFFF),
+				};
+
+				face::TermBold::draw_chars(message.chars(), target, 0, 48, WHITE, BLACK);
+
+				lights.first(light);
+				lights.second(light);
+				lights.third(light);
+			}
+		}
+
+		let pct = ((self.count as i32 - 1).max(0) * 100) as usize / self.total.max(1);
 		let pct_chars = [
 			(b'0' + ((pct / 10) % 10) as u8) as char,
 			(b'0' + (pct % 10) as u8) as char,
@@ -338,6 +378,22 @@ impl TestRenderer {
 
 		face::Progress::draw_chars(pct_chars, target, 256 - total_width, 0, WHITE, BLACK);
 
+		// The tally goes to the bottom right, left of the progress.
+		if self.passed + self.failed > 0 {
+			let mut tally = String::<32>::new();
+			write!(tally, "{} ok {} failed", self.passed, self.failed).ok();
+
+			let tally_width: i32 = tally.chars().map(face::TermNormal::char_width).sum();
+			face::TermNormal::draw_chars(
+				tally.chars(),
+				target,
+				256 - padded_width - tally_width - 4,
+				48,
+				if self.failed > 0 { WHITE } else { LIGHT_GRAY },
+				BLACK,
+			);
+		}
+
 		target.present().ok();
 	}
 
@@ -360,6 +416,9 @@ impl TestRenderer {
 		self.author = author;
 		self.title = title;
 		self.ref_id = ref_id;
+		self.passed = 0;
+		self.failed = 0;
+		self.outcome = None;
 		self.dirty = true;
 	}
 
@@ -368,6 +427,20 @@ impl TestRenderer {
 		self.dirty = true;
 		self.count += 1;
 	}
+
+	fn end_test(&mut self, outcome: TestOutcome) {
+		match outcome {
+			TestOutcome::Passed => self.passed += 1,
+			TestOutcome::Failed => self.failed += 1,
+			TestOutcome::Skipped => {}
+		}
+		self.dirty = true;
+	}
+
+	fn end_test_run(&mut self, outcome: SessionOutcome) {
+		self.outcome = Some(outcome);
+		self.dirty = true;
+	}
 }
 
 /// Ported from <https://github.com/Qix-/color-convert/blob/master/conversions.js>
@@ -3,6 +3,9 @@ mod stm32f479vg;
 #[cfg(feature = "stm32f479vg")]
 pub use stm32f479vg::*;
 
+#[cfg(not(feature = "stm32f479vg"))]
+compile_error!("no STM32 board variant selected; enable one of the uC features (e.g. `stm32f479vg`)");
+
 use crate::chip;
 use cortex_m::peripheral::SCB;
 use embassy_stm32::{
@@ -28,6 +28,15 @@ bind_interrupts!(struct Irqs {
 	OTG_FS => stm32_usb::InterruptHandler<peripherals::USB_OTG_FS>;
 });
 
+/// USB HID over OTG FS and a single SUT serial port over USART3 (see
+/// [`init`]); there's no SD card mux on this board.
+pub const HARDWARE: uc::Hardware = uc::Hardware {
+	board: "stm32f479vg",
+	usb_hid: true,
+	sd_mux: false,
+	serial_ports: 1,
+};
+
 type EthernetSPI = ExclusiveDevice<Spi<'static, Async>, Output<'static>, Delay>;
 
 #[embassy_executor::task]
@@ -54,28 +54,93 @@ impl Parse for ProtoMeta {
 	}
 }
 
+/// Options given to the enum itself, e.g. `#[proto(kinds)]`.
+#[derive(Default)]
+struct EnumMeta {
+	/// Whether to generate a fieldless `<Enum>Kind` enum along with
+	/// `kind()`, `id()` and `name()` accessors
+	kinds: bool,
+}
+
+impl Parse for EnumMeta {
+	fn parse(input: ParseStream<'_>) -> Result<Self, Error> {
+		let options = Punctuated::<Ident, Comma>::parse_terminated(input)?;
+		let mut meta = EnumMeta::default();
+
+		for option in options {
+			match option.to_string().as_str() {
+				"kinds" => meta.kinds = true,
+				_ => {
+					return Err(Error::new(
+						option.span(),
+						"unknown link protocol enum `proto()` option",
+					));
+				}
+			}
+		}
+
+		Ok(meta)
+	}
+}
+
 fn paste<A: ToString, B: ToString>(a: &A, b: &B) -> Ident {
 	let a = a.to_string();
 	let b = b.to_string();
 	Ident::new(&format!("{a}{b}"), Span::call_site())
 }
 
+/// Derives the binser (de)serialization of an enum whose variants are each
+/// tagged with a unique, non-zero `#[proto(id = ...)]`.
+///
+/// With `#[proto(kinds)]` on the enum itself, a fieldless `<Enum>Kind` enum
+/// is generated too, along with `kind()`, `id()` and `name()` accessors.
 #[proc_macro_derive(LinkMessage, attributes(proto))]
 pub fn derive_link_protocol_message(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
 	let ast = parse_macro_input!(item as ItemEnum);
 
 	let enum_ident = ast.ident.clone();
+	let kind_ident = paste(&enum_ident, &"Kind");
+
+	let non_exhaustive = ast
+		.attrs
+		.iter()
+		.any(|attr| attr.path().is_ident("non_exhaustive"));
+
+	let mut enum_meta = EnumMeta::default();
+	for attr in ast
+		.attrs
+		.iter()
+		.filter(|attr| attr.path().is_ident("proto"))
+	{
+		match attr.parse_args_with(EnumMeta::parse) {
+			Ok(meta) => enum_meta.kinds |= meta.kinds,
+			Err(err) => return err.into_compile_error().into(),
+		}
+	}
 
 	let mut known_discriminants = HashMap::<u8, Ident>::new();
 
 	let mut serialize_matches = Vec::new();
 	let mut deserialize_matches = Vec::new();
+	let mut kind_variants = Vec::new();
+	let mut kind_matches = Vec::new();
+	let mut kind_id_matches = Vec::new();
+	let mut kind_name_matches = Vec::new();
+	let mut kind_from_id_matches = Vec::new();
+	let mut kind_all = Punctuated::<TokenStream, Comma>::new();
 
 	for variant in ast.variants {
 		let ident = variant.ident;
 
 		let mut serialize_statements = Vec::new();
 
+		let docs = variant
+			.attrs
+			.iter()
+			.filter(|attr| attr.path().is_ident("doc"))
+			.cloned()
+			.collect::<Vec<_>>();
+
 		let mut proto = None;
 		for attr in variant.attrs {
 			if let Meta::List(attr) = attr.meta {
@@ -137,6 +202,37 @@ pub fn derive_link_protocol_message(item: proc_macro::TokenStream) -> proc_macro
 
 		known_discriminants.insert(discriminant, ident.clone());
 
+		let name = ident.to_string();
+
+		kind_variants.push(quote! {
+			#(#docs)*
+			#ident,
+		});
+
+		kind_id_matches.push(quote! {
+			#kind_ident :: #ident => #discriminant,
+		});
+
+		kind_name_matches.push(quote! {
+			#kind_ident :: #ident => #name,
+		});
+
+		kind_from_id_matches.push(quote! {
+			#discriminant => Some(#kind_ident :: #ident),
+		});
+
+		kind_all.push(quote! { #kind_ident :: #ident });
+
+		let wildcard = match &variant.fields {
+			Fields::Named(_) => quote! { { .. } },
+			Fields::Unnamed(_) => quote! { (..) },
+			Fields::Unit => quote! {},
+		};
+
+		kind_matches.push(quote! {
+			#enum_ident :: #ident #wildcard => #kind_ident :: #ident,
+		});
+
 		serialize_statements.push(quote! {
 			<u8 as ::link_protocol_binser::Serialize>::serialize(&#discriminant, writer).await?;
 		});
@@ -224,6 +320,7 @@ pub fn derive_link_protocol_message(item: proc_macro::TokenStream) -> proc_macro
 	}
 
 	let ident = ast.ident;
+	let vis = ast.vis;
 	let (generics_pre, generics_mid, generics_post) = ast.generics.split_for_impl();
 
 	let mut serialize_matches_stream = TokenStream::new();
@@ -231,7 +328,77 @@ pub fn derive_link_protocol_message(item: proc_macro::TokenStream) -> proc_macro
 	let mut deserialize_matches_stream = TokenStream::new();
 	deserialize_matches_stream.append_all(deserialize_matches);
 
+	let non_exhaustive = if non_exhaustive {
+		quote! { #[non_exhaustive] }
+	} else {
+		quote! {}
+	};
+
+	let kind_doc = format!("The fieldless kinds of [`{ident}`] variants.");
+
+	let kinds = enum_meta.kinds.then(|| {
+		quote! {
+			#[doc = #kind_doc]
+			#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+			#non_exhaustive
+			#vis enum #kind_ident {
+				#(#kind_variants)*
+			}
+
+			impl #kind_ident {
+				/// All kinds, in declaration order.
+				pub const ALL: &'static [#kind_ident] = &[#kind_all];
+
+				/// Returns the kind with the given protocol `id`, if there is one.
+				pub const fn from_id(id: u8) -> Option<Self> {
+					match id {
+						#(#kind_from_id_matches)*
+						_ => None,
+					}
+				}
+
+				/// Returns the protocol `id` of this kind.
+				pub const fn id(&self) -> u8 {
+					match self {
+						#(#kind_id_matches)*
+					}
+				}
+
+				/// Returns the name of this kind's variant.
+				pub const fn name(&self) -> &'static str {
+					match self {
+						#(#kind_name_matches)*
+					}
+				}
+			}
+
+			#[automatically_derived]
+			impl #generics_pre #ident #generics_mid #generics_post {
+				/// Returns the kind of this message.
+				pub const fn kind(&self) -> #kind_ident {
+					match self {
+						#(#kind_matches)*
+					}
+				}
+
+				/// Returns the protocol `id` of this message.
+				#[inline]
+				pub const fn id(&self) -> u8 {
+					self.kind().id()
+				}
+
+				/// Returns the name of this message's variant.
+				#[inline]
+				pub const fn name(&self) -> &'static str {
+					self.kind().name()
+				}
+			}
+		}
+	});
+
 	quote! {
+		#kinds
+
 		const _: () = {
 			#[automatically_derived]
 			impl #generics_pre ::link_protocol_binser::Serialize for #ident #generics_mid #generics_post {
@@ -0,0 +1,287 @@
+//! (De)serialization support for `alloc` collections.
+//!
+//! Allocated collections are encoded exactly like their `heapless` counterparts
+//! (a big-endian length prefix sized by the maximum length, followed by the
+//! elements), so host-side types can use ordinary collections while still
+//! speaking the same wire format as the firmware.
+//!
+//! Unwrapped collections are limited to [`DEFAULT_MAX_LEN`] elements; use
+//! [`Bounded`] to pick a different limit (e.g. `Bounded<String, 255>` is
+//! wire-compatible with `heapless::String<255>`).
+use crate::{Deserialize, Error, Read, Serialize, Write, num_bytes_for_size};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use core::ops::{Deref, DerefMut};
+
+/// The maximum length of collections that aren't wrapped in a [`Bounded`].
+pub const DEFAULT_MAX_LEN: usize = u16::MAX as usize;
+
+/// The most elements reserved up front when deserializing a vector; the
+/// length prefix comes off the wire, so anything beyond this is only
+/// allocated as the elements actually arrive.
+const MAX_PREALLOC: usize = 256;
+
+/// Wraps an allocated collection, enforcing a maximum length of `MAX`
+/// elements (bytes, in the case of strings) when (de)serializing.
+///
+/// The length prefix is sized according to `MAX`, just like the `heapless`
+/// collections.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bounded<T, const MAX: usize>(pub T);
+
+impl<T, const MAX: usize> Bounded<T, MAX> {
+	/// Unwraps the inner collection
+	#[inline]
+	pub fn into_inner(self) -> T {
+		self.0
+	}
+}
+
+impl<T, const MAX: usize> From<T> for Bounded<T, MAX> {
+	#[inline]
+	fn from(value: T) -> Self {
+		Self(value)
+	}
+}
+
+impl<T, const MAX: usize> Deref for Bounded<T, MAX> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T, const MAX: usize> DerefMut for Bounded<T, MAX> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.0
+	}
+}
+
+async fn serialize_len<const MAX: usize, W: Write>(
+	len: usize,
+	writer: &mut W,
+) -> Result<(), Error<W::Error>> {
+	debug_assert!(len <= MAX);
+	debug_assert!(len <= u32::MAX as usize);
+
+	let num_bytes = num_bytes_for_size::<MAX>();
+	let len_bytes = (len as u32).to_be_bytes();
+
+	writer.write(&len_bytes[(4 - num_bytes)..]).await
+}
+
+async fn deserialize_len<const MAX: usize, R: Read>(
+	reader: &mut R,
+) -> Result<usize, Error<R::Error>> {
+	let num_bytes = num_bytes_for_size::<MAX>();
+
+	let mut len_bytes = [0u8; 4];
+	reader.read(&mut len_bytes[4 - num_bytes..]).await?;
+
+	Ok(u32::from_be_bytes(len_bytes) as usize)
+}
+
+async fn serialize_str<const MAX: usize, W: Write>(
+	s: &str,
+	writer: &mut W,
+) -> Result<(), Error<W::Error>> {
+	let bytes = s.as_bytes();
+
+	if bytes.len() > MAX {
+		return Err(Error::StringTooLong);
+	}
+
+	serialize_len::<MAX, W>(bytes.len(), writer).await?;
+	writer.write(bytes).await
+}
+
+async fn deserialize_string<const MAX: usize, R: Read>(
+	reader: &mut R,
+) -> Result<String, Error<R::Error>> {
+	let len = deserialize_len::<MAX, R>(reader).await?;
+
+	if len > MAX {
+		return Err(Error::StringTooLong);
+	}
+
+	let mut buffer = alloc::vec![0u8; len];
+	reader.read(&mut buffer[..]).await?;
+
+	String::from_utf8(buffer).map_err(|_| Error::MalformedString)
+}
+
+async fn serialize_slice<const MAX: usize, T: Serialize, W: Write>(
+	items: &[T],
+	writer: &mut W,
+) -> Result<(), Error<W::Error>> {
+	if items.len() > MAX {
+		return Err(Error::ArrayTooLong);
+	}
+
+	serialize_len::<MAX, W>(items.len(), writer).await?;
+
+	for item in items {
+		item.serialize(writer).await?;
+	}
+
+	Ok(())
+}
+
+async fn deserialize_vec<const MAX: usize, T: Deserialize, R: Read>(
+	reader: &mut R,
+) -> Result<Vec<T>, Error<R::Error>> {
+	let len = deserialize_len::<MAX, R>(reader).await?;
+
+	if len > MAX {
+		return Err(Error::ArrayTooLong);
+	}
+
+	let mut r = Vec::with_capacity(len.min(MAX_PREALLOC));
+	for _ in 0..len {
+		r.push(T::deserialize(reader).await?);
+	}
+
+	Ok(r)
+}
+
+async fn serialize_map<const MAX: usize, K: Serialize, V: Serialize, W: Write>(
+	map: &BTreeMap<K, V>,
+	writer: &mut W,
+) -> Result<(), Error<W::Error>> {
+	if map.len() > MAX {
+		return Err(Error::ArrayTooLong);
+	}
+
+	serialize_len::<MAX, W>(map.len(), writer).await?;
+
+	for (k, v) in map {
+		k.serialize(writer).await?;
+		v.serialize(writer).await?;
+	}
+
+	Ok(())
+}
+
+async fn deserialize_map<const MAX: usize, K: Deserialize + Ord, V: Deserialize, R: Read>(
+	reader: &mut R,
+) -> Result<BTreeMap<K, V>, Error<R::Error>> {
+	let len = deserialize_len::<MAX, R>(reader).await?;
+
+	if len > MAX {
+		return Err(Error::ArrayTooLong);
+	}
+
+	let mut r = BTreeMap::new();
+	for _ in 0..len {
+		let k = K::deserialize(reader).await?;
+		let v = V::deserialize(reader).await?;
+		// Otherwise, differing encodings would decode to the same map.
+		if r.insert(k, v).is_some() {
+			return Err(Error::DuplicateKey);
+		}
+	}
+
+	Ok(r)
+}
+
+impl Serialize for String {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_str::<DEFAULT_MAX_LEN, W>(self, writer).await
+	}
+}
+
+impl Deserialize for String {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		deserialize_string::<DEFAULT_MAX_LEN, R>(reader).await
+	}
+}
+
+impl<const MAX: usize> Serialize for Bounded<String, MAX> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_str::<MAX, W>(&self.0, writer).await
+	}
+}
+
+impl<const MAX: usize> Deserialize for Bounded<String, MAX> {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		Ok(Self(deserialize_string::<MAX, R>(reader).await?))
+	}
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_slice::<DEFAULT_MAX_LEN, T, W>(self, writer).await
+	}
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		deserialize_vec::<DEFAULT_MAX_LEN, T, R>(reader).await
+	}
+}
+
+impl<T: Serialize, const MAX: usize> Serialize for Bounded<Vec<T>, MAX> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_slice::<MAX, T, W>(&self.0, writer).await
+	}
+}
+
+impl<T: Deserialize, const MAX: usize> Deserialize for Bounded<Vec<T>, MAX> {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		Ok(Self(deserialize_vec::<MAX, T, R>(reader).await?))
+	}
+}
+
+impl<K: Serialize, V: Serialize> Serialize for BTreeMap<K, V> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_map::<DEFAULT_MAX_LEN, K, V, W>(self, writer).await
+	}
+}
+
+impl<K: Deserialize + Ord, V: Deserialize> Deserialize for BTreeMap<K, V> {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		deserialize_map::<DEFAULT_MAX_LEN, K, V, R>(reader).await
+	}
+}
+
+impl<K: Serialize, V: Serialize, const MAX: usize> Serialize for Bounded<BTreeMap<K, V>, MAX> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		serialize_map::<MAX, K, V, W>(&self.0, writer).await
+	}
+}
+
+impl<K: Deserialize + Ord, V: Deserialize, const MAX: usize> Deserialize
+	for Bounded<BTreeMap<K, V>, MAX>
+{
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		Ok(Self(deserialize_map::<MAX, K, V, R>(reader).await?))
+	}
+}
+
+impl<T: Serialize> Serialize for Box<T> {
+	#[inline]
+	async fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), Error<W::Error>> {
+		(**self).serialize(writer).await
+	}
+}
+
+impl<T: Deserialize> Deserialize for Box<T> {
+	#[inline]
+	async fn deserialize<R: Read>(reader: &mut R) -> Result<Self, Error<R::Error>> {
+		Ok(Box::new(T::deserialize(reader).await?))
+	}
+}
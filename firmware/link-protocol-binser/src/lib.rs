@@ -1,16 +1,25 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![allow(async_fn_in_trait)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(feature = "async-std")]
 mod async_std;
+#[cfg(feature = "alloc")]
+mod collections;
 #[cfg(feature = "embedded-io")]
 mod embedded_io;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 use core::str::FromStr;
 
 #[cfg(feature = "defmt")]
 use defmt::Format;
 
+#[cfg(feature = "alloc")]
+pub use collections::{Bounded, DEFAULT_MAX_LEN};
 pub use link_protocol_binser_proc::LinkMessage;
 
 #[cfg(feature = "std")]
@@ -57,6 +66,11 @@ pub enum Error<IoError: MaybeFormat> {
 	InvalidEnumeration,
 	#[cfg_attr(feature = "thiserror", error("a string failed to decode as utf-8"))]
 	MalformedString,
+	#[cfg_attr(
+		feature = "thiserror",
+		error("a map contained the same key more than once")
+	)]
+	DuplicateKey,
 	#[cfg_attr(feature = "thiserror", error("unexpected EOF"))]
 	Eof,
 	#[cfg_attr(feature = "thiserror", error("io error occurred: {0}"))]
@@ -220,7 +234,7 @@ impl Deserialize for bool {
 	}
 }
 
-#[cfg(feature = "heapless")]
+#[cfg(any(feature = "heapless", feature = "alloc"))]
 const fn num_bytes_for_size<const SZ: usize>() -> usize {
 	const U8_MAX: usize = u8::MAX as usize;
 	const U8_UPPER: usize = (u8::MAX as usize) + 1;
@@ -0,0 +1,65 @@
+//! In-memory (de)serialization, for tests.
+//!
+//! Nothing here ever waits on I/O, so the futures are polled just once.
+use crate::{Deserialize, Error, Read, Serialize, Write};
+use core::{
+	convert::Infallible,
+	future::Future,
+	pin::pin,
+	task::{Context, Poll, Waker},
+};
+
+struct WireBuf(Vec<u8>);
+
+impl Write for WireBuf {
+	type Error = Infallible;
+
+	async fn write(&mut self, buf: &[u8]) -> Result<(), Error<Self::Error>> {
+		self.0.extend_from_slice(buf);
+		Ok(())
+	}
+
+	async fn flush(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+struct WireReader<'a>(&'a [u8]);
+
+impl Read for WireReader<'_> {
+	type Error = Infallible;
+
+	async fn read(&mut self, buf: &mut [u8]) -> Result<(), Error<Self::Error>> {
+		if buf.len() > self.0.len() {
+			return Err(Error::Eof);
+		}
+
+		let (head, tail) = self.0.split_at(buf.len());
+		buf.copy_from_slice(head);
+		self.0 = tail;
+		Ok(())
+	}
+}
+
+fn block_on<T>(fut: impl Future<Output = T>) -> T {
+	let fut = pin!(fut);
+	let Poll::Ready(r) = fut.poll(&mut Context::from_waker(Waker::noop())) else {
+		unreachable!("in-memory (de)serialization never yields");
+	};
+	r
+}
+
+/// Serializes `value` into its wire encoding.
+pub fn wire<T: Serialize>(value: &T) -> Result<Vec<u8>, Error<Infallible>> {
+	let mut buf = WireBuf(Vec::new());
+	block_on(value.serialize(&mut buf))?;
+	Ok(buf.0)
+}
+
+/// Deserializes a `T` from `bytes`, which it must use up entirely.
+pub fn read<T: Deserialize>(bytes: &[u8]) -> Result<T, Error<Infallible>> {
+	let mut reader = WireReader(bytes);
+	let value = block_on(T::deserialize(&mut reader))?;
+	assert!(reader.0.is_empty(), "trailing bytes");
+	Ok(value)
+}
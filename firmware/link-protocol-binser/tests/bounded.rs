@@ -0,0 +1,96 @@
+//! Checks that `Bounded` collections are encoded exactly like their
+//! `heapless` counterparts, and that their limits hold both ways.
+//! Also checks that maps only decode from a single encoding.
+use link_protocol_binser::{
+	Bounded, Error,
+	testing::{read, wire},
+};
+use std::{collections::BTreeMap, str::FromStr};
+
+#[test]
+fn strings_match_heapless() {
+	for s in ["", "hello", "été", &"x".repeat(255)].map(String::from) {
+		let bytes = wire(&Bounded::<String, 255>(s.clone())).unwrap();
+		assert_eq!(
+			bytes,
+			wire(&heapless::String::<255>::from_str(&s).unwrap()).unwrap()
+		);
+		assert_eq!(read::<Bounded<String, 255>>(&bytes).unwrap().0, s);
+	}
+
+	// Longer limits get longer length prefixes.
+	let bounded = Bounded::<String, 1024>("hello".into());
+	let bytes = wire(&bounded).unwrap();
+	assert_eq!(
+		bytes,
+		wire(&heapless::String::<1024>::from_str("hello").unwrap()).unwrap()
+	);
+	assert_eq!(&bytes[..2], &[0, 5]);
+	assert_eq!(read::<Bounded<String, 1024>>(&bytes).unwrap(), bounded);
+}
+
+#[test]
+fn vecs_match_heapless() {
+	for v in [&[][..], &[1, 2, 3][..], &[0xAA; 255][..]] {
+		let bounded = Bounded::<Vec<u8>, 255>(v.into());
+		let bytes = wire(&bounded).unwrap();
+		assert_eq!(
+			bytes,
+			wire(&heapless::Vec::<u8, 255>::from_slice(v).unwrap()).unwrap()
+		);
+		assert_eq!(read::<Bounded<Vec<u8>, 255>>(&bytes).unwrap(), bounded);
+	}
+
+	let bounded = Bounded::<Vec<u8>, 1024>(vec![7; 300]);
+	let bytes = wire(&bounded).unwrap();
+	assert_eq!(
+		bytes,
+		wire(&heapless::Vec::<u8, 1024>::from_slice(&[7; 300]).unwrap()).unwrap()
+	);
+	assert_eq!(read::<Bounded<Vec<u8>, 1024>>(&bytes).unwrap(), bounded);
+}
+
+#[test]
+fn rejects_overlong_input() {
+	assert_eq!(
+		wire(&Bounded::<String, 4>("hello".into())),
+		Err(Error::StringTooLong)
+	);
+	assert_eq!(
+		wire(&Bounded::<Vec<u8>, 4>(vec![0; 5])),
+		Err(Error::ArrayTooLong)
+	);
+
+	// Encoded with a larger limit (but the same size of length prefix)
+	let string = wire(&Bounded::<String, 8>("hello".into())).unwrap();
+	assert_eq!(
+		read::<Bounded<String, 4>>(&string),
+		Err(Error::StringTooLong)
+	);
+	let vec = wire(&Bounded::<Vec<u8>, 8>(vec![0; 5])).unwrap();
+	assert_eq!(read::<Bounded<Vec<u8>, 4>>(&vec), Err(Error::ArrayTooLong));
+}
+
+#[test]
+fn does_not_trust_length_prefixes() {
+	// Claims far more elements than there are; this fails on the missing
+	// input rather than by reserving room for all of them up front.
+	let mut bytes = u32::MAX.to_be_bytes().to_vec();
+	bytes.extend_from_slice(&[0; 4]);
+	assert_eq!(
+		read::<Bounded<Vec<u64>, { u32::MAX as usize }>>(&bytes),
+		Err(Error::Eof)
+	);
+}
+
+#[test]
+fn rejects_duplicate_map_keys() {
+	let map = BTreeMap::from([(1u8, 2u8), (3, 4)]);
+	let mut bytes = wire(&map).unwrap();
+	assert_eq!(read::<BTreeMap<u8, u8>>(&bytes).unwrap(), map);
+
+	// {1: 2, 1: 4}
+	let at = bytes.len() - 2;
+	bytes[at] = 1;
+	assert_eq!(read::<BTreeMap<u8, u8>>(&bytes), Err(Error::DuplicateKey));
+}
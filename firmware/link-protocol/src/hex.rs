@@ -0,0 +1,74 @@
+//! Renders fixed-size byte arrays (e.g. link UIDs) as upper-case hex strings
+//! when (de)serializing with `serde`.
+//!
+//! Use with `#[serde(with = "crate::hex")]`.
+use core::fmt;
+use serde::{
+	Deserializer, Serializer,
+	de::{Error, Visitor},
+};
+
+const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+pub fn serialize<S: Serializer, const SZ: usize>(
+	bytes: &[u8; SZ],
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	struct Hex<'a>(&'a [u8]);
+
+	impl fmt::Display for Hex<'_> {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			for b in self.0 {
+				let pair = [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xF) as usize]];
+				// SAFETY: `DIGITS` is entirely ASCII.
+				f.write_str(unsafe { core::str::from_utf8_unchecked(&pair) })?;
+			}
+
+			Ok(())
+		}
+	}
+
+	serializer.collect_str(&Hex(&bytes[..]))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, const SZ: usize>(
+	deserializer: D,
+) -> Result<[u8; SZ], D::Error> {
+	struct HexVisitor<const SZ: usize>;
+
+	impl<const SZ: usize> Visitor<'_> for HexVisitor<SZ> {
+		type Value = [u8; SZ];
+
+		fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			write!(f, "a hex string of {} bytes", SZ)
+		}
+
+		fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+			let v = v.as_bytes();
+
+			if v.len() != SZ * 2 {
+				return Err(E::invalid_length(v.len() / 2, &self));
+			}
+
+			let mut r = [0u8; SZ];
+			for (i, pair) in v.chunks_exact(2).enumerate() {
+				let hi = nibble(pair[0]).ok_or_else(|| E::custom("invalid hex digit"))?;
+				let lo = nibble(pair[1]).ok_or_else(|| E::custom("invalid hex digit"))?;
+				r[i] = (hi << 4) | lo;
+			}
+
+			Ok(r)
+		}
+	}
+
+	deserializer.deserialize_str(HexVisitor::<SZ>)
+}
+
+fn nibble(c: u8) -> Option<u8> {
+	match c {
+		b'0'..=b'9' => Some(c - b'0'),
+		b'a'..=b'f' => Some(c - b'a' + 10),
+		b'A'..=b'F' => Some(c - b'A' + 10),
+		_ => None,
+	}
+}
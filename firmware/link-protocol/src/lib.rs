@@ -12,6 +12,8 @@
 
 #[cfg(feature = "channels")]
 pub mod channel;
+#[cfg(feature = "serde")]
+mod hex;
 #[cfg(feature = "channels")]
 mod macros;
 
@@ -21,7 +23,9 @@ pub use link_protocol_binser::{Deserialize, Error, Read, Serialize, Write};
 
 /// Packets sent between the client and daemon.
 #[derive(Debug, Clone, LinkMessage)]
+#[proto(kinds)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum Packet {
 	/// The link is online and ready to receive work. Must be sent at least
@@ -30,6 +34,7 @@ pub enum Packet {
 	LinkOnline {
 		/// The link's 256 bit UID (should be a Sha256
 		/// of the PAC/etc. UID chip readout).
+		#[cfg_attr(feature = "serde", serde(with = "crate::hex"))]
 		uid: [u8; 32],
 		/// The link's firmware version
 		version: String<16>,
@@ -91,10 +96,72 @@ pub enum Packet {
 	/// (DEBUG) An HID key for USB HID testing
 	#[proto(id = 14)]
 	DebugUsbKey(u8),
+
+	/// Reports how the session ended, once the runner has exited
+	#[proto(id = 15)]
+	SessionOutcome(SessionOutcome),
+
+	/// Asks the receiver to reply with a [`Packet::Pong`] carrying the
+	/// same value; used by the daemon to measure round-trip time.
+	#[proto(id = 16)]
+	Ping(u32),
+
+	/// Replies to a [`Packet::Ping`]
+	#[proto(id = 17)]
+	Pong(u32),
+
+	/// Describes the link's hardware. Sent by the link right after
+	/// [`Packet::LinkOnline`].
+	#[proto(id = 18)]
+	HardwareReport {
+		/// The link's board variant (e.g. `stm32f479vg`)
+		board: String<32>,
+		/// The architecture of the system under test
+		arch: SutArch,
+		/// Whether the link can act as a USB HID device for the SUT
+		usb_hid: bool,
+		/// Whether the link can capture the SUT's network traffic
+		capture: bool,
+		/// Whether the link can switch the SUT's SD card
+		sd_mux: bool,
+		/// How many SUT serial ports the link bridges
+		serial_ports: u8,
+		/// Comma-separated firmware features (e.g. `monitor,indicators`)
+		features: String<128>,
+	},
+
+	/// Sent by a session socket client as its first packet to watch the
+	/// session rather than drive it. Observers receive serial output and
+	/// session events, but can't send anything to the link.
+	#[proto(id = 19)]
+	Observe,
+
+	/// Reports the result of the test last started with [`Packet::StartTest`]
+	#[proto(id = 20)]
+	TestResult {
+		name: String<255>,
+		outcome: TestOutcome,
+		/// How long the test took, in milliseconds
+		duration_ms: u64,
+		/// Why the test failed (or was skipped); may be empty
+		message: String<255>,
+	},
+
+	/// Ends the test session once all tests have run. The runner is then
+	/// expected to exit; [`Packet::SessionOutcome`] follows once it has.
+	#[proto(id = 21)]
+	EndTestSession,
+
+	/// Sent to a session socket client or observer once the serial output
+	/// from before it connected has been replayed; serial output after
+	/// this is live.
+	#[proto(id = 22)]
+	BacklogReplayed,
 }
 
 #[derive(Debug, Clone, LinkMessage)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum Scene {
 	#[proto(id = 1)]
@@ -107,6 +174,7 @@ pub enum Scene {
 
 #[derive(Debug, Clone, LinkMessage)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum LogEntry {
 	#[proto(id = 1)]
@@ -119,6 +187,7 @@ pub enum LogEntry {
 
 #[derive(Debug, Clone, LinkMessage)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum PowerState {
 	#[proto(id = 1)]
@@ -128,3 +197,50 @@ pub enum PowerState {
 	#[proto(id = 3)]
 	On,
 }
+
+/// The architecture of a link's system under test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, LinkMessage)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SutArch {
+	#[proto(id = 1)]
+	X86_64,
+	#[proto(id = 2)]
+	Aarch64,
+	#[proto(id = 3)]
+	Riscv64,
+}
+
+/// How a single test went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, LinkMessage)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum TestOutcome {
+	#[proto(id = 1)]
+	Passed,
+	#[proto(id = 2)]
+	Failed,
+	#[proto(id = 3)]
+	Skipped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, LinkMessage)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SessionOutcome {
+	/// The runner exited successfully
+	#[proto(id = 1)]
+	Passed,
+	/// The runner exited with a failure
+	#[proto(id = 2)]
+	Failed,
+	/// The runner didn't exit in time and was stopped
+	#[proto(id = 3)]
+	TimedOut,
+	/// The runner was killed before it could exit on its own
+	#[proto(id = 4)]
+	Killed,
+}
@@ -0,0 +1,45 @@
+//! Checks that packet kinds agree with the packets' wire encoding.
+use link_protocol::{Packet, PacketKind, SessionOutcome};
+use link_protocol_binser::testing::wire;
+use std::collections::HashSet;
+
+#[test]
+fn kinds_round_trip_through_ids() {
+	for &kind in PacketKind::ALL {
+		assert_eq!(PacketKind::from_id(kind.id()), Some(kind), "{kind:?}");
+	}
+
+	assert_eq!(PacketKind::from_id(0), None);
+}
+
+#[test]
+fn kinds_are_unique() {
+	let ids = PacketKind::ALL
+		.iter()
+		.map(PacketKind::id)
+		.collect::<HashSet<_>>();
+	assert_eq!(ids.len(), PacketKind::ALL.len());
+
+	let names = PacketKind::ALL
+		.iter()
+		.map(PacketKind::name)
+		.collect::<HashSet<_>>();
+	assert_eq!(names.len(), PacketKind::ALL.len());
+	assert!(names.contains("SessionOutcome"));
+}
+
+#[test]
+fn packets_are_tagged_with_their_kind() {
+	for packet in [
+		Packet::ResetLink,
+		Packet::Serial(heapless::Vec::from_slice(b"login: ").unwrap()),
+		Packet::SessionOutcome(SessionOutcome::Passed),
+		Packet::BootfileSize { uefi: 1, bios: 2 },
+		Packet::EndTestSession,
+	] {
+		let kind = packet.kind();
+		assert_eq!(PacketKind::from_id(packet.id()), Some(kind));
+		assert_eq!(packet.name(), kind.name());
+		assert_eq!(wire(&packet).unwrap()[0], packet.id(), "{packet:?}");
+	}
+}
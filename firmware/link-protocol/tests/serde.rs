@@ -0,0 +1,116 @@
+//! Checks that packets survive a trip through JSON without
+//! changing their binary wire encoding.
+use link_protocol::{LogEntry, Packet, PowerState, Scene, SessionOutcome, SutArch, TestOutcome};
+use link_protocol_binser::testing::wire;
+
+fn packets() -> Vec<Packet> {
+	let mut uid = [0u8; 32];
+	for (i, b) in uid.iter_mut().enumerate() {
+		*b = (i as u8).wrapping_mul(37);
+	}
+
+	vec![
+		Packet::LinkOnline {
+			uid,
+			version: "1.0.0".try_into().unwrap(),
+		},
+		Packet::ResetLink,
+		Packet::SetScene(Scene::Logo),
+		Packet::SetScene(Scene::Test),
+		Packet::SetScene(Scene::Log),
+		Packet::Log(LogEntry::Info("hello".try_into().unwrap())),
+		Packet::Log(LogEntry::Warn("careful".try_into().unwrap())),
+		Packet::Log(LogEntry::Error("oh no \u{1F525}".try_into().unwrap())),
+		Packet::SetMonitorStandby(true),
+		Packet::StartTestSession {
+			total_tests: 42,
+			author: "qix-".try_into().unwrap(),
+			title: "add \"json\" support".try_into().unwrap(),
+			ref_id: "refs/pull/1/merge".try_into().unwrap(),
+		},
+		Packet::StartTest {
+			name: "boot".try_into().unwrap(),
+		},
+		Packet::SetPowerState(PowerState::Off),
+		Packet::SetPowerState(PowerState::Standby),
+		Packet::SetPowerState(PowerState::On),
+		Packet::PressPower,
+		Packet::PressReset,
+		Packet::BootfileSize {
+			uefi: u64::MAX,
+			bios: 1234,
+		},
+		Packet::Serial(heapless::Vec::from_slice(b"\x1b[0mlogin: \0\xff").unwrap()),
+		Packet::DebugUsbKey(4),
+		Packet::SessionOutcome(SessionOutcome::Passed),
+		Packet::SessionOutcome(SessionOutcome::TimedOut),
+		Packet::Ping(0xDEAD_BEEF),
+		Packet::Pong(0xDEAD_BEEF),
+		Packet::HardwareReport {
+			board: "stm32f479vg".try_into().unwrap(),
+			arch: SutArch::X86_64,
+			usb_hid: true,
+			capture: false,
+			sd_mux: false,
+			serial_ports: 1,
+			features: "monitor,indicators".try_into().unwrap(),
+		},
+		Packet::Observe,
+		Packet::TestResult {
+			name: "boot".try_into().unwrap(),
+			outcome: TestOutcome::Failed,
+			duration_ms: 1234,
+			message: "no \"login:\" prompt".try_into().unwrap(),
+		},
+		Packet::EndTestSession,
+		Packet::BacklogReplayed,
+	]
+}
+
+#[test]
+fn json_round_trips_to_same_wire_encoding() {
+	for packet in packets() {
+		let json = serde_json::to_string(&packet).unwrap();
+		let decoded: Packet = serde_json::from_str(&json).unwrap();
+		assert_eq!(
+			wire(&packet).unwrap(),
+			wire(&decoded).unwrap(),
+			"packet changed: {json}"
+		);
+
+		let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+		let decoded: Packet = serde_json::from_value(value).unwrap();
+		assert_eq!(
+			wire(&packet).unwrap(),
+			wire(&decoded).unwrap(),
+			"packet changed: {json}"
+		);
+	}
+}
+
+#[test]
+fn uid_is_rendered_as_hex() {
+	let packet = Packet::LinkOnline {
+		uid: [0xAB; 32],
+		version: "1.0.0".try_into().unwrap(),
+	};
+
+	let json = serde_json::to_value(&packet).unwrap();
+	assert_eq!(json["LinkOnline"]["uid"], "AB".repeat(32));
+
+	let lower = serde_json::json!({
+		"LinkOnline": { "uid": "ab".repeat(32), "version": "1.0.0" }
+	});
+	let decoded: Packet = serde_json::from_value(lower).unwrap();
+	assert_eq!(wire(&packet).unwrap(), wire(&decoded).unwrap());
+}
+
+#[test]
+fn invalid_uid_is_rejected() {
+	for uid in ["AB".repeat(31), "ZZ".repeat(32), String::new()] {
+		let json = serde_json::json!({
+			"LinkOnline": { "uid": uid, "version": "1.0.0" }
+		});
+		assert!(serde_json::from_value::<Packet>(json).is_err());
+	}
+}
@@ -4,7 +4,7 @@ use async_std::{io, net::TcpListener, prelude::*, sync::Mutex, task};
 use envconfig::Envconfig;
 
 use link_protocol::{
-	Error as ProtoError, LogEntry, PowerState,
+	Error as ProtoError, LogEntry, PowerState, SessionOutcome,
 	channel::{PacketSender, RWError},
 };
 use mini_async_repl::{
@@ -264,7 +264,18 @@ fn make_repl(outgoing: PacketSender<BufWriter<TcpStream>>) -> Repl {
 					CommandArgType::String,
 					"name",
 				)],
-				Box::new(TestCommand(outgoing)),
+				Box::new(TestCommand(outgoing.clone())),
+			),
+		)
+		.add(
+			"outcome",
+			Command::new(
+				"ends the test suite with an outcome (passed/failed/timeout/killed)",
+				vec![CommandArgInfo::new_with_name(
+					CommandArgType::String,
+					"outcome",
+				)],
+				Box::new(OutcomeCommand(outgoing)),
 			),
 		)
 		.build()
@@ -607,3 +618,39 @@ impl ExecuteCommand for TestCommand {
 		})
 	}
 }
+
+struct OutcomeCommand(Arc<Mutex<PacketSender<BufWriter<TcpStream>>>>);
+
+impl ExecuteCommand for OutcomeCommand {
+	fn execute(
+		&mut self,
+		args: Vec<String>,
+		_args_info: Vec<mini_async_repl::command::CommandArgInfo>,
+	) -> std::pin::Pin<
+		Box<
+			dyn Future<Output = mini_async_repl::anyhow::Result<mini_async_repl::CommandStatus>>
+				+ '_,
+		>,
+	> {
+		Box::pin(async move {
+			let mut sender = self.0.lock().await;
+
+			let outcome = match args[0].as_str() {
+				"passed" => SessionOutcome::Passed,
+				"failed" => SessionOutcome::Failed,
+				"timeout" => SessionOutcome::TimedOut,
+				"killed" => SessionOutcome::Killed,
+				unknown => {
+					warn!("unknown outcome: {}", unknown);
+					return Ok(CommandStatus::Done);
+				}
+			};
+
+			let packet = Packet::SessionOutcome(outcome);
+
+			sender.send(packet).await?;
+
+			Ok(CommandStatus::Done)
+		})
+	}
+}